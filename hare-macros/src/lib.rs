@@ -0,0 +1,237 @@
+//! Macros of `hare`, use them through the `macros` feature of `hare`.
+#![forbid(unsafe_code)]
+#![deny(nonstandard_style)]
+#![warn(
+    missing_docs,
+    clippy::missing_docs_in_private_items,
+    clippy::pedantic,
+    clippy::unwrap_used
+)]
+
+use proc_macro::TokenStream;
+use proc_macro2::{Ident, Span};
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Fields, FnArg, ItemFn, LitStr, ReturnType,
+    Signature, Type,
+};
+
+/// Generates the `ConsumerHandler` wiring of a consumer handler from its signature, with
+/// compile errors pointing at the offending extractor or return type instead of the `consume`
+/// call the handler is passed to.
+///
+/// The function is turned into a unit struct of the same name implementing `ConsumerHandler`,
+/// which extracts every argument and calls the function, now available as its associated
+/// `handle` function. Unlike plain functions, it takes any number of extractors. Pass the state
+/// type of the `Client` with `state`, it defaults to `()`:
+///
+/// ```rust,ignore
+/// #[hare::consumer(state = AppState)]
+/// async fn handle_order(
+///     Json(order): Json<Order>,
+///     State(state): State<AppState>,
+/// ) -> Result<(), anyhow::Error> {
+///     // ...
+/// }
+///
+/// client.basic_consume_builder(&queue, "orders").consume(handle_order).await?;
+/// ```
+#[proc_macro_attribute]
+pub fn consumer(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut state: Type = parse_quote!(());
+    let state_parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("state") {
+            state = meta.value()?.parse()?;
+            Ok(())
+        } else {
+            Err(meta.error("unsupported argument, expected `state = Type`"))
+        }
+    });
+    parse_macro_input!(attr with state_parser);
+    let handler = parse_macro_input!(item as ItemFn);
+
+    consumer_impl(handler, &state)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Generate the unit struct replacing the handler and its `ConsumerHandler` implementation.
+fn consumer_impl(mut handler: ItemFn, state: &Type) -> syn::Result<proc_macro2::TokenStream> {
+    let sig = &handler.sig;
+    let extractors = extractors(sig)?;
+    let output = match &sig.output {
+        ReturnType::Default => parse_quote!(()),
+        ReturnType::Type(_, output) => (**output).clone(),
+    };
+
+    let name = sig.ident.clone();
+    let vis = handler.vis.clone();
+    let docs = handler
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .cloned()
+        .collect::<Vec<_>>();
+    handler.sig.ident = Ident::new("handle", name.span());
+
+    let output_bound = quote_spanned! {output.span()=>
+        #output: ::hare::__private::IntoConsumerHandlerResult,
+    };
+    let args = (0..extractors.len())
+        .map(|i| Ident::new(&format!("__arg{i}"), Span::call_site()))
+        .collect::<Vec<_>>();
+
+    // Spanned, so unsupported extractors are reported at their type.
+    let extractions = args.iter().zip(&extractors).map(|(arg, extractor)| {
+        quote_spanned! {extractor.span()=>
+            let #arg = ::hare::__private::extract::<#state, #extractor>(&client, &ctx, &delivery)
+                .await?;
+        }
+    });
+
+    Ok(quote! {
+        #(#docs)*
+        #[allow(non_camel_case_types)]
+        #[derive(Debug, Clone, Copy)]
+        #vis struct #name;
+
+        impl #name {
+            #handler
+        }
+
+        impl ::hare::ConsumerHandler<#state, ()> for #name
+        where
+            #output_bound
+        {
+            type Value = <#output as ::hare::__private::IntoConsumerHandlerResult>::Value;
+            type Future = ::std::pin::Pin<
+                ::std::boxed::Box<
+                    dyn ::std::future::Future<
+                            Output = ::std::result::Result<
+                                Self::Value,
+                                ::hare::__private::ConsumerHandlerError,
+                            >,
+                        > + ::std::marker::Send,
+                >,
+            >;
+
+            fn call(
+                self,
+                client: ::hare::Client<#state>,
+                ctx: ::std::sync::Arc<::hare::__private::ConsumerContext>,
+                delivery: ::std::sync::Arc<::hare::__private::Delivery>,
+            ) -> Self::Future {
+                ::std::boxed::Box::pin(async move {
+                    let extraction_start = ::std::time::Instant::now();
+                    #(#extractions)*
+                    ::hare::__private::timed(extraction_start, Self::handle(#(#args),*)).await
+                })
+            }
+        }
+    })
+}
+
+/// The extractor types of the handler, if it is a consumer handler at all.
+fn extractors(sig: &Signature) -> syn::Result<Vec<Type>> {
+    if sig.asyncness.is_none() {
+        return Err(syn::Error::new(
+            sig.fn_token.span(),
+            "consumer handlers must be `async`",
+        ));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            sig.generics.span(),
+            "generic consumer handlers are not supported",
+        ));
+    }
+    sig.inputs
+        .iter()
+        .map(|input| match input {
+            FnArg::Typed(pat_type) => Ok((*pat_type.ty).clone()),
+            FnArg::Receiver(receiver) => Err(syn::Error::new(
+                receiver.span(),
+                "consumer handlers can not take `self`",
+            )),
+        })
+        .collect()
+}
+
+/// Derives `FromHeaders`, reading every field of a struct from the header of the same name, so
+/// the struct can be extracted with the `TypedHeaders` extractor. Set another header name with
+/// `#[header(rename = "...")]`. Fields of type `Option` may be missing, all other fields are
+/// required.
+///
+/// ```rust,ignore
+/// #[derive(hare::FromHeaders)]
+/// struct Routing {
+///     #[header(rename = "x-tenant-id")]
+///     tenant_id: String,
+///     attempt: Option<u32>,
+/// }
+/// ```
+#[proc_macro_derive(FromHeaders, attributes(header))]
+pub fn from_headers(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    from_headers_impl(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Generate the `FromHeaders` implementation of the struct.
+fn from_headers_impl(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(
+            input.ident.span(),
+            "FromHeaders can only be derived for structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new(
+            input.ident.span(),
+            "FromHeaders can only be derived for structs with named fields",
+        ));
+    };
+
+    let mut reads = Vec::with_capacity(fields.named.len());
+    for field in &fields.named {
+        let Some(ident) = &field.ident else {
+            continue;
+        };
+        let mut header = ident.to_string();
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("header"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    header = meta.value()?.parse::<LitStr>()?.value();
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported argument, expected `rename = \"...\"`"))
+                }
+            })?;
+        }
+        reads.push(quote! {
+            #ident: headers.extract(#header)?,
+        });
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::hare::consumer_extract::FromHeaders for #name #ty_generics
+        #where_clause
+        {
+            fn from_headers(
+                headers: &::hare::consumer_extract::Headers,
+            ) -> ::std::result::Result<Self, ::hare::consumer_extract::HeadersError> {
+                ::std::result::Result::Ok(Self {
+                    #(#reads)*
+                })
+            }
+        }
+    })
+}
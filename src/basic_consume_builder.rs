@@ -1,23 +1,46 @@
 //! Provide the builder pattern for building a `RabbitMq` consumer.
 
-use std::sync::Arc;
-use std::time::Duration;
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 
+use chrono::Utc;
+use futures::future::BoxFuture;
 use futures::prelude::*;
 use lapin::message::Delivery;
-use lapin::options::{BasicAckOptions, BasicConsumeOptions, BasicNackOptions};
-use lapin::types::FieldTable;
+use lapin::options::{
+    BasicAckOptions, BasicCancelOptions, BasicConsumeOptions, BasicNackOptions,
+    BasicPublishOptions, BasicQosOptions,
+};
+use lapin::types::{AMQPValue, DeliveryTag, FieldTable};
 use lapin::Consumer;
-use tokio::task::{self, JoinSet};
-use tokio::time::Instant;
-use tracing::{debug, debug_span, error, info, instrument, Instrument};
+use tokio::sync::oneshot;
+use tokio::task::JoinSet;
+use tracing::{debug, debug_span, error, info, instrument, warn, Instrument};
 
 use crate::prelude::*;
+use crate::batch_ack::BatchAck;
+use crate::batch_handler::BatchHandler;
+use crate::consumer_extract::SchemaVersion;
+use crate::consumer_handle::ConsumerHandle;
 use crate::consumer_handler_result::ConsumerHandlerError;
+use crate::runtime::Runtime;
 use crate::{dlx, BuilderArgs, Client, ConsumerHandler, Queue};
-use crate::util;
+use crate::{health, runtime, util};
+
+/// Key name for the consumer priority.
+const XARGS_PRIORITY: &str = "x-priority";
+
+/// Header of quarantined deliveries, containing the extractor error.
+pub const QUARANTINE_HEADER_ERROR: &str = "x-quarantine-error";
+/// Header of quarantined deliveries, containing the name of the failed extractor.
+pub const QUARANTINE_HEADER_EXTRACTOR: &str = "x-quarantine-extractor";
+/// Header of quarantined deliveries, containing the queue the delivery was consumed from.
+pub const QUARANTINE_HEADER_ORIGIN_QUEUE: &str = "x-quarantine-origin-queue";
 
 /// The builder
+#[allow(clippy::struct_excessive_bools)]
 pub struct BasicConsumeBuilder<'a, S>
 where
     S: Clone + Send + Sync + 'static,
@@ -36,15 +59,57 @@ where
     timeout: Duration,
     /// Consume messages in order, or in parallel.
     in_parallel: bool,
+    /// Acknowledge deliveries in batches of `count`, at least every `interval`.
+    batch_ack: Option<(usize, Duration)>,
+    /// Expected type and version of [`crate::message::Envelope`]s.
+    envelope: Option<(String, u32)>,
+    /// Schema versions of deliveries the consumer supports.
+    supported_versions: Option<RangeInclusive<u32>>,
+    /// How often to try recovering the consumer after a channel error.
+    recovery_attempts: u32,
+    /// Initial backoff between recovery attempts.
+    recovery_backoff: Duration,
+    /// Abandon in-flight deliveries after this duration when shutting down.
+    drain_timeout: Option<Duration>,
+    /// How to deal with in-flight deliveries when shutting down.
+    shutdown_strategy: ShutdownStrategy,
+    /// Skip deliveries older than this.
+    max_message_age: Option<Duration>,
+    /// Queue deliveries failing extraction are moved to.
+    quarantine_queue: Option<String>,
+    /// Rewrites the body of deliveries before extraction.
+    transform_body: Option<BodyTransform>,
+    /// Skips deliveries the handler is not interested in.
+    filter: Option<DeliveryFilter>,
+    /// Identifies the consumer in its tag and traces.
+    label: Option<String>,
+    /// Priority of the consumer.
+    priority: Option<i32>,
+    /// Park deliveries after this many failed deliveries.
+    max_deliveries: Option<u64>,
+    /// Maximum number of unacknowledged deliveries.
+    prefetch_count: Option<u16>,
+    /// Apply the prefetch count to the whole channel.
+    prefetch_global: bool,
+    /// Leave acknowledging deliveries to the handler.
+    manual_ack: bool,
+    /// Requeue deliveries the handler failed for, instead of dead-lettering them.
+    requeue_on_error: bool,
+    /// Maximum number of deliveries handled at once.
+    max_concurrency: Option<usize>,
+    /// Shut the consumer down after running this long.
+    run_for: Option<Duration>,
 }
 
 impl<'a, S> BasicConsumeBuilder<'a, S>
 where
     S: Clone + Send + Sync + 'static,
 {
-    /// Creates a new [`BasicConsumeBuilder`] instance.
+    /// Creates a new [`BasicConsumeBuilder`] instance, starting from the [`ConsumeDefaults`] of
+    /// the client.
     #[must_use]
     pub fn new(client: &'a Client<S>, queue: &'a Queue, consumer_tag: &'a str) -> Self {
+        let defaults = client.consume_defaults;
         Self {
             client,
             queue,
@@ -56,8 +121,28 @@ where
                 nowait: false,
             },
             args: FieldTable::default(),
-            timeout: Duration::from_secs(300),
-            in_parallel: true,
+            timeout: defaults.timeout,
+            in_parallel: defaults.in_parallel,
+            batch_ack: None,
+            envelope: None,
+            supported_versions: None,
+            recovery_attempts: defaults.recovery_attempts,
+            recovery_backoff: defaults.recovery_backoff,
+            drain_timeout: defaults.drain_timeout,
+            shutdown_strategy: defaults.shutdown_strategy,
+            max_message_age: None,
+            quarantine_queue: None,
+            transform_body: None,
+            filter: None,
+            label: None,
+            priority: None,
+            max_deliveries: defaults.max_deliveries,
+            prefetch_count: defaults.prefetch_count,
+            prefetch_global: false,
+            manual_ack: false,
+            requeue_on_error: defaults.requeue_on_error,
+            max_concurrency: None,
+            run_for: None,
         }
     }
 
@@ -68,7 +153,11 @@ where
         self
     }
 
-    /// Defaults to `false`.
+    /// Defaults to `false`. Messages published on the same connection are not delivered to the
+    /// consumer. Publishing and consuming share the connection of the [`Client`], so this applies
+    /// to everything published through it, including after the consumer was recovered. Note
+    /// that `RabbitMQ` does not implement `no_local` and ignores it, a warning is logged when the
+    /// consumer is created.
     #[must_use]
     pub fn no_local(mut self, no_local: bool) -> Self {
         self.opts.no_local = no_local;
@@ -82,6 +171,14 @@ where
         self
     }
 
+    /// Defaults to the consumer priority of the queues [`crate::Priority`], if any. Consumers with
+    /// a higher priority receive deliveries first, as long as they have spare prefetch capacity.
+    #[must_use]
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
     /// Defaults to 5 minutes.
     #[must_use]
     pub fn timeout(mut self, timeout: Duration) -> Self {
@@ -96,6 +193,226 @@ where
         self
     }
 
+    /// Not set by default. Limits how many deliveries are handled at once when consuming in
+    /// parallel. Once the limit is reached no further deliveries are taken from the consumer
+    /// until one finishes, combine it with the [`Self::prefetch_count`] so the broker stops
+    /// sending deliveries as well. Values below `1` are treated as `1`.
+    #[must_use]
+    pub fn max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = Some(max_concurrency.max(1));
+        self
+    }
+
+    /// Not set by default. Accumulates acknowledgements and flushes them with a single
+    /// `multiple` ack once `count` deliveries are processed, or at least every `interval`. Only
+    /// the contiguous prefix of processed deliveries is acknowledged, so deliveries completing
+    /// out of order while consuming in parallel are never acknowledged prematurely. Failed
+    /// deliveries are nacked the same way, a run of them with a single `multiple` nack, so
+    /// requeued deliveries are only redelivered once the nack is flushed.
+    #[must_use]
+    pub fn batch_ack(mut self, count: usize, interval: Duration) -> Self {
+        self.batch_ack = Some((count, interval));
+        self
+    }
+
+    /// Not set by default. Sets the type and version the [`crate::message::Envelope`] extractor
+    /// expects. Deliveries with a mismatching envelope fail extraction and get dead-lettered. The
+    /// [`crate::message::MESSAGE_TYPE_HEADER`] and [`crate::message::SCHEMA_VERSION_HEADER`]
+    /// headers are checked before deserializing, if present.
+    #[must_use]
+    pub fn envelope(mut self, kind: impl Into<String>, version: u32) -> Self {
+        self.envelope = Some((kind.into(), version));
+        self
+    }
+
+    /// Not set by default. Deliveries whose [`crate::message::SCHEMA_VERSION_HEADER`] header is
+    /// missing or outside of `versions` are rejected before the handler runs, they are handled
+    /// like deliveries failing extraction. Use the [`crate::consumer_extract::SchemaVersion`]
+    /// extractor to dispatch on the supported versions.
+    #[must_use]
+    pub fn supported_versions(mut self, versions: RangeInclusive<u32>) -> Self {
+        self.supported_versions = Some(versions);
+        self
+    }
+
+    /// Defaults to 5 attempts, starting with a backoff of 500 milliseconds. When the consumers
+    /// channel fails while the connection is still alive, e.g. due to an invalid acknowledgement,
+    /// a new channel is created and consuming resumes. The backoff doubles with every failed
+    /// attempt, setting `attempts` to `0` disables the recovery.
+    #[must_use]
+    pub fn recovery(mut self, attempts: u32, backoff: Duration) -> Self {
+        self.recovery_attempts = attempts;
+        self.recovery_backoff = backoff;
+        self
+    }
+
+    /// Not set by default, shutting down waits for all in-flight deliveries to finish. When set,
+    /// deliveries still in-flight after the timeout are abandoned, their tasks are aborted and
+    /// the deliveries are left unacknowledged for the broker to redeliver them. Only applies to
+    /// [`ShutdownStrategy::Drain`].
+    #[must_use]
+    pub fn drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = Some(drain_timeout);
+        self
+    }
+
+    /// Not set by default, the consumer runs until it is stopped by a signal. Shuts the consumer
+    /// down once it ran for `run_for`, like a stop signal would, e.g. for cron-style jobs
+    /// draining a queue for a bounded time. In-flight deliveries are dealt with according to
+    /// the [`Self::shutdown_strategy`].
+    #[must_use]
+    pub fn run_for(mut self, run_for: Duration) -> Self {
+        self.run_for = Some(run_for);
+        self
+    }
+
+    /// Not set by default, the broker delivers without limit. Limits the number of
+    /// unacknowledged deliveries the broker sends to the consumer, bounding the deliveries
+    /// handled in parallel and buffered in memory. Sequential consumers handle one delivery at a
+    /// time anyway, a prefetch count of `1` keeps them from buffering more.
+    #[must_use]
+    pub fn prefetch_count(mut self, prefetch_count: u16) -> Self {
+        self.prefetch_count = Some(prefetch_count);
+        self
+    }
+
+    /// Defaults to `false`. Applies the [`Self::prefetch_count`] to the consumer's channel
+    /// instead of the consumer. Every consumer has its own channel, so this only matters for
+    /// brokers other than `RabbitMQ`, which interpret the flag differently.
+    #[must_use]
+    pub fn prefetch_global(mut self, prefetch_global: bool) -> Self {
+        self.prefetch_global = prefetch_global;
+        self
+    }
+
+    /// Not set by default. Deliveries whose `timestamp` property is older than `max_message_age`
+    /// are acknowledged and skipped without running the handler, e.g. to drop obsolete commands
+    /// after a backlog built up. They are moved to the [`Self::quarantine_queue`] instead, if
+    /// set. Deliveries without a timestamp are processed normally.
+    #[must_use]
+    pub fn max_message_age(mut self, max_message_age: Duration) -> Self {
+        self.max_message_age = Some(max_message_age);
+        self
+    }
+
+    /// Defaults to [`ShutdownStrategy::Drain`]. See [`ShutdownStrategy`] for the trade-offs.
+    #[must_use]
+    pub fn shutdown_strategy(mut self, shutdown_strategy: ShutdownStrategy) -> Self {
+        self.shutdown_strategy = shutdown_strategy;
+        self
+    }
+
+    /// Defaults to `false`. Leaves acknowledging deliveries to the handler, which settles them
+    /// with the [`crate::consumer_extract::Acker`] extractor, e.g. only after a downstream commit
+    /// succeeded. Deliveries are neither acknowledged nor nacked automatically, not even when the
+    /// handler fails. Only if the handler timed out or panicked before settling the delivery, it
+    /// is nacked like a failed delivery, as nobody is left to settle it. Deliveries failing
+    /// extraction never reach the handler and are still handled automatically. A delivery the
+    /// handler forgets to settle stays unacknowledged until the channel is closed and is then
+    /// redelivered, it counts against the [`Self::prefetch_count`] until then. Acknowledging in
+    /// batches is disabled.
+    #[must_use]
+    pub fn manual_ack(mut self, manual_ack: bool) -> Self {
+        self.manual_ack = manual_ack;
+        self
+    }
+
+    /// Defaults to `false`. Requeues deliveries the handler failed for, e.g. due to transient
+    /// errors, instead of dead-lettering them. Requeued deliveries are not dead yet, so no error
+    /// data is published to the DLX. A delivery that keeps failing is redelivered over and over
+    /// again, use [`Self::max_deliveries`] to bound the retries, it takes precedence. Deliveries
+    /// failing extraction are still dead-lettered, as retrying them does not help.
+    #[must_use]
+    pub fn requeue_on_error(mut self, requeue_on_error: bool) -> Self {
+        self.requeue_on_error = requeue_on_error;
+        self
+    }
+
+    /// Not set by default. Deliveries the handler failed for are requeued and retried, until they
+    /// were delivered `max_deliveries` times. Then they are nacked and dead-lettered, and
+    /// [`dlx::ParkedData`] is published with [`dlx::ROUTING_KEY_PARKED`] instead of error data
+    /// with [`dlx::ROUTING_KEY_ERROR`], separating permanently failed messages from transient
+    /// errors, see [`Client::parking_lot_queue`]. Failing deliveries are requeued after a delay,
+    /// starting at 100 milliseconds and doubling with every delivery up to 10 seconds.
+    ///
+    /// Deliveries are counted by quorum queues. On other queues, like classic queues, the
+    /// consumer counts the failures of each message by its message id itself, so with several
+    /// consumers a message may be delivered more often. Messages without a message id are
+    /// parked once they were redelivered.
+    #[must_use]
+    pub fn max_deliveries(mut self, max_deliveries: u64) -> Self {
+        self.max_deliveries = Some(max_deliveries);
+        self
+    }
+
+    /// Not set by default. Deliveries whose extractors fail, e.g. because the payload is not
+    /// deserializable, are published unchanged to this queue and acknowledged, instead of being
+    /// nacked and dead-lettered. The payload is published as received, before the body was
+    /// transformed, see [`Self::transform_body`]. Diagnostic headers describing the failure are
+    /// added, see [`QUARANTINE_HEADER_ERROR`]. Failures of the handler itself still use the dead
+    /// letter exchange.
+    #[must_use]
+    pub fn quarantine_queue(mut self, quarantine_queue: impl Into<String>) -> Self {
+        self.quarantine_queue = Some(quarantine_queue.into());
+        self
+    }
+
+    /// Not set by default. Rewrites the body of every delivery before any extractor runs, e.g.
+    /// to decrypt or decompress it. Extractors, the [`crate::consumer_extract::Delivery`]
+    /// extractor included, only ever see the transformed body. Deliveries failing the
+    /// transformation are handled like deliveries failing extraction.
+    #[must_use]
+    pub fn transform_body<F, E>(mut self, transform_body: F) -> Self
+    where
+        F: Fn(&[u8]) -> Result<Vec<u8>, E> + Send + Sync + 'static,
+        E: Into<anyhow::Error>,
+    {
+        self.transform_body = Some(BodyTransform(Arc::new(move |data| {
+            transform_body(data).map_err(Into::into)
+        })));
+        self
+    }
+
+    /// Not set by default. Deliveries `filter` returns `false` for are acknowledged and skipped
+    /// without running the handler, e.g. to only handle some of the messages on a shared queue by
+    /// their headers. Skipped deliveries are consumed and lost for other consumers, see
+    /// [`Self::filter_requeue`]. The filter runs before the body is transformed or extracted.
+    #[must_use]
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&Delivery) -> bool + Send + Sync + 'static,
+    {
+        self.filter = Some(DeliveryFilter {
+            predicate: Arc::new(filter),
+            requeue: false,
+        });
+        self
+    }
+
+    /// Not set by default. Like [`Self::filter`], but skipped deliveries are requeued for other
+    /// consumers instead of being acknowledged. A delivery no consumer accepts is redelivered over
+    /// and over again, so make sure another consumer of the queue handles it.
+    #[must_use]
+    pub fn filter_requeue<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&Delivery) -> bool + Send + Sync + 'static,
+    {
+        self.filter = Some(DeliveryFilter {
+            predicate: Arc::new(filter),
+            requeue: true,
+        });
+        self
+    }
+
+    /// Not set by default. Identifies the consumer in the management UI and in traces, e.g.
+    /// `orders-worker-2`. The label is appended to the consumer tag, if the tag is empty a unique
+    /// tag of the form `{app_id}.{label}.{uuid}` is used instead of the broker generated one.
+    #[must_use]
+    pub fn identified_by(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
     /// Provides additional options for the consumer.
     #[must_use]
     pub fn opts<F>(mut self, f: F) -> Self
@@ -108,57 +425,382 @@ where
 
     /// Finishes the build process and consumes `self`. Creates
     /// the consumer on the provided configuration.
+    ///
+    /// # Errors
+    /// See [`ConsumeError`].
+    pub async fn consume<H, T>(self, handler: H) -> Result<ConsumerHandle, ConsumeError>
+    where
+        H: ConsumerHandler<S, T>,
+        T: 'static,
+    {
+        self.consume_inner(handler, None).await
+    }
+
+    /// Like [`Self::consume`], but the value produced by the `handler` is passed on to `commit`,
+    /// e.g. to commit a database transaction. Deliveries are only acknowledged once `commit`
+    /// succeeded, if it fails the delivery is nacked and requeued instead of being
+    /// dead-lettered.
+    ///
+    /// # Errors
+    /// See [`ConsumeError`].
+    pub async fn consume_with_commit<H, T, C, Fut, E>(
+        self,
+        handler: H,
+        commit: C,
+    ) -> Result<ConsumerHandle, ConsumeError>
+    where
+        H: ConsumerHandler<S, T>,
+        T: 'static,
+        C: Fn(H::Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Into<anyhow::Error> + 'static,
+    {
+        let commit: Arc<CommitFn<H::Value>> =
+            Arc::new(move |value| commit(value).map_err(Into::into).boxed());
+        self.consume_inner(handler, Some(commit)).await
+    }
+
+    /// Like [`Self::consume`], but deliveries are collected into batches of up to `max_size`
+    /// deliveries, a batch is handed to the `handler` once it is full or `max_wait` after its
+    /// first delivery arrived. Consumes in parallel, the [`Self::prefetch_count`] needs to be at
+    /// least `max_size` for batches to fill up. The [`Self::timeout`] applies to every delivery,
+    /// including the time it waits for its batch to be handled.
+    ///
+    /// On success the whole batch is acknowledged, on failure the deliveries are nacked and
+    /// dead-lettered, either all of them or only the ones listed in [`BatchError::Partial`].
+    /// Combine with [`Self::batch_ack`] to settle a batch with a single `multiple` ack or nack
+    /// instead of one per delivery.
+    ///
+    /// # Errors
+    /// See [`ConsumeError`].
+    pub async fn consume_batched<F, Fut>(
+        mut self,
+        max_size: usize,
+        max_wait: Duration,
+        handler: F,
+    ) -> Result<ConsumerHandle, ConsumeError>
+    where
+        F: Fn(Vec<crate::consumer_extract::Delivery>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), BatchError>> + Send + 'static,
+    {
+        self.in_parallel = true;
+        let runtime = self.client.runtime.clone();
+        self.consume(BatchHandler::new(handler, max_size, max_wait, runtime))
+            .await
+    }
+
+    /// The consumer tag including the label, see [`Self::identified_by`].
+    fn labeled_consumer_tag(&self) -> String {
+        match &self.label {
+            None => self.consumer_tag.to_string(),
+            Some(label) if self.consumer_tag.is_empty() => {
+                format!("{}.{label}.{}", self.client.app_id, uuid::Uuid::new_v4())
+            }
+            Some(label) => format!("{}.{label}", self.consumer_tag),
+        }
+    }
+
+    /// Creates the consumer, see [`Self::consume`].
     #[instrument(
         name = "consumer"
         skip_all,
         fields(
             queue_name = %self.queue.name().as_str(),
             consumer_tag = %self.consumer_tag,
+            label = ?self.label,
             in_parallel = %self.in_parallel
         )
     )]
-    pub async fn consume<H, T>(self, handler: H) -> Result<Consumer, ConsumeError>
+    async fn consume_inner<H, T>(
+        self,
+        handler: H,
+        commit: Option<Arc<CommitFn<H::Value>>>,
+    ) -> Result<ConsumerHandle, ConsumeError>
+    where
+        H: ConsumerHandler<S, T>,
+        T: 'static,
+    {
+        let client = self.client.clone();
+        let (builder, chan, consumer) = self.open().await?;
+        let (handle, ctx) = builder.into_context(&chan, &consumer);
+
+        let task_name = format!("hare consumer {}:{}", ctx.queue_name, ctx.consumer_tag);
+        util::spawn_named_in(
+            &mut *client.consumer_set.lock().await,
+            &task_name,
+            consumer_task_handler(client.clone(), ctx, handler, commit, consumer).in_current_span(),
+        );
+        Ok(handle)
+    }
+
+    /// Consumes a single delivery, runs the `handler` on it and acknowledges it like
+    /// [`Self::consume`] does, then cancels the consumer and returns, without spawning a consumer
+    /// task. E.g. for CLI tools and integration tests expecting a single message. Waits until a
+    /// delivery arrives, wrap it in a timeout to bound the wait. Acknowledging in batches is
+    /// disabled.
+    ///
+    /// # Errors
+    /// See [`ConsumeError`]. Also if the consumer is canceled before a delivery arrived.
+    pub async fn consume_one<H, T>(mut self, handler: H) -> Result<(), ConsumeError>
     where
         H: ConsumerHandler<S, T>,
         T: 'static,
     {
-        let chan = self
-            .client
-            .create_channel()
+        self.batch_ack = None;
+        self.prefetch_count = Some(1);
+        let client = self.client.clone();
+        let (builder, chan, mut consumer) = self.open().await?;
+        let (handle, ctx) = builder.into_context(&chan, &consumer);
+
+        let delivery_result_opt = consumer.next().await;
+        if let Err(err) = chan
+            .basic_cancel(consumer.tag().as_str(), BasicCancelOptions::default())
             .await
-            .map_err(|err| ConsumeError(err.into()))?;
+        {
+            warn!("canceling the consumer failed: {err}");
+        }
+        let result = match delivery_result_opt {
+            Some(Ok(delivery)) => {
+                let delivery_span = delivery_span(&delivery);
+                delivery_task_handler(client, ctx, handler, None, delivery)
+                    .instrument(delivery_span)
+                    .await;
+                Ok(())
+            }
+            Some(Err(err)) => Err(ConsumeError(err.into())),
+            None => Err(ConsumeError(anyhow::format_err!(
+                "consumer was canceled before a delivery arrived"
+            ))),
+        };
+        handle.close().await;
+        result
+    }
+
+    /// The prefetch count and whether it applies to the whole channel, if set.
+    fn prefetch(&self) -> Option<(u16, bool)> {
+        self.prefetch_count
+            .map(|prefetch_count| (prefetch_count, self.prefetch_global))
+    }
+
+    /// Creates a channel and starts consuming on it.
+    pub(super) async fn open(mut self) -> Result<(Self, lapin::Channel, Consumer), ConsumeError> {
+        if self.opts.no_local {
+            warn!("no_local is not supported by RabbitMQ, own messages will still be delivered");
+        }
+        let priority = self
+            .priority
+            .or_else(|| self.queue.priority.get_consumer_priority());
+        if let Some(priority) = priority {
+            self = self.arg_i32(XARGS_PRIORITY, priority);
+        }
+
+        let chan = open_channel(self.client, self.prefetch())
+            .await
+            .map_err(ConsumeError)?;
         let consumer = chan
             .basic_consume(
                 self.queue.name().as_str(),
-                self.consumer_tag,
+                &self.labeled_consumer_tag(),
                 self.opts,
-                self.args,
+                self.args.clone(),
             )
             .await
             .map_err(|err| ConsumeError(err.into()))?;
+        Ok((self, chan, consumer))
+    }
 
-        self.client.consumer_set.lock().await.spawn(
-            consumer_task_handler(
-                self.client.clone(),
-                self.queue.name().to_string(),
-                self.queue.dead_lettering,
-                self.timeout,
-                self.in_parallel,
-                handler,
-                consumer.clone(),
-            )
-            .in_current_span(),
-        );
-        Ok(consumer)
+    /// Turns the builder into the [`ConsumerContext`] of a consumer and its handle.
+    pub(super) fn into_context(
+        self,
+        chan: &lapin::Channel,
+        consumer: &Consumer,
+    ) -> (ConsumerHandle, Arc<ConsumerContext>) {
+        let handle = ConsumerHandle::new(chan.clone(), consumer.clone());
+        let prefetch = self.prefetch();
+        let ctx = Arc::new(ConsumerContext {
+            handle: handle.clone(),
+            queue_name: self.queue.name().to_string(),
+            consumer_tag: consumer.tag().to_string(),
+            opts: self.opts,
+            args: self.args,
+            dead_lettering: self.queue.dead_lettering,
+            timeout: self.timeout,
+            in_parallel: self.in_parallel,
+            batch_ack: self
+                .batch_ack
+                .filter(|_| !self.manual_ack)
+                .map(|(count, interval)| Arc::new(BatchAck::new(chan.clone(), count, interval))),
+            envelope: self.envelope,
+            supported_versions: self.supported_versions,
+            recovery_attempts: self.recovery_attempts,
+            recovery_backoff: self.recovery_backoff,
+            drain_timeout: self.drain_timeout,
+            shutdown_strategy: self.shutdown_strategy,
+            max_message_age: self.max_message_age,
+            quarantine_queue: self.quarantine_queue,
+            transform_body: self.transform_body,
+            filter: self.filter,
+            max_deliveries: self.max_deliveries,
+            failures: Arc::default(),
+            prefetch,
+            manual_ack: self.manual_ack,
+            requeue_on_error: self.requeue_on_error,
+            max_concurrency: self.max_concurrency,
+            run_for: self.run_for,
+            ack_tokens: Arc::default(),
+        });
+        (handle, ctx)
     }
 }
 
+/// Defaults of all [`BasicConsumeBuilder`]s of a client, see
+/// [`Client::with_consume_defaults`], so a consistent policy does not need to be repeated for
+/// every consumer. Each consumer can still override them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsumeDefaults {
+    /// Timeout/abort consumer operations.
+    timeout: Duration,
+    /// Consume messages in order, or in parallel.
+    in_parallel: bool,
+    /// How often to try recovering the consumer after a channel error.
+    recovery_attempts: u32,
+    /// Initial backoff between recovery attempts.
+    recovery_backoff: Duration,
+    /// Abandon in-flight deliveries after this duration when shutting down.
+    drain_timeout: Option<Duration>,
+    /// How to deal with in-flight deliveries when shutting down.
+    shutdown_strategy: ShutdownStrategy,
+    /// Park deliveries after this many failed deliveries.
+    max_deliveries: Option<u64>,
+    /// Requeue deliveries the handler failed for, instead of dead-lettering them.
+    requeue_on_error: bool,
+    /// Maximum amount of unacknowledged deliveries per consumer.
+    prefetch_count: Option<u16>,
+}
+
+impl Default for ConsumeDefaults {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(300),
+            in_parallel: true,
+            recovery_attempts: 5,
+            recovery_backoff: Duration::from_millis(500),
+            drain_timeout: None,
+            shutdown_strategy: ShutdownStrategy::Drain,
+            max_deliveries: None,
+            requeue_on_error: false,
+            prefetch_count: None,
+        }
+    }
+}
+
+impl ConsumeDefaults {
+    /// Create new `ConsumeDefaults` with the defaults of [`BasicConsumeBuilder`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// See [`BasicConsumeBuilder::timeout`].
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// See [`BasicConsumeBuilder::in_parallel`].
+    #[must_use]
+    pub fn in_parallel(mut self, in_parallel: bool) -> Self {
+        self.in_parallel = in_parallel;
+        self
+    }
+
+    /// See [`BasicConsumeBuilder::recovery`].
+    #[must_use]
+    pub fn recovery(mut self, attempts: u32, backoff: Duration) -> Self {
+        self.recovery_attempts = attempts;
+        self.recovery_backoff = backoff;
+        self
+    }
+
+    /// See [`BasicConsumeBuilder::drain_timeout`].
+    #[must_use]
+    pub fn drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = Some(drain_timeout);
+        self
+    }
+
+    /// See [`BasicConsumeBuilder::shutdown_strategy`].
+    #[must_use]
+    pub fn shutdown_strategy(mut self, shutdown_strategy: ShutdownStrategy) -> Self {
+        self.shutdown_strategy = shutdown_strategy;
+        self
+    }
+
+    /// See [`BasicConsumeBuilder::max_deliveries`]. Failing deliveries are requeued instead of
+    /// dead-lettered right away, on queues with dead lettering disabled they are dropped once
+    /// they exceed the maximum.
+    #[must_use]
+    pub fn max_deliveries(mut self, max_deliveries: u64) -> Self {
+        self.max_deliveries = Some(max_deliveries);
+        self
+    }
+
+    /// See [`BasicConsumeBuilder::requeue_on_error`].
+    #[must_use]
+    pub fn requeue_on_error(mut self, requeue_on_error: bool) -> Self {
+        self.requeue_on_error = requeue_on_error;
+        self
+    }
+
+    /// See [`BasicConsumeBuilder::prefetch_count`].
+    #[must_use]
+    pub fn prefetch_count(mut self, prefetch_count: u16) -> Self {
+        self.prefetch_count = Some(prefetch_count);
+        self
+    }
+}
+
+/// How a consumer deals with in-flight deliveries when it is shut down by a stop signal, see
+/// [`BasicConsumeBuilder::shutdown_strategy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownStrategy {
+    /// Wait for in-flight deliveries to finish, at most for the
+    /// [`BasicConsumeBuilder::drain_timeout`]. Completes the work already started, but delays
+    /// the shutdown by the slowest in-flight delivery.
+    Drain,
+    /// Abort in-flight deliveries right away and close the channel, the broker requeues all
+    /// unacknowledged deliveries to be redelivered to other consumers. Shuts down fast without
+    /// losing deliveries, e.g. for rolling deploys, but work already started is interrupted and
+    /// done again, so handlers need to be idempotent.
+    Requeue,
+}
+
 /// Error wrapper for this module.
 #[derive(Debug, thiserror::Error)]
 #[error(transparent)]
 pub struct ConsumeError(#[from] anyhow::Error);
 
-impl<'a, S> BuilderArgs for BasicConsumeBuilder<'a, S>
+/// Failure of a batch handler, see [`BasicConsumeBuilder::consume_batched`].
+#[derive(Debug)]
+pub enum BatchError {
+    /// The whole batch failed, every delivery is nacked.
+    All(anyhow::Error),
+    /// Only the deliveries at the given indices of the batch failed, the others are
+    /// acknowledged.
+    Partial(Vec<(usize, anyhow::Error)>),
+}
+
+impl<E> From<E> for BatchError
+where
+    E: Into<anyhow::Error>,
+{
+    fn from(err: E) -> Self {
+        Self::All(err.into())
+    }
+}
+
+impl<S> BuilderArgs for BasicConsumeBuilder<'_, S>
 where
     S: Clone + Send + Sync + 'static,
 {
@@ -167,17 +809,155 @@ where
     }
 }
 
-/// Consumer task, handle incoming deliveries.
-/// Also handle signals like sigterm.
+/// Settings and shared state of a consumer, passed on to its delivery tasks and extractors.
+#[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct ConsumerContext {
+    /// Handle to the consumers current channel.
+    pub(super) handle: ConsumerHandle,
+    /// Name of the queue that is consumed.
+    pub(super) queue_name: String,
+    /// The consumer tag the consumer is registered on.
+    pub(super) consumer_tag: String,
+    /// Lapins basic consume options.
+    pub(super) opts: BasicConsumeOptions,
+    /// Lapins field table.
+    pub(super) args: FieldTable,
+    /// Enable or disable dead lettering.
+    pub(super) dead_lettering: bool,
+    /// Timeout/abort consumer operations.
+    pub(super) timeout: Duration,
+    /// Consume messages in order, or in parallel.
+    pub(super) in_parallel: bool,
+    /// Acknowledge deliveries in batches.
+    pub(super) batch_ack: Option<Arc<BatchAck>>,
+    /// Expected type and version of [`crate::message::Envelope`]s.
+    pub(super) envelope: Option<(String, u32)>,
+    /// Schema versions of deliveries the consumer supports.
+    pub(super) supported_versions: Option<RangeInclusive<u32>>,
+    /// How often to try recovering the consumer after a channel error.
+    pub(super) recovery_attempts: u32,
+    /// Initial backoff between recovery attempts.
+    pub(super) recovery_backoff: Duration,
+    /// Abandon in-flight deliveries after this duration when shutting down.
+    pub(super) drain_timeout: Option<Duration>,
+    /// How to deal with in-flight deliveries when shutting down.
+    pub(super) shutdown_strategy: ShutdownStrategy,
+    /// Skip deliveries older than this.
+    pub(super) max_message_age: Option<Duration>,
+    /// Queue deliveries failing extraction are moved to.
+    pub(super) quarantine_queue: Option<String>,
+    /// Rewrites the body of deliveries before extraction.
+    pub(super) transform_body: Option<BodyTransform>,
+    /// Skips deliveries the handler is not interested in.
+    pub(super) filter: Option<DeliveryFilter>,
+    /// Park deliveries after this many failed deliveries.
+    pub(super) max_deliveries: Option<u64>,
+    /// Failed deliveries on queues that do not count deliveries themselves.
+    pub(super) failures: Arc<dlx::FailureCounter>,
+    /// Maximum number of unacknowledged deliveries, and whether it applies to the whole channel.
+    pub(super) prefetch: Option<(u16, bool)>,
+    /// Leave acknowledging deliveries to the handler.
+    pub(super) manual_ack: bool,
+    /// Requeue deliveries the handler failed for, instead of dead-lettering them.
+    pub(super) requeue_on_error: bool,
+    /// Maximum number of deliveries handled at once.
+    pub(super) max_concurrency: Option<usize>,
+    /// Shut the consumer down after running this long.
+    pub(super) run_for: Option<Duration>,
+    /// Deliveries an [`crate::consumer_extract::AckToken`] was handed out for, they are not
+    /// acknowledged automatically.
+    pub(super) ack_tokens: Arc<Mutex<HashSet<DeliveryTag>>>,
+}
+
+impl ConsumerContext {
+    /// Remember that the acknowledgement of a delivery is up to its
+    /// [`crate::consumer_extract::AckToken`].
+    pub(super) fn hand_out_ack_token(&self, delivery_tag: DeliveryTag) {
+        self.ack_tokens
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(delivery_tag);
+    }
+
+    /// Forget about the [`crate::consumer_extract::AckToken`] of a delivery, returns whether
+    /// one was handed out.
+    fn take_ack_token(&self, delivery_tag: DeliveryTag) -> bool {
+        self.ack_tokens
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&delivery_tag)
+    }
+}
+
+/// Function rewriting the body of a delivery.
+type BodyTransformFn = dyn Fn(&[u8]) -> Result<Vec<u8>, anyhow::Error> + Send + Sync;
+
+/// Rewrites the body of a delivery, see [`BasicConsumeBuilder::transform_body`].
+#[derive(Clone)]
+pub(super) struct BodyTransform(
+    /// The transformation.
+    Arc<BodyTransformFn>,
+);
+
+impl std::fmt::Debug for BodyTransform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("BodyTransform")
+    }
+}
+
+/// Predicate deciding whether a delivery is handled.
+type DeliveryFilterFn = dyn Fn(&Delivery) -> bool + Send + Sync;
+
+/// Skips deliveries, see [`BasicConsumeBuilder::filter`].
+#[derive(Clone)]
+pub(super) struct DeliveryFilter {
+    /// Whether to handle a delivery.
+    predicate: Arc<DeliveryFilterFn>,
+    /// Requeue skipped deliveries instead of acknowledging them.
+    requeue: bool,
+}
+
+impl std::fmt::Debug for DeliveryFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeliveryFilter")
+            .field("requeue", &self.requeue)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Commits the value produced by a handler, see [`BasicConsumeBuilder::consume_with_commit`].
+type CommitFn<V> = dyn Fn(V) -> BoxFuture<'static, Result<(), anyhow::Error>> + Send + Sync;
+
+/// Consumer task, handle incoming deliveries and close the consumers channel once done.
 async fn consumer_task_handler<S, H, T>(
     client: Client<S>,
-    queue_name: String,
-    dead_lettering: bool,
-    timeout: Duration,
-    in_parallel: bool,
+    ctx: Arc<ConsumerContext>,
+    handler: H,
+    commit: Option<Arc<CommitFn<H::Value>>>,
+    consumer: Consumer,
+) -> Result<ConsumerTaskReport, ConsumerTaskHandlerError>
+where
+    S: Clone + Send + Sync + 'static,
+    H: ConsumerHandler<S, T>,
+    T: 'static,
+{
+    let handle = ctx.handle.clone();
+    let result = consume_deliveries(client, ctx, handler, commit, consumer).await;
+    // Always close the dedicated channel, so starting and stopping consumers does not leak
+    // channels on the broker.
+    handle.close().await;
+    result
+}
+
+/// Handle incoming deliveries until the consumer fails or a signal like sigterm is received.
+async fn consume_deliveries<S, H, T>(
+    client: Client<S>,
+    mut ctx: Arc<ConsumerContext>,
     handler: H,
+    commit: Option<Arc<CommitFn<H::Value>>>,
     mut consumer: Consumer,
-) -> Result<(), ConsumerTaskHandlerError>
+) -> Result<ConsumerTaskReport, ConsumerTaskHandlerError>
 where
     S: Clone + Send + Sync + 'static,
     H: ConsumerHandler<S, T>,
@@ -188,66 +968,137 @@ where
 
     info!("consumer started");
 
+    let mut report = ConsumerTaskReport::default();
     let mut delivery_set = JoinSet::<()>::new();
+    let batch_ack_interval = ctx.batch_ack.as_ref().map(|batch_ack| batch_ack.interval());
+    let mut batch_ack_tick = batch_ack_interval.map(|interval| client.runtime.sleep(interval));
+    let mut deadline = ctx.run_for.map(|run_for| client.runtime.sleep(run_for));
+    let mut paused = false;
     loop {
         tokio::select! {
             signal_stop_result = util::signal_stop() => match signal_stop_result {
-                Ok(()) => break,
+                Ok(()) => {
+                    report.graceful = true;
+                    break;
+                }
                 Err(err) => {
                     return Err(ConsumerTaskHandlerError::StopSignal(err.into()))
                         .on_err(|err| error!("{ERR_TRACE_PREFIX}: {err}"));
                 }
             },
-            delivery_result_opt = consumer.next() => {
-                let Some(delivery_result) = delivery_result_opt else {
-                    return Err(ConsumerTaskHandlerError::Consumer(anyhow::format_err!(
-                        "consumer has stopped for an unknown reason"
-                    )))
-                    .on_err(|err| error!("{ERR_TRACE_PREFIX}: {err}"));
-                };
-                let delivery = match delivery_result {
-                    Ok(delivery) => Arc::new(delivery),
-                    Err(err) => {
-                        return Err(ConsumerTaskHandlerError::Consumer(err.into()))
-                            .on_err(|err| error!("{ERR_TRACE_PREFIX}: {err}"));
+            delivery_result_opt = consumer.next(), if !paused && has_capacity(&ctx, &delivery_set) => {
+                let delivery = match delivery_result_opt {
+                    Some(Ok(delivery)) => delivery,
+                    // Resumed right away if `resume` was called in the meantime, as its
+                    // notification is stored.
+                    None if ctx.handle.take_canceled() => {
+                        info!("consumer paused");
+                        paused = true;
+                        continue;
+                    }
+                    Some(Err(err)) => {
+                        consumer = recover_consumer(&client, &mut ctx, err.into())
+                            .await
+                            .on_err(|err| error!("{ERR_TRACE_PREFIX}: {err}"))?;
+                        continue;
+                    }
+                    None => {
+                        let err = anyhow::format_err!("consumer has stopped for an unknown reason");
+                        consumer = recover_consumer(&client, &mut ctx, err)
+                            .await
+                            .on_err(|err| error!("{ERR_TRACE_PREFIX}: {err}"))?;
+                        continue;
                     }
                 };
+                report.deliveries += 1;
+                let runtime = client.runtime.clone();
                 let client = client.clone();
-                let queue_name = queue_name.clone();
+                let ctx = ctx.clone();
                 let handler = handler.clone();
+                let commit = commit.clone();
 
-                let delivery_span = debug_span!(
-                    "delivery",
-                    msg_id = %delivery
-                        .properties
-                        .message_id()
-                        .clone()
-                        .map(|s| s.to_string())
-                        .unwrap_or_default(),
-                    delivery_tag = %delivery.delivery_tag,
+                let delivery_span = delivery_span(&delivery);
+                let in_parallel = ctx.in_parallel;
+                let task_name = format!(
+                    "hare delivery {}:{}#{}",
+                    ctx.queue_name, ctx.consumer_tag, delivery.delivery_tag
                 );
-                let delivery_task_handler = delivery_task_handler(
-                    client,
-                    queue_name,
-                    dead_lettering,
-                    timeout,
-                    handler,
-                    delivery,
-                )
-                .instrument(delivery_span);
+                let delivery_task_handler = delivery_task_handler(client, ctx, handler, commit, delivery)
+                    .instrument(delivery_span);
                 if in_parallel {
-                    delivery_set.spawn(delivery_task_handler);
+                    util::spawn_named_in(&mut delivery_set, &task_name, delivery_task_handler);
                 } else {
-                    std::mem::drop(task::spawn(delivery_task_handler).await);
+                    run_delivery_task(&*runtime, &task_name, delivery_task_handler).await;
+                }
+            },
+            () = ctx.handle.resumed(), if paused => {
+                if !ctx.handle.is_paused() {
+                    consumer = resume_consumer(&client, &mut ctx)
+                        .await
+                        .on_err(|err| error!("{ERR_TRACE_PREFIX}: {err}"))?;
+                    paused = false;
                 }
             },
             Some(_) = delivery_set.join_next() => (),
+            Some(()) = async { batch_ack_tick.as_mut()?.await; Some(()) } => {
+                flush_batch_ack(ctx.batch_ack.as_deref()).await;
+                batch_ack_tick = batch_ack_interval.map(|interval| client.runtime.sleep(interval));
+            },
+            Some(()) = async { deadline.as_mut()?.await; Some(()) } => {
+                info!("consumer ran for its run time, shutting down");
+                report.graceful = true;
+                break;
+            },
         }
     }
-    while delivery_set.join_next().await.is_some() {}
+    match ctx.shutdown_strategy {
+        ShutdownStrategy::Drain => {
+            drain_deliveries(&*client.runtime, &mut delivery_set, ctx.drain_timeout).await;
+        }
+        ShutdownStrategy::Requeue => {
+            warn!(
+                aborted = delivery_set.len(),
+                "aborting in-flight deliveries, the broker requeues them"
+            );
+            delivery_set.shutdown().await;
+        }
+    }
+    flush_batch_ack(ctx.batch_ack.as_deref()).await;
 
-    info!("consumer shut down");
-    Ok(())
+    info!(deliveries = report.deliveries, "consumer shut down");
+    Ok(report)
+}
+
+/// Run the delivery task on the runtime and wait until it finished.
+async fn run_delivery_task<F>(runtime: &dyn Runtime, name: &str, task: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let (done_sender, done_receiver) = oneshot::channel();
+    runtime.spawn_named(
+        name,
+        Box::pin(async move {
+            task.await;
+            let _ = done_sender.send(());
+        }),
+    );
+    // Also completes once the sender was dropped, because the task panicked.
+    std::mem::drop(done_receiver.await);
+}
+
+/// Whether another delivery may be handled, without exceeding the maximum concurrency.
+fn has_capacity(ctx: &ConsumerContext, delivery_set: &JoinSet<()>) -> bool {
+    ctx.max_concurrency
+        .map_or(true, |max_concurrency| delivery_set.len() < max_concurrency)
+}
+
+/// Summary of a consumer task that has shut down.
+#[derive(Debug, Default)]
+pub(super) struct ConsumerTaskReport {
+    /// Amount of deliveries handed to the handler.
+    pub deliveries: u64,
+    /// Whether the consumer shut down due to a stop signal.
+    pub graceful: bool,
 }
 
 /// Errors that can occur when handling the consumer task.
@@ -261,59 +1112,526 @@ pub(super) enum ConsumerTaskHandlerError {
     Consumer(#[from] anyhow::Error),
 }
 
+/// Create a channel for a consumer, limiting its unacknowledged deliveries to the prefetch count.
+async fn open_channel<S>(
+    client: &Client<S>,
+    prefetch: Option<(u16, bool)>,
+) -> Result<lapin::Channel, anyhow::Error>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let chan = client.create_channel().await?;
+    if let Some((prefetch_count, global)) = prefetch {
+        chan.basic_qos(prefetch_count, BasicQosOptions { global })
+            .await?;
+    }
+    Ok(chan)
+}
+
+/// Recover a consumer after its channel failed, by consuming on a new channel. Only attempted
+/// while the connection itself is still alive, or if the [`Client`] reconnects, see
+/// [`Client::with_reconnect`], other connection errors are handled by the [`Client`].
+async fn recover_consumer<S>(
+    client: &Client<S>,
+    ctx: &mut Arc<ConsumerContext>,
+    err: anyhow::Error,
+) -> Result<Consumer, ConsumerTaskHandlerError>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let _recovering = health::Recovering::start(&client.recovering);
+    let mut backoff = ctx.recovery_backoff;
+    for attempt in 1..=ctx.recovery_attempts {
+        if !client.status().connected() && !client.connector.reconnects() {
+            break;
+        }
+        warn!(attempt, "consumer channel failed, recovering: {err}");
+        client.runtime.sleep(backoff).await;
+        backoff = backoff.saturating_mul(2);
+
+        let chan = match open_channel(client, ctx.prefetch).await {
+            Ok(chan) => chan,
+            Err(err) => {
+                warn!(attempt, "recovering consumer failed: {err}");
+                continue;
+            }
+        };
+        let consumer = match chan
+            .basic_consume(
+                &ctx.queue_name,
+                &ctx.consumer_tag,
+                ctx.opts,
+                ctx.args.clone(),
+            )
+            .await
+        {
+            Ok(consumer) => consumer,
+            Err(err) => {
+                warn!(attempt, "recovering consumer failed: {err}");
+                continue;
+            }
+        };
+
+        ctx.handle.replace(chan.clone(), consumer.clone());
+
+        // Delivery tags are scoped to the channel, in-flight deliveries of the failed channel
+        // keep acknowledging on their own (failed) batch and tracking their own ack tokens, so
+        // their tags do not collide with the ones of the new channel.
+        let mut recovered_ctx = (**ctx).clone();
+        recovered_ctx.batch_ack = ctx
+            .batch_ack
+            .as_ref()
+            .map(|batch_ack| Arc::new(batch_ack.with_channel(chan)));
+        recovered_ctx.ack_tokens = Arc::default();
+        *ctx = Arc::new(recovered_ctx);
+
+        info!(attempt, "consumer recovered");
+        return Ok(consumer);
+    }
+    Err(ConsumerTaskHandlerError::Consumer(err))
+}
+
+/// The span of a delivery, the consumer handler records the time spent in extractors and the
+/// handler itself on it.
+pub(super) fn delivery_span(delivery: &Delivery) -> tracing::Span {
+    debug_span!(
+        "delivery",
+        msg_id = %delivery
+            .properties
+            .message_id()
+            .clone()
+            .map(|s| s.to_string())
+            .unwrap_or_default(),
+        delivery_tag = %delivery.delivery_tag,
+        extraction_time = tracing::field::Empty,
+        handler_time = tracing::field::Empty,
+    )
+}
+
+/// Resume a paused consumer by consuming on its channel again, recovers the consumer on a new
+/// channel if that fails.
+async fn resume_consumer<S>(
+    client: &Client<S>,
+    ctx: &mut Arc<ConsumerContext>,
+) -> Result<Consumer, ConsumerTaskHandlerError>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let chan = ctx.handle.channel();
+    match chan
+        .basic_consume(
+            &ctx.queue_name,
+            &ctx.consumer_tag,
+            ctx.opts,
+            ctx.args.clone(),
+        )
+        .await
+    {
+        Ok(consumer) => {
+            ctx.handle.replace(chan, consumer.clone());
+            info!("consumer resumed");
+            Ok(consumer)
+        }
+        Err(err) => recover_consumer(client, ctx, err.into()).await,
+    }
+}
+
+/// Wait for in-flight deliveries to finish, abort them once the `drain_timeout` is reached.
+async fn drain_deliveries(
+    runtime: &dyn Runtime,
+    delivery_set: &mut JoinSet<()>,
+    drain_timeout: Option<Duration>,
+) {
+    let Some(drain_timeout) = drain_timeout else {
+        while delivery_set.join_next().await.is_some() {}
+        return;
+    };
+    let drain = async { while delivery_set.join_next().await.is_some() {} };
+    if runtime::timeout(runtime, drain_timeout, drain)
+        .await
+        .is_none()
+    {
+        warn!(
+            abandoned = delivery_set.len(),
+            "drain timeout of {drain_timeout:?} reached, abandoning in-flight deliveries"
+        );
+        delivery_set.shutdown().await;
+    }
+}
+
+/// Flush pending acknowledgements, if acknowledging in batches.
+async fn flush_batch_ack(batch_ack: Option<&BatchAck>) {
+    let Some(batch_ack) = batch_ack else {
+        return;
+    };
+    if let Err(err) = batch_ack.flush().await {
+        error!("acking delivery batch failed: {err}");
+    }
+}
+
 /// Handle incoming deliveries.
-async fn delivery_task_handler<S, H, T>(
+pub(super) async fn delivery_task_handler<S, H, T>(
     client: Client<S>,
-    queue_name: String,
-    dead_lettering: bool,
-    timeout: Duration,
+    ctx: Arc<ConsumerContext>,
     handler: H,
-    delivery: Arc<Delivery>,
+    commit: Option<Arc<CommitFn<H::Value>>>,
+    mut delivery: Delivery,
 ) where
     S: Clone + Send + Sync + 'static,
     H: ConsumerHandler<S, T>,
     T: 'static,
 {
+    if let Some(filter) = ctx
+        .filter
+        .as_ref()
+        .filter(|filter| !(filter.predicate)(&delivery))
+    {
+        skip_delivery(&ctx, &delivery, filter.requeue).await;
+        return;
+    }
+
     let start = Instant::now();
     debug!("started processing delivery");
+    client.observer.on_consume_start(&ctx.queue_name, &delivery);
+
+    // Quarantined deliveries are published as received, not as transformed, e.g. decrypted.
+    let received_data = (ctx.quarantine_queue.is_some() && ctx.transform_body.is_some())
+        .then(|| delivery.data.clone());
+    let prepare_res = prepare_delivery(&ctx, &mut delivery);
+    let delivery = Arc::new(delivery);
 
     let mut was_acked = false;
     'handler: {
-        let handler_res =
-            tokio::time::timeout(timeout, handler.call(client.clone(), delivery.clone()))
-                .await
-                .unwrap_or(Err(ConsumerHandlerError::Timeout(timeout)));
+        let handler_res = match prepare_res {
+            Ok(()) => call_handler(&client, &ctx, handler, &delivery).await,
+            Err(err) => Err(err),
+        };
+        let handler_res = match (handler_res, commit) {
+            (Ok(value), Some(commit)) => commit(value).await.map_err(ConsumerHandlerError::Commit),
+            (Ok(_), None) => Ok(()),
+            (Err(err), _) => Err(err),
+        };
+        // Deliveries failing extraction never reached the handler, they are settled as usual.
+        let handler_ran = handler_res
+            .as_ref()
+            .map_or_else(|err| err.extraction_failure().is_none(), |()| true);
+        if ctx.manual_ack && handler_ran {
+            settle_manually(&client, &ctx, &delivery, handler_res).await;
+            break 'handler;
+        }
+        let ack_token_handed_out = ctx.take_ack_token(delivery.delivery_tag);
         match handler_res {
+            Ok(()) if ack_token_handed_out => {
+                debug!("leaving acknowledgement to the ack token");
+            }
             Ok(()) => {
-                if let Err(err) = delivery.ack(BasicAckOptions::default()).await {
+                if let Err(err) = ack_delivery(&ctx, &delivery).await {
                     error!("acking delivery failed: {err}");
                     break 'handler;
                 }
                 was_acked = true;
             }
-            Err(err) => {
-                error!("handler failed: {err}");
-                if let Err(err) = delivery.nack(BasicNackOptions::default()).await {
-                    error!("nacking delivery failed: {err}");
+            Err(err) if ctx.quarantine_queue.is_some() && err.extraction_failure().is_some() => {
+                error!("quarantining delivery: {err}");
+                let data = received_data.as_deref().unwrap_or(&delivery.data);
+                if let Err(err) = quarantine_delivery(&client, &ctx, &delivery, data, &err).await {
+                    error!("quarantining delivery failed: {err}");
+                    if let Err(err) = nack_delivery(&ctx, &delivery, false).await {
+                        error!("nacking delivery failed: {err}");
+                    }
+                    break 'handler;
+                }
+                if let Err(err) = ack_delivery(&ctx, &delivery).await {
+                    error!("acking delivery failed: {err}");
                     break 'handler;
                 }
-                if !dead_lettering || delivery.properties.message_id().is_none() {
+                was_acked = true;
+            }
+            Err(err @ ConsumerHandlerError::Stale(_)) => {
+                warn!("skipping delivery: {err}");
+                if let Err(err) = ack_delivery(&ctx, &delivery).await {
+                    error!("acking delivery failed: {err}");
                     break 'handler;
                 }
-                if let Err(err) = client
-                    .basic_publish_builder(dlx::EXCHANGE_NAME, dlx::ROUTING_KEY_ERROR)
-                    .publish(dlx::ErrorData::new(queue_name, &delivery, &err))
-                    .await
-                {
-                    error!("publishing error data to DLX failed: {err}");
+                was_acked = true;
+            }
+            Err(err @ ConsumerHandlerError::Commit(_)) => {
+                error!("{err}, requeueing delivery");
+                client.observer.on_nack(&ctx.queue_name, &delivery, &err);
+                if let Err(err) = nack_delivery(&ctx, &delivery, true).await {
+                    error!("nacking delivery failed: {err}");
                 }
             }
+            Err(err) => {
+                error!("handler failed: {err}");
+                client.observer.on_nack(&ctx.queue_name, &delivery, &err);
+                fail_delivery(&client, &ctx, &delivery, &err).await;
+            }
         }
     }
 
+    let latency = start.elapsed();
+    client
+        .observer
+        .on_consume_end(&ctx.queue_name, &delivery, latency, was_acked);
     debug!(
-        latency = ?start.elapsed(),
+        ?latency,
         %was_acked,
         "finished processing delivery",
     );
 }
+
+/// Call the handler, bounded by the consumer timeout. A panicking handler fails with
+/// [`ConsumerHandlerError::Panicked`] instead of tearing down the delivery task.
+async fn call_handler<S, H, T>(
+    client: &Client<S>,
+    ctx: &Arc<ConsumerContext>,
+    handler: H,
+    delivery: &Arc<Delivery>,
+) -> Result<H::Value, ConsumerHandlerError>
+where
+    S: Clone + Send + Sync + 'static,
+    H: ConsumerHandler<S, T>,
+    T: 'static,
+{
+    let call =
+        std::panic::AssertUnwindSafe(handler.call(client.clone(), ctx.clone(), delivery.clone()))
+            .catch_unwind();
+    match runtime::timeout(&*client.runtime, ctx.timeout, call).await {
+        Some(Ok(handler_res)) => handler_res,
+        Some(Err(panic)) => Err(ConsumerHandlerError::Panicked(
+            panic
+                .downcast_ref::<&str>()
+                .map(ToString::to_string)
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default(),
+        )),
+        None => Err(ConsumerHandlerError::Timeout(ctx.timeout)),
+    }
+}
+
+/// Report the outcome of a delivery the handler settles itself, see
+/// [`BasicConsumeBuilder::manual_ack`]. If the handler timed out or panicked before settling
+/// the delivery, nobody is left to settle it, so it is nacked like a failed delivery.
+async fn settle_manually<S>(
+    client: &Client<S>,
+    ctx: &ConsumerContext,
+    delivery: &Delivery,
+    handler_res: Result<(), ConsumerHandlerError>,
+) where
+    S: Clone + Send + Sync + 'static,
+{
+    match handler_res {
+        Err(err @ (ConsumerHandlerError::Timeout(_) | ConsumerHandlerError::Panicked(_)))
+            if !delivery.acker.used() =>
+        {
+            error!("handler failed without settling the delivery: {err}");
+            client.observer.on_nack(&ctx.queue_name, delivery, &err);
+            fail_delivery(client, ctx, delivery, &err).await;
+            return;
+        }
+        Err(err) => {
+            error!("handler failed: {err}");
+            client.observer.on_nack(&ctx.queue_name, delivery, &err);
+        }
+        Ok(()) => {}
+    }
+    if !delivery.acker.used() {
+        warn!("handler did not settle the delivery, it stays unacknowledged");
+    }
+}
+
+/// Acknowledge or requeue a delivery rejected by the [`DeliveryFilter`], without handling it.
+async fn skip_delivery(ctx: &ConsumerContext, delivery: &Delivery, requeue: bool) {
+    let skip_res = if requeue {
+        debug!("requeueing filtered delivery");
+        nack_delivery(ctx, delivery, true).await
+    } else {
+        debug!("skipping filtered delivery");
+        ack_delivery(ctx, delivery).await
+    };
+    if let Err(err) = skip_res {
+        error!("settling filtered delivery failed: {err}");
+    }
+}
+
+/// Nack a delivery the handler failed for. Requeues it if it has deliveries left or requeueing on
+/// errors is enabled, otherwise dead-letters it and publishes error data, or parked data once it
+/// exceeded the max deliveries.
+async fn fail_delivery<S>(
+    client: &Client<S>,
+    ctx: &ConsumerContext,
+    delivery: &Delivery,
+    err: &ConsumerHandlerError,
+) where
+    S: Clone + Send + Sync + 'static,
+{
+    let deliveries = ctx
+        .max_deliveries
+        .map(|max| (dlx::deliveries(delivery, max, &ctx.failures), max));
+    if let Some((deliveries, max)) = deliveries.filter(|(deliveries, max)| deliveries < max) {
+        let delay = requeue_delay(deliveries);
+        warn!("requeueing delivery in {delay:?}, delivered {deliveries} of {max} times");
+        // Back off instead of hot-looping a message that keeps failing.
+        client.runtime.sleep(delay).await;
+        if let Err(err) = nack_delivery(ctx, delivery, true).await {
+            error!("nacking delivery failed: {err}");
+        }
+        return;
+    }
+    if let (Some(_), Some(message_id)) = (deliveries, delivery.properties.message_id()) {
+        ctx.failures.forget(message_id.as_str());
+    }
+    let requeue =
+        ctx.requeue_on_error && ctx.max_deliveries.is_none() && err.extraction_failure().is_none();
+    if let Err(err) = nack_delivery(ctx, delivery, requeue).await {
+        error!("nacking delivery failed: {err}");
+        return;
+    }
+    if requeue {
+        warn!("requeued delivery");
+        return;
+    }
+    if !ctx.dead_lettering || delivery.properties.message_id().is_none() {
+        return;
+    }
+    let queue_name = ctx.queue_name.clone();
+    let publish_res = if let Some((deliveries, _)) = deliveries {
+        error!("parking delivery, delivered {deliveries} times");
+        let parked_data = dlx::ParkedData::new(queue_name, delivery, err, deliveries);
+        dlx::publish_error_data(client, dlx::ROUTING_KEY_PARKED, &parked_data).await
+    } else {
+        let error_data = dlx::ErrorData::new(queue_name, delivery, err);
+        dlx::publish_error_data(client, dlx::ROUTING_KEY_ERROR, &error_data).await
+    };
+    if let Err(err) = publish_res {
+        error!("publishing error data to DLX failed: {err}");
+        return;
+    }
+    client.observer.on_dead_letter(&ctx.queue_name, delivery);
+}
+
+/// Delay before requeueing a delivery that was delivered `deliveries` times, see
+/// [`BasicConsumeBuilder::max_deliveries`]. Starts at 100 milliseconds and doubles with every
+/// delivery, up to 10 seconds.
+fn requeue_delay(deliveries: u64) -> Duration {
+    /// Delay after the first delivery.
+    const INITIAL_DELAY: Duration = Duration::from_millis(100);
+    /// Upper bound of the delay.
+    const MAX_DELAY: Duration = Duration::from_secs(10);
+
+    let exponent = u32::try_from(deliveries.saturating_sub(1)).unwrap_or(u32::MAX);
+    INITIAL_DELAY
+        .saturating_mul(2_u32.saturating_pow(exponent))
+        .min(MAX_DELAY)
+}
+
+/// Check the age of a delivery, transform its body and check its schema version, before
+/// handing it to the handler.
+fn prepare_delivery(
+    ctx: &ConsumerContext,
+    delivery: &mut Delivery,
+) -> Result<(), ConsumerHandlerError> {
+    if let (Some(max_message_age), Some(timestamp)) =
+        (ctx.max_message_age, delivery.properties.timestamp())
+    {
+        #[allow(clippy::cast_sign_loss)]
+        let now = Utc::now().timestamp() as u64;
+        let age = Duration::from_secs(now.saturating_sub(*timestamp));
+        if age > max_message_age {
+            return Err(ConsumerHandlerError::Stale(anyhow::format_err!(
+                "message is {age:?} old, exceeding the max age of {max_message_age:?}"
+            )));
+        }
+    }
+    if let Some(BodyTransform(transform_body)) = &ctx.transform_body {
+        delivery.data = transform_body(&delivery.data).map_err(ConsumerHandlerError::Transform)?;
+    }
+    if let Some(supported_versions) = &ctx.supported_versions {
+        let extractor_err = |err| ConsumerHandlerError::Extractor("SchemaVersion".into(), err);
+        let SchemaVersion(version) =
+            SchemaVersion::from_delivery(delivery).map_err(extractor_err)?;
+        if !supported_versions.contains(&version) {
+            return Err(extractor_err(anyhow::format_err!(
+                "unsupported schema version {version}, supported are {}..={}",
+                supported_versions.start(),
+                supported_versions.end()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Acknowledge a delivery, in batches if enabled.
+async fn ack_delivery(ctx: &ConsumerContext, delivery: &Delivery) -> Result<(), lapin::Error> {
+    match &ctx.batch_ack {
+        Some(batch_ack) => batch_ack.ack(delivery.delivery_tag).await,
+        None => delivery.ack(BasicAckOptions::default()).await,
+    }
+}
+
+/// Negatively acknowledge a delivery, in batches if enabled.
+async fn nack_delivery(
+    ctx: &ConsumerContext,
+    delivery: &Delivery,
+    requeue: bool,
+) -> Result<(), lapin::Error> {
+    match &ctx.batch_ack {
+        Some(batch_ack) => batch_ack.nack(delivery.delivery_tag, requeue).await,
+        None => {
+            delivery
+                .nack(BasicNackOptions {
+                    requeue,
+                    ..BasicNackOptions::default()
+                })
+                .await
+        }
+    }
+}
+
+/// Publish a delivery whose extractor failed to the quarantine queue, keeping its properties and
+/// adding diagnostic headers. `data` is the payload as received, before the body was
+/// transformed.
+async fn quarantine_delivery<S>(
+    client: &Client<S>,
+    ctx: &ConsumerContext,
+    delivery: &Delivery,
+    data: &[u8],
+    err: &ConsumerHandlerError,
+) -> Result<(), anyhow::Error>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let (Some(quarantine_queue), Some((extractor, err))) =
+        (&ctx.quarantine_queue, err.extraction_failure())
+    else {
+        return Ok(());
+    };
+    let mut headers = delivery.properties.headers().clone().unwrap_or_default();
+    headers.insert(
+        QUARANTINE_HEADER_ERROR.into(),
+        AMQPValue::LongString(err.to_string().into()),
+    );
+    headers.insert(
+        QUARANTINE_HEADER_EXTRACTOR.into(),
+        AMQPValue::LongString(extractor.into()),
+    );
+    headers.insert(
+        QUARANTINE_HEADER_ORIGIN_QUEUE.into(),
+        AMQPValue::LongString(ctx.queue_name.as_str().into()),
+    );
+    let props = delivery.properties.clone().with_headers(headers);
+
+    let chan = client.get_channel().await?;
+    client.observer.on_publish("", quarantine_queue, &props);
+    chan.basic_publish(
+        "",
+        quarantine_queue,
+        BasicPublishOptions::default(),
+        data,
+        props,
+    )
+    .await?
+    .await?;
+    Ok(())
+}
@@ -1,13 +1,25 @@
 //! [`BasicPublishBuilder`] implementation.
 
+use std::future::IntoFuture;
+use std::marker::PhantomData;
+use std::time::Duration;
+
 use chrono::Utc;
-use lapin::options::BasicPublishOptions;
-use lapin::publisher_confirm::PublisherConfirm;
-use lapin::types::{FieldTable, ShortString};
+use lapin::message::Delivery;
+use lapin::options::{BasicPublishOptions, ConfirmSelectOptions};
+use lapin::protocol::constants::REPLY_SUCCESS;
+use lapin::publisher_confirm::{Confirmation, PublisherConfirm};
+use lapin::types::{AMQPValue, Boolean, FieldTable, LongLongInt, LongString, ShortString};
 use lapin::BasicProperties;
 use serde::Serialize;
+use tracing::warn;
+
+use crate::{message, Client, RateLimiter};
 
-use crate::{message, Client};
+/// `delivery-mode` of messages that are not written to disk.
+const DELIVERY_MODE_TRANSIENT: u8 = 1;
+/// `delivery-mode` of messages that are written to disk.
+const DELIVERY_MODE_PERSISTENT: u8 = 2;
 
 /// Publish a serializable data to an exchange.
 pub struct BasicPublishBuilder<'a, S>
@@ -24,6 +36,11 @@ where
     opts: BasicPublishOptions,
     /// Message properties.
     props: BasicProperties,
+    /// Limits the publish throughput.
+    rate_limiter: Option<RateLimiter>,
+    /// Type, `None` for the payloads type name, and version to wrap the payload in an
+    /// [`message::Envelope`] with.
+    envelope: Option<(Option<String>, u32)>,
 }
 
 impl<'a, S> BasicPublishBuilder<'a, S>
@@ -33,7 +50,6 @@ where
     /// Create a new `QueueDeleteBuilder` instance.
     #[must_use]
     pub fn new(client: &'a Client<S>, exchange_name: &'a str, routing_key: &'a str) -> Self {
-        #[allow(clippy::cast_sign_loss)]
         Self {
             client,
             exchange_name,
@@ -42,20 +58,43 @@ where
                 immediate: false,
                 mandatory: false,
             },
-            props: BasicProperties::default()
-                .with_app_id(client.app_id.to_string().into())
-                .with_message_id(message::Id::new_v4().to_string().into())
-                .with_timestamp(Utc::now().timestamp() as u64),
+            props: Self::default_props(client),
+            rate_limiter: None,
+            envelope: None,
         }
     }
 
-    /// Defaults to `false`.
+    /// The properties every message is published with by default.
+    pub(super) fn default_props(client: &Client<S>) -> BasicProperties {
+        #[allow(clippy::cast_sign_loss)]
+        let props = BasicProperties::default()
+            .with_app_id(client.app_id.to_string().into())
+            .with_message_id((client.message_id_generator)().to_string().into())
+            .with_timestamp(Utc::now().timestamp() as u64)
+            .with_delivery_mode(DELIVERY_MODE_PERSISTENT);
+        match client.username.as_ref() {
+            Some(username) => props.with_user_id(username.clone().into()),
+            None => props,
+        }
+    }
+
+    /// Defaults to `false`. `RabbitMQ` removed support for the `immediate` flag in 3.0 and closes
+    /// the channel when it is set, a warning is logged when publishing with it.
+    #[deprecated(note = "RabbitMQ does not support the immediate flag and closes the channel")]
     #[must_use]
     pub fn immediate(mut self, immediate: bool) -> Self {
         self.opts.immediate = immediate;
         self
     }
 
+    /// Not set by default. Waits until the [`RateLimiter`] allows the publish, publishing never
+    /// fails because the limit is hit. Share the limiter to cap several publishers together.
+    #[must_use]
+    pub fn rate_limit(mut self, rate_limiter: &RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter.clone());
+        self
+    }
+
     /// Defaults to `false`.
     #[must_use]
     pub fn mandatory(mut self, mandatory: bool) -> Self {
@@ -63,6 +102,40 @@ where
         self
     }
 
+    /// Not set by default. Wraps the payload in a [`message::Envelope`] of type `kind` and
+    /// `version`, which are also set as the [`message::MESSAGE_TYPE_HEADER`] and
+    /// [`message::SCHEMA_VERSION_HEADER`] headers. Consumers using the [`message::Envelope`]
+    /// extractor validate the headers before deserializing the body, see
+    /// [`crate::basic_consume_builder::BasicConsumeBuilder::envelope`].
+    #[must_use]
+    pub fn envelope(mut self, kind: impl Into<String>, version: u32) -> Self {
+        self.envelope = Some((Some(kind.into()), version));
+        self
+    }
+
+    /// Not set by default. Like [`Self::envelope`], using the type name of the payload, see
+    /// [`std::any::type_name`], as the type. Note that type names are not guaranteed to be
+    /// stable across compiler versions, prefer [`Self::envelope`] for long-lived messages.
+    #[must_use]
+    pub fn typed_envelope(mut self, version: u32) -> Self {
+        self.envelope = Some((None, version));
+        self
+    }
+
+    /// Defaults to `true`. Persistent messages are written to disk and survive a broker restart,
+    /// but only if they are routed to a durable queue, a persistent message in a non-durable
+    /// queue is lost with the queue. Transient messages are faster, but kept in memory only.
+    #[must_use]
+    pub fn persistent(mut self, persistent: bool) -> Self {
+        let delivery_mode = if persistent {
+            DELIVERY_MODE_PERSISTENT
+        } else {
+            DELIVERY_MODE_TRANSIENT
+        };
+        self.props = self.props.with_delivery_mode(delivery_mode);
+        self
+    }
+
     /// Add additional publish options.
     #[must_use]
     pub fn opts<F>(mut self, f: F) -> Self
@@ -89,6 +162,44 @@ where
         self
     }
 
+    /// Chains the message to the delivery that caused it, by setting the `correlation id` to the
+    /// message id of the delivery, e.g. when publishing follow-up events from a handler. Leaves
+    /// the `correlation id` untouched if the delivery has no message id.
+    #[must_use]
+    pub fn caused_by(mut self, delivery: &Delivery) -> Self {
+        if let Some(message_id) = delivery.properties.message_id() {
+            self.props = self.props.with_correlation_id(message_id.clone());
+        }
+        self
+    }
+
+    /// Not set by default. Sets the [`message::CAUSATION_ID_HEADER`] header, the id of the
+    /// message that caused this one. Merged into the existing headers, so set
+    /// [`Self::headers`] first.
+    #[must_use]
+    pub fn causation_id(self, causation_id: impl Into<String>) -> Self {
+        self.header_str(message::CAUSATION_ID_HEADER, causation_id.into())
+    }
+
+    /// Continues the event lineage of the delivery that caused the message, e.g. when publishing
+    /// follow-up events from a handler. The causation id is set to the message id of the
+    /// delivery, the `correlation id` is taken over from the delivery, or set to its message id
+    /// if it has none, so all messages of a chain share the id of the message that started it.
+    /// Leaves both untouched if the delivery has no message id.
+    #[must_use]
+    pub fn follows(mut self, delivery: &Delivery) -> Self {
+        let Some(message_id) = delivery.properties.message_id() else {
+            return self;
+        };
+        let correlation_id = delivery
+            .properties
+            .correlation_id()
+            .as_ref()
+            .unwrap_or(message_id);
+        self.props = self.props.with_correlation_id(correlation_id.clone());
+        self.causation_id(message_id.as_str())
+    }
+
     /// Defaults to `None`.
     #[must_use]
     pub fn headers<T>(mut self, headers: T) -> Self
@@ -99,13 +210,80 @@ where
         self
     }
 
-    /// Defaults to a randomly generated [`message::Id`].
+    /// Set a single header, merged into the existing headers, so set [`Self::headers`] first.
+    /// Takes any [`AMQPValue`], e.g. a [`FieldArray`](lapin::types::FieldArray) or a nested
+    /// [`FieldTable`].
+    #[must_use]
+    pub fn header<K, V>(mut self, k: K, v: V) -> Self
+    where
+        K: Into<ShortString>,
+        V: Into<AMQPValue>,
+    {
+        let mut headers = self.props.headers().clone().unwrap_or_default();
+        headers.insert(k.into(), v.into());
+        self.props = self.props.with_headers(headers);
+        self
+    }
+
+    /// Set a `string` header, see [`Self::header`].
+    #[must_use]
+    pub fn header_str<K, V>(self, k: K, v: V) -> Self
+    where
+        K: Into<ShortString>,
+        V: Into<LongString>,
+    {
+        self.header(k, AMQPValue::LongString(v.into()))
+    }
+
+    /// Set an `i64` header, see [`Self::header`].
+    #[must_use]
+    pub fn header_i64<K, V>(self, k: K, v: V) -> Self
+    where
+        K: Into<ShortString>,
+        V: Into<LongLongInt>,
+    {
+        self.header(k, AMQPValue::LongLongInt(v.into()))
+    }
+
+    /// Set a boolean header, see [`Self::header`].
+    #[must_use]
+    pub fn header_bool<K, V>(self, k: K, v: V) -> Self
+    where
+        K: Into<ShortString>,
+        V: Into<Boolean>,
+    {
+        self.header(k, AMQPValue::Boolean(v.into()))
+    }
+
+    /// Defaults to a [`message::Id`] generated by the generator of the client, see
+    /// [`Client::with_message_id_generator`].
     #[must_use]
     pub fn message_id(mut self, message_id: message::Id) -> Self {
         self.props = self.props.with_message_id(message_id.to_string().into());
         self
     }
 
+    /// Defaults to `None`. Ignored by queues without a maximum message priority, see
+    /// [`crate::Priority::message_priority`] to validate it.
+    #[must_use]
+    pub fn priority(mut self, priority: u8) -> Self {
+        self.props = self.props.with_priority(priority);
+        self
+    }
+
+    /// Not set by default. Sets the per-message TTL, the broker discards or dead-letters the
+    /// message once it waited longer than `expiration` in a queue.
+    ///
+    /// # Errors
+    /// [`PublishError::ExpirationTooLong`] when the duration millis are bigger than `u32::MAX`,
+    /// the maximum TTL of `RabbitMQ`.
+    pub fn expiration(mut self, expiration: Duration) -> Result<Self, PublishError> {
+        let millis = u32::try_from(expiration.as_millis())
+            .map_err(|_| PublishError::ExpirationTooLong(expiration))?;
+        self.props = self.props.with_expiration(millis.to_string().into());
+        Ok(self)
+    }
+
     /// Defaults to `None`.
     #[must_use]
     pub fn reply_to<T>(mut self, reply_to: T) -> Self
@@ -116,6 +294,50 @@ where
         self
     }
 
+    /// Not set by default. Sets the `type` property, the name of the message or event type,
+    /// e.g. for consumers to dispatch on. Not to be confused with the `content-type` or the
+    /// [`message::MESSAGE_TYPE_HEADER`] header of envelopes.
+    #[must_use]
+    pub fn message_type<T>(mut self, message_type: T) -> Self
+    where
+        T: Into<ShortString>,
+    {
+        self.props = self.props.with_type(message_type.into());
+        self
+    }
+
+    /// Defaults to [`message::JSON_CONTENT_TYPE`] for payloads serialized by the builder, not set
+    /// otherwise. Name the format of the payload for consumers in other languages and tools.
+    #[must_use]
+    pub fn content_type<T>(mut self, content_type: T) -> Self
+    where
+        T: Into<ShortString>,
+    {
+        self.props = self.props.with_content_type(content_type.into());
+        self
+    }
+
+    /// Not set by default. Sets the `content-encoding` property, e.g. `gzip` for compressed
+    /// payloads.
+    #[must_use]
+    pub fn content_encoding<T>(mut self, content_encoding: T) -> Self
+    where
+        T: Into<ShortString>,
+    {
+        self.props = self.props.with_content_encoding(content_encoding.into());
+        self
+    }
+
+    /// Defaults to the username of the connection uri. Not set by default if the uri names no
+    /// user or uses the `EXTERNAL` authentication mechanism. `RabbitMQ` validates that the
+    /// `user-id` property matches the user the connection was authenticated with; a mismatch
+    /// causes the broker to reject the publish.
+    #[must_use]
+    pub fn user_id(mut self, user_id: String) -> Self {
+        self.props = self.props.with_user_id(user_id.into());
+        self
+    }
+
     /// Defaults to [`Utc::now().timestamp()`].
     #[must_use]
     pub fn timestamp(mut self, timestamp: u64) -> Self {
@@ -133,32 +355,381 @@ where
         self
     }
 
+    /// Replace all message properties with `props`, including the `app_id`, `message_id`,
+    /// `timestamp`, `user_id` and `delivery-mode` stamped by default, e.g. to relay a message
+    /// from another system verbatim. Note that failed deliveries are only published to the dead
+    /// letter exchange if they have a message id, which is used to track their origin.
+    #[must_use]
+    pub fn preserve_properties(mut self, props: BasicProperties) -> Self {
+        self.props = props;
+        self
+    }
+
+    /// Derive the exchange and routing key from the payload at publish time, e.g. to route by
+    /// tenant. Overrides the exchange and routing key the builder was created with.
+    #[must_use]
+    pub fn route_with<P, F>(self, route: F) -> RoutedPublishBuilder<'a, S, P, F>
+    where
+        P: Serialize,
+        F: FnOnce(&P) -> (String, String),
+    {
+        RoutedPublishBuilder {
+            builder: self,
+            route,
+            payload: PhantomData,
+        }
+    }
+
+    /// Publish the payload. The returned [`Published`] carries the message id that was sent and
+    /// can be awaited like the [`PublisherConfirm`] it wraps.
+    ///
     /// # Errors
-    pub async fn publish<P>(self, payload: P) -> Result<PublisherConfirm, PublishError>
+    pub async fn publish<P>(self, payload: P) -> Result<Published, PublishError>
     where
         P: Serialize,
     {
+        let (exchange_name, routing_key) = (self.exchange_name, self.routing_key);
+        self.publish_to(exchange_name, routing_key, payload).await
+    }
+
+    /// Publish the bytes verbatim, without serializing them or wrapping them in an envelope, e.g.
+    /// payloads encoded in another format than JSON or relayed untouched. The properties and
+    /// options are applied as usual, but the `content-type` is only set if configured with
+    /// [`Self::content_type`].
+    ///
+    /// # Errors
+    /// See [`PublishError`].
+    pub async fn publish_bytes<B>(mut self, payload: B) -> Result<Published, PublishError>
+    where
+        B: AsRef<[u8]>,
+    {
+        self.throttle().await;
         let chan = self
             .client
             .get_channel()
             .await
-            .map_err(|err| PublishError(err.into()))?;
-        let payload = message::Payload(payload)
-            .serialize()
-            .map_err(|err| PublishError(err.into()))?;
-        chan.basic_publish(
-            self.exchange_name,
-            self.routing_key,
-            self.opts,
-            &payload,
-            self.props,
-        )
+            .map_err(|err| PublishError::Io(err.into()))?;
+        let (exchange_name, routing_key) = (self.exchange_name, self.routing_key);
+        self.send_on(&chan, exchange_name, routing_key, payload.as_ref())
+            .await
+    }
+
+    /// Publish the payload and wait until the broker confirmed it. Unlike [`Self::publish`], the
+    /// message is sent on a pooled channel in confirm mode, so the broker acknowledges it once it
+    /// took responsibility for it. Messages published with the `mandatory` flag, see
+    /// [`Self::opts`], that could not be routed are returned as [`PublishError::Returned`].
+    /// Returns the message id that was sent.
+    ///
+    /// # Errors
+    /// See [`PublishError`], [`PublishError::Nacked`] if the broker did not accept the message.
+    pub async fn publish_confirmed<P>(mut self, payload: P) -> Result<Option<String>, PublishError>
+    where
+        P: Serialize,
+    {
+        self.throttle().await;
+        let chan = self
+            .client
+            .get_confirm_channel()
+            .await
+            .map_err(|err| PublishError::Io(err.into()))?;
+        let (exchange_name, routing_key) = (self.exchange_name, self.routing_key);
+        let Published {
+            message_id,
+            confirm,
+        } = self
+            .publish_on(&chan, exchange_name, routing_key, payload)
+            .await?;
+        match confirm.await.map_err(|err| PublishError::Io(err.into()))? {
+            Confirmation::Ack(None) | Confirmation::NotRequested => Ok(message_id),
+            Confirmation::Ack(Some(returned)) => Err(PublishError::Returned(Unroutable {
+                reply_code: returned.reply_code,
+                reply_text: returned.reply_text.to_string(),
+                payload: returned.delivery.data,
+            })),
+            Confirmation::Nack(_) => Err(PublishError::Nacked),
+        }
+    }
+
+    /// Publish the payload with the `mandatory` flag set and wait until the broker either routed
+    /// it to at least one queue or returned it as unroutable. Unlike [`Self::publish`], this
+    /// opens a dedicated channel in confirm mode, as returned messages are only reported on such
+    /// channels, so use it where knowing the outcome is worth the extra round-trips.
+    ///
+    /// # Errors
+    /// See [`PublishMandatoryError`].
+    pub async fn publish_mandatory<P>(mut self, payload: P) -> Result<(), PublishMandatoryError>
+    where
+        P: Serialize,
+    {
+        self.opts.mandatory = true;
+        self.throttle().await;
+        let chan = self
+            .client
+            .create_channel()
+            .await
+            .map_err(|err| PublishError::Io(err.into()))?;
+        let confirmation = async {
+            chan.confirm_select(ConfirmSelectOptions::default())
+                .await
+                .map_err(|err| PublishError::Io(err.into()))?;
+            let (exchange_name, routing_key) = (self.exchange_name, self.routing_key);
+            self.publish_on(&chan, exchange_name, routing_key, payload)
+                .await?
+                .confirm
+                .await
+                .map_err(|err| PublishError::Io(err.into()))
+        }
+        .await;
+        if let Err(err) = chan.close(REPLY_SUCCESS, "OK").await {
+            warn!("closing the mandatory publish channel failed: {err}");
+        }
+
+        match confirmation? {
+            Confirmation::Ack(None) | Confirmation::NotRequested => Ok(()),
+            Confirmation::Ack(Some(returned)) | Confirmation::Nack(Some(returned)) => {
+                Err(Unroutable {
+                    reply_code: returned.reply_code,
+                    reply_text: returned.reply_text.to_string(),
+                    payload: returned.delivery.data,
+                }
+                .into())
+            }
+            Confirmation::Nack(None) => Err(PublishError::Nacked.into()),
+        }
+    }
+
+    /// Publish the payload to the given exchange and routing key.
+    async fn publish_to<P>(
+        mut self,
+        exchange_name: &str,
+        routing_key: &str,
+        payload: P,
+    ) -> Result<Published, PublishError>
+    where
+        P: Serialize,
+    {
+        self.throttle().await;
+        let chan = self
+            .client
+            .get_channel()
+            .await
+            .map_err(|err| PublishError::Io(err.into()))?;
+        self.publish_on(&chan, exchange_name, routing_key, payload)
+            .await
+    }
+
+    /// Wait until the [`RateLimiter`] allows the publish, if set. Call it before taking a
+    /// channel, so throttled publishes do not hold channels other publishers are waiting for.
+    pub(super) async fn throttle(&mut self) {
+        if let Some(rate_limiter) = self.rate_limiter.take() {
+            rate_limiter.until_ready().await;
+        }
+    }
+
+    /// Publish the payload to the given exchange and routing key on `chan`.
+    pub(super) async fn publish_on<P>(
+        mut self,
+        chan: &lapin::Channel,
+        exchange_name: &str,
+        routing_key: &str,
+        payload: P,
+    ) -> Result<Published, PublishError>
+    where
+        P: Serialize,
+    {
+        let payload = match self.envelope.take() {
+            Some((kind, version)) => {
+                let kind = kind.unwrap_or_else(|| std::any::type_name::<P>().to_string());
+                let mut headers = self.props.headers().clone().unwrap_or_default();
+                headers.insert(
+                    message::MESSAGE_TYPE_HEADER.into(),
+                    AMQPValue::LongString(kind.as_str().into()),
+                );
+                headers.insert(
+                    message::SCHEMA_VERSION_HEADER.into(),
+                    AMQPValue::LongUInt(version),
+                );
+                self.props = self.props.with_headers(headers);
+                message::Payload(message::Envelope {
+                    kind,
+                    version,
+                    payload,
+                })
+                .serialize()
+            }
+            None => message::Payload(payload).serialize(),
+        }
+        .map_err(|err| PublishError::Io(err.into()))?;
+        if self.props.content_type().is_none() {
+            self.props = self
+                .props
+                .with_content_type(message::JSON_CONTENT_TYPE.into());
+        }
+        self.send_on(chan, exchange_name, routing_key, &payload)
+            .await
+    }
+
+    /// Send the serialized payload to the given exchange and routing key on `chan`.
+    async fn send_on(
+        mut self,
+        chan: &lapin::Channel,
+        exchange_name: &str,
+        routing_key: &str,
+        payload: &[u8],
+    ) -> Result<Published, PublishError> {
+        if self.opts.immediate {
+            warn!(
+                "publishing with the immediate flag, which RabbitMQ does not support, the broker \
+                 will close the channel"
+            );
+        }
+        // A no-op for publishes that were throttled before taking their channel.
+        self.throttle().await;
+        self.client
+            .observer
+            .on_publish(exchange_name, routing_key, &self.props);
+        let message_id = self.props.message_id().as_ref().map(ToString::to_string);
+        let confirm = chan
+            .basic_publish(exchange_name, routing_key, self.opts, payload, self.props)
+            .await
+            .map_err(|err| PublishError::Io(err.into()))?;
+        Ok(Published {
+            message_id,
+            confirm,
+        })
+    }
+}
+
+/// A [`BasicPublishBuilder`] that derives the exchange and routing key from the payload, see
+/// [`BasicPublishBuilder::route_with`].
+pub struct RoutedPublishBuilder<'a, S, P, F>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    /// The underlying builder.
+    builder: BasicPublishBuilder<'a, S>,
+    /// Derives the exchange and routing key from the payload.
+    route: F,
+    /// The payload type the route is derived from.
+    payload: PhantomData<fn(&P)>,
+}
+
+impl<S, P, F> RoutedPublishBuilder<'_, S, P, F>
+where
+    S: Clone + Send + Sync + 'static,
+    P: Serialize,
+    F: FnOnce(&P) -> (String, String),
+{
+    /// Publish the payload to the exchange and routing key derived from it.
+    ///
+    /// # Errors
+    /// See [`PublishError`].
+    pub async fn publish(self, payload: P) -> Result<Published, PublishError> {
+        let (exchange_name, routing_key) = (self.route)(&payload);
+        self.builder
+            .publish_to(&exchange_name, &routing_key, payload)
+            .await
+    }
+}
+
+/// A published message, await it to wait for the [`PublisherConfirm`].
+#[derive(Debug)]
+pub struct Published {
+    /// The message id the message was published with, generated unless it was set explicitly.
+    pub message_id: Option<String>,
+    /// The confirmation of the publish.
+    pub confirm: PublisherConfirm,
+}
+
+impl IntoFuture for Published {
+    type Output = lapin::Result<Confirmation>;
+    type IntoFuture = PublisherConfirm;
+
+    fn into_future(self) -> Self::IntoFuture {
+        self.confirm
+    }
+}
+
+/// Publishes the same payload to several exchanges and routing keys, see
+/// [`Client::publish_to_all`].
+pub(super) async fn publish_to_all<S, P>(
+    client: &Client<S>,
+    targets: &[(&str, &str)],
+    payload: P,
+) -> Result<Vec<Result<PublisherConfirm, PublishError>>, PublishError>
+where
+    S: Clone + Send + Sync + 'static,
+    P: Serialize,
+{
+    let chan = client
+        .get_channel()
         .await
-        .map_err(|err| PublishError(err.into()))
+        .map_err(|err| PublishError::Io(err.into()))?;
+    let payload = message::Payload(payload)
+        .serialize()
+        .map_err(|err| PublishError::Io(err.into()))?;
+    let props = BasicPublishBuilder::default_props(client)
+        .with_content_type(message::JSON_CONTENT_TYPE.into());
+
+    let mut results = Vec::with_capacity(targets.len());
+    for (exchange_name, routing_key) in targets {
+        client
+            .observer
+            .on_publish(exchange_name, routing_key, &props);
+        let result = chan
+            .basic_publish(
+                exchange_name,
+                routing_key,
+                BasicPublishOptions::default(),
+                &payload,
+                props.clone(),
+            )
+            .await
+            .map_err(|err| PublishError::Io(err.into()));
+        results.push(result);
     }
+    Ok(results)
 }
 
 /// Errors that can occur while publishing a message.
 #[derive(Debug, thiserror::Error)]
-#[error(transparent)]
-pub struct PublishError(#[from] anyhow::Error);
+pub enum PublishError {
+    /// The broker did not accept the message, see [`BasicPublishBuilder::publish_confirmed`].
+    #[error("the broker did not accept the message")]
+    Nacked,
+    /// The broker returned the message as unroutable, see
+    /// [`BasicPublishBuilder::publish_confirmed`].
+    #[error(transparent)]
+    Returned(Unroutable),
+    /// The per-message TTL exceeds `u32::MAX` milliseconds, the maximum of `RabbitMQ`, see
+    /// [`BasicPublishBuilder::expiration`].
+    #[error("expiration of {0:?} exceeds u32::MAX milliseconds")]
+    ExpirationTooLong(Duration),
+    /// Serializing the payload, getting a channel or sending the message failed.
+    #[error(transparent)]
+    Io(#[from] anyhow::Error),
+}
+
+/// Errors that can occur while publishing a message with
+/// [`BasicPublishBuilder::publish_mandatory`].
+#[derive(Debug, thiserror::Error)]
+pub enum PublishMandatoryError {
+    /// The message could not be routed to any queue.
+    #[error(transparent)]
+    Unroutable(#[from] Unroutable),
+    /// Publishing the message failed.
+    #[error(transparent)]
+    Publish(#[from] PublishError),
+}
+
+/// A message returned by the broker because it could not be routed to any queue.
+#[derive(Debug, thiserror::Error)]
+#[error("message returned as unroutable ({reply_code}): {reply_text}")]
+pub struct Unroutable {
+    /// The reply code, usually `NO_ROUTE`.
+    pub reply_code: u16,
+    /// The reply text explaining why the message was returned.
+    pub reply_text: String,
+    /// The serialized payload of the returned message.
+    pub payload: Vec<u8>,
+}
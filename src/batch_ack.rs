@@ -0,0 +1,155 @@
+//! [`BatchAck`] implementation, acknowledge deliveries in batches.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use lapin::options::{BasicAckOptions, BasicNackOptions};
+use lapin::types::DeliveryTag;
+use tokio::sync::Mutex;
+
+/// Accumulates acknowledgements and nacks of a consumer and flushes them with `multiple: true`.
+///
+/// Deliveries may complete out of order when they are consumed in parallel, so only the
+/// contiguous prefix of settled delivery tags is ever flushed. A `multiple` acknowledgement or
+/// nack settles every delivery up to and including its tag that is not settled yet, so the
+/// prefix is flushed as one call per run of deliveries settled the same way, e.g. a single nack
+/// for a whole failed batch.
+#[derive(Debug)]
+pub(super) struct BatchAck {
+    /// The consumers channel, delivery tags are scoped to it.
+    chan: lapin::Channel,
+    /// Flush after this many acknowledgements are pending.
+    count: usize,
+    /// Flush at least this often.
+    interval: Duration,
+    /// Bookkeeping of settled deliveries.
+    state: Mutex<BatchAckState>,
+}
+
+/// Bookkeeping of settled deliveries.
+#[derive(Debug, Default)]
+struct BatchAckState {
+    /// Every delivery up to and including this tag is settled.
+    settled_up_to: DeliveryTag,
+    /// Settled deliveries past `settled_up_to`.
+    settled: BTreeMap<DeliveryTag, Settlement>,
+    /// Amount of settlements not yet flushed.
+    pending: usize,
+}
+
+/// How a delivery was settled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Settlement {
+    /// Acknowledged.
+    Ack,
+    /// Nacked, requeued if set.
+    Nack(bool),
+}
+
+impl BatchAck {
+    /// Create a new `BatchAck` for the consumers channel.
+    pub fn new(chan: lapin::Channel, count: usize, interval: Duration) -> Self {
+        Self {
+            chan,
+            count: count.max(1),
+            interval: interval.max(Duration::from_millis(1)),
+            state: Mutex::default(),
+        }
+    }
+
+    /// Create a new `BatchAck` with the same settings for another channel.
+    pub fn with_channel(&self, chan: lapin::Channel) -> Self {
+        Self::new(chan, self.count, self.interval)
+    }
+
+    /// Interval in which pending acknowledgements should be flushed.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Mark a delivery as successfully processed. Flushes once enough settlements are pending.
+    ///
+    /// # Errors
+    /// If flushing the batch fails.
+    pub async fn ack(&self, delivery_tag: DeliveryTag) -> Result<(), lapin::Error> {
+        self.settle(delivery_tag, Settlement::Ack).await
+    }
+
+    /// Mark a delivery as failed, it is requeued if `requeue` is set. Flushes once enough
+    /// settlements are pending.
+    ///
+    /// # Errors
+    /// If flushing the batch fails.
+    pub async fn nack(&self, delivery_tag: DeliveryTag, requeue: bool) -> Result<(), lapin::Error> {
+        self.settle(delivery_tag, Settlement::Nack(requeue)).await
+    }
+
+    /// Record the settlement of a delivery. Flushes once enough settlements are pending.
+    async fn settle(
+        &self,
+        delivery_tag: DeliveryTag,
+        settlement: Settlement,
+    ) -> Result<(), lapin::Error> {
+        let mut state = self.state.lock().await;
+        state.settled.insert(delivery_tag, settlement);
+        state.pending += 1;
+        if state.pending < self.count {
+            return Ok(());
+        }
+        self.flush_locked(&mut state).await
+    }
+
+    /// Settle the contiguous prefix of settled deliveries.
+    ///
+    /// # Errors
+    /// If flushing the batch fails.
+    pub async fn flush(&self) -> Result<(), lapin::Error> {
+        let mut state = self.state.lock().await;
+        self.flush_locked(&mut state).await
+    }
+
+    /// Settle the contiguous prefix of settled deliveries, while holding the lock.
+    async fn flush_locked(&self, state: &mut BatchAckState) -> Result<(), lapin::Error> {
+        let mut run: Option<(DeliveryTag, Settlement)> = None;
+        let mut next = state.settled_up_to + 1;
+        while let Some(settlement) = state.settled.remove(&next) {
+            if let Some((last_tag, run_settlement)) = run.filter(|(_, run)| *run != settlement) {
+                self.send(last_tag, run_settlement).await?;
+            }
+            run = Some((next, settlement));
+            state.settled_up_to = next;
+            state.pending -= 1;
+            next += 1;
+        }
+        match run {
+            Some((last_tag, settlement)) => self.send(last_tag, settlement).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Settle every delivery up to and including the tag that is not settled yet.
+    async fn send(
+        &self,
+        delivery_tag: DeliveryTag,
+        settlement: Settlement,
+    ) -> Result<(), lapin::Error> {
+        match settlement {
+            Settlement::Ack => {
+                self.chan
+                    .basic_ack(delivery_tag, BasicAckOptions { multiple: true })
+                    .await
+            }
+            Settlement::Nack(requeue) => {
+                self.chan
+                    .basic_nack(
+                        delivery_tag,
+                        BasicNackOptions {
+                            multiple: true,
+                            requeue,
+                        },
+                    )
+                    .await
+            }
+        }
+    }
+}
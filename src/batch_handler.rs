@@ -0,0 +1,170 @@
+//! [`BatchHandler`] implementation, handle deliveries in micro-batches.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+
+use crate::basic_consume_builder::{BatchError, ConsumerContext};
+use crate::consumer_extract::Delivery;
+use crate::runtime::Runtime;
+use crate::{Client, ConsumerHandler, ConsumerHandlerError, ConsumerHandlerResult};
+
+/// Collects deliveries into batches and hands them to a batch handler, see
+/// [`crate::basic_consume_builder::BasicConsumeBuilder::consume_batched`].
+///
+/// Every delivery task pushes its delivery and waits for the outcome of its batch, so
+/// acknowledging, nacking and dead-lettering work per delivery, just like for regular handlers.
+pub(super) struct BatchHandler<F>(Arc<BatchHandlerInner<F>>);
+
+impl<F> Clone for BatchHandler<F> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+/// Shared state of a [`BatchHandler`].
+struct BatchHandlerInner<F> {
+    /// The batch handler.
+    handler: F,
+    /// Hand the batch to the handler once it holds this many deliveries.
+    max_size: usize,
+    /// Hand the batch to the handler at the latest this long after its first delivery.
+    max_wait: Duration,
+    /// The batch currently being collected.
+    pending: Mutex<PendingBatch>,
+    /// Spawns the batch tasks and runs the `max_wait` timers.
+    runtime: Arc<dyn Runtime>,
+}
+
+/// Notifies a delivery task about the outcome of its batch.
+type Outcome = oneshot::Sender<Result<(), anyhow::Error>>;
+
+/// A batch currently being collected.
+#[derive(Default)]
+struct PendingBatch {
+    /// Collected deliveries.
+    deliveries: Vec<Delivery>,
+    /// Notify the delivery tasks about the outcome of the batch, in order of `deliveries`.
+    outcomes: Vec<Outcome>,
+    /// Incremented whenever a batch is taken, so stale `max_wait` timers do nothing.
+    generation: u64,
+}
+
+/// Marker for the [`ConsumerHandler`] implementation of [`BatchHandler`].
+pub(super) struct Batched;
+
+impl<F, Fut> BatchHandler<F>
+where
+    F: Fn(Vec<Delivery>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), BatchError>> + Send + 'static,
+{
+    /// Create a new `BatchHandler`.
+    pub fn new(handler: F, max_size: usize, max_wait: Duration, runtime: Arc<dyn Runtime>) -> Self {
+        Self(Arc::new(BatchHandlerInner {
+            handler,
+            max_size: max_size.max(1),
+            max_wait,
+            pending: Mutex::default(),
+            runtime,
+        }))
+    }
+
+    /// Add a delivery to the pending batch and wait for the outcome of its batch.
+    async fn push(self, delivery: Delivery) -> Result<(), anyhow::Error> {
+        let (outcome_sender, outcome_receiver) = oneshot::channel();
+        let full_batch = {
+            let mut pending = self
+                .0
+                .pending
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            pending.deliveries.push(delivery);
+            pending.outcomes.push(outcome_sender);
+            if pending.deliveries.len() == 1 {
+                self.0.runtime.spawn(Box::pin(
+                    self.clone().flush_after_max_wait(pending.generation),
+                ));
+            }
+            (pending.deliveries.len() >= self.0.max_size).then(|| take_batch(&mut pending))
+        };
+        // Handle the batch in its own task, so it is not aborted if this delivery times out.
+        if let Some((deliveries, outcomes)) = full_batch {
+            self.0
+                .runtime
+                .spawn(Box::pin(self.clone().handle(deliveries, outcomes)));
+        }
+        outcome_receiver
+            .await
+            .map_err(|_| anyhow::format_err!("batch was dropped before it was handled"))?
+    }
+
+    /// Hand the batch to the handler after `max_wait`, unless it was taken in the meantime.
+    async fn flush_after_max_wait(self, generation: u64) {
+        self.0.runtime.sleep(self.0.max_wait).await;
+        let batch = {
+            let mut pending = self
+                .0
+                .pending
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            (pending.generation == generation).then(|| take_batch(&mut pending))
+        };
+        if let Some((deliveries, outcomes)) = batch {
+            self.handle(deliveries, outcomes).await;
+        }
+    }
+
+    /// Run the handler and notify the delivery tasks about the outcome.
+    async fn handle(self, deliveries: Vec<Delivery>, outcomes: Vec<Outcome>) {
+        let results: Vec<Result<(), anyhow::Error>> = match (self.0.handler)(deliveries).await {
+            Ok(()) => outcomes.iter().map(|_| Ok(())).collect(),
+            Err(BatchError::All(err)) => outcomes
+                .iter()
+                .map(|_| Err(anyhow::format_err!("batch failed: {err:#}")))
+                .collect(),
+            Err(BatchError::Partial(failures)) => {
+                let mut results: Vec<_> = outcomes.iter().map(|_| Ok(())).collect();
+                for (index, err) in failures {
+                    if let Some(result) = results.get_mut(index) {
+                        *result = Err(err);
+                    }
+                }
+                results
+            }
+        };
+        for (outcome, result) in outcomes.into_iter().zip(results) {
+            // The delivery task is gone if it timed out.
+            std::mem::drop(outcome.send(result));
+        }
+    }
+}
+
+/// Take the pending batch, leaving an empty one behind.
+fn take_batch(pending: &mut PendingBatch) -> (Vec<Delivery>, Vec<Outcome>) {
+    pending.generation += 1;
+    (
+        std::mem::take(&mut pending.deliveries),
+        std::mem::take(&mut pending.outcomes),
+    )
+}
+
+impl<S, F, Fut> ConsumerHandler<S, Batched> for BatchHandler<F>
+where
+    S: Clone + Send + Sync + 'static,
+    F: Fn(Vec<Delivery>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), BatchError>> + Send + 'static,
+{
+    type Value = ();
+    type Future = Pin<Box<dyn Future<Output = ConsumerHandlerResult> + Send>>;
+
+    fn call(self, _: Client<S>, _: Arc<ConsumerContext>, delivery: Delivery) -> Self::Future {
+        Box::pin(async move {
+            self.push(delivery)
+                .await
+                .map_err(ConsumerHandlerError::Other)
+        })
+    }
+}
@@ -1,25 +1,60 @@
 //! [`ChannelPool`] definition and implementation.
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
 
 use async_trait::async_trait;
-use deadpool::managed;
+use deadpool::managed::{self, Hook, HookError, HookErrorCause, Metrics};
+use futures::FutureExt;
+use lapin::message::BasicReturnMessage;
+use lapin::options::ConfirmSelectOptions;
+use lapin::protocol::constants::REPLY_SUCCESS;
+use tracing::{debug, warn};
+
+use crate::reconnect::Connector;
 
 /// Wrap [`managed::Pool`] for channels and add convenience functions
 /// to interface with it.
 #[derive(Debug, Clone)]
-pub(super) struct ChannelPool(managed::Pool<ChannelManager>);
+pub(super) struct ChannelPool {
+    /// Channels without publisher confirms.
+    inner: managed::Pool<ChannelManager>,
+    /// Channels in confirm mode, kept apart so confirm mode does not leak to other publishes.
+    confirm: managed::Pool<ChannelManager>,
+}
 
 impl ChannelPool {
     /// Create a new pool.
     ///
     /// # Errors
     /// See [`NewError`]
-    pub fn new(conn: Arc<lapin::Connection>) -> Result<Self, NewError> {
-        let inner = managed::Pool::builder(ChannelManager(conn))
+    pub fn new(conn: Arc<Connector>) -> Result<Self, NewError> {
+        let manager = ChannelManager {
+            conn,
+            on_return: Arc::default(),
+            recycling: Arc::default(),
+            confirm: false,
+        };
+        let confirm_manager = ChannelManager {
+            confirm: true,
+            ..manager.clone()
+        };
+        Ok(Self {
+            inner: Self::build(manager)?,
+            confirm: Self::build(confirm_manager)?,
+        })
+    }
+
+    /// Build a pool of the channels created by the manager.
+    fn build(manager: ChannelManager) -> Result<managed::Pool<ChannelManager>, NewError> {
+        let retiring = manager.clone();
+        managed::Pool::builder(manager)
+            .pre_recycle(Hook::async_fn(move |chan, metrics| {
+                let retiring = retiring.clone();
+                async move { retiring.retire(chan, metrics).await }.boxed()
+            }))
             .build()
-            .map_err(|err| NewError::Other(err.into()))?;
-        Ok(Self(inner))
+            .map_err(|err| NewError::Other(err.into()))
     }
 
     /// Get a [`Channel`].
@@ -28,12 +63,102 @@ impl ChannelPool {
     /// See [`GetError`]
     pub async fn get(&self) -> Result<Channel, GetError> {
         let inner = self
-            .0
+            .inner
+            .get()
+            .await
+            .map_err(|err| GetError::Other(err.into()))?;
+        Ok(Channel(inner))
+    }
+
+    /// Get a [`Channel`] in confirm mode.
+    ///
+    /// # Errors
+    /// See [`GetError`]
+    pub async fn get_confirm(&self) -> Result<Channel, GetError> {
+        let inner = self
+            .confirm
             .get()
             .await
             .map_err(|err| GetError::Other(err.into()))?;
         Ok(Channel(inner))
     }
+
+    /// Register the callback invoked for returned messages, replacing the previous one.
+    pub(super) fn on_return(&self, on_return: Arc<ReturnFn>) {
+        *self
+            .manager()
+            .on_return
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(ReturnHandler(on_return));
+    }
+
+    /// Set the maximum number of channels of the pool, and of the pool of channels in confirm
+    /// mode.
+    pub(super) fn set_max_size(&self, max_size: usize) {
+        self.inner.resize(max_size);
+        self.confirm.resize(max_size);
+    }
+
+    /// Set the [`ChannelRecycling`] policy. It is enforced in the `pre_recycle` hook, when an idle
+    /// channel is taken out of the pool again, not when it is returned.
+    pub(super) fn set_recycling(&self, recycling: ChannelRecycling) {
+        *self
+            .manager()
+            .recycling
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = recycling;
+    }
+}
+
+/// Policy deciding when pooled channels are closed and replaced by fresh ones, instead of being
+/// reused, see [`crate::Client::with_channel_recycling`]. Rotating channels keeps state one user
+/// left on a channel, e.g. enabled publisher confirms or a changed `QoS`, from leaking to the
+/// next one. The policy is checked when an idle channel is taken out of the pool, a channel due
+/// to be retired is closed then and replaced by a fresh one. By default channels are reused as
+/// long as they are connected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelRecycling {
+    /// Retire channels older than this.
+    max_age: Option<Duration>,
+    /// Retire channels after they were handed out this many times.
+    max_uses: Option<usize>,
+}
+
+impl ChannelRecycling {
+    /// Reuse channels as long as they are connected.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Never reuse a channel, a returned channel is closed instead of being handed out again.
+    /// Trades a round-trip to the broker per channel for full isolation.
+    #[must_use]
+    pub fn always_fresh() -> Self {
+        Self::new().max_uses(1)
+    }
+
+    /// Not set by default. Close channels once they are older than `max_age`.
+    #[must_use]
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Not set by default. Close channels once they were handed out `max_uses` times.
+    #[must_use]
+    pub fn max_uses(mut self, max_uses: usize) -> Self {
+        self.max_uses = Some(max_uses);
+        self
+    }
+
+    /// Whether a channel with the metrics is due to be retired.
+    fn is_due(&self, metrics: &Metrics) -> bool {
+        self.max_age.is_some_and(|max_age| metrics.age() >= max_age)
+            || self
+                .max_uses
+                .is_some_and(|max_uses| metrics.recycle_count + 1 >= max_uses)
+    }
 }
 
 /// Errors that can occur when creating a new pool.
@@ -56,13 +181,80 @@ impl Deref for ChannelPool {
     type Target = managed::Pool<ChannelManager>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.inner
+    }
+}
+
+/// Callback invoked for messages the broker returned, see [`crate::Client::on_return`].
+pub(super) type ReturnFn = dyn Fn(&BasicReturnMessage) + Send + Sync;
+
+/// Invokes the callback for returned messages, see [`crate::Client::on_return`].
+#[derive(Clone)]
+struct ReturnHandler(
+    /// The callback.
+    Arc<ReturnFn>,
+);
+
+impl std::fmt::Debug for ReturnHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ReturnHandler")
     }
 }
 
 /// Wrap [`lapin::Connection`] to implement [`managed::Manager`] for it.
 #[derive(Debug, Clone)]
-pub struct ChannelManager(Arc<lapin::Connection>);
+pub struct ChannelManager {
+    /// Holds the connection to create channels on.
+    conn: Arc<Connector>,
+    /// Invoked for messages returned on pooled channels.
+    on_return: Arc<RwLock<Option<ReturnHandler>>>,
+    /// When to retire pooled channels.
+    recycling: Arc<RwLock<ChannelRecycling>>,
+    /// Whether the channels are in confirm mode.
+    confirm: bool,
+}
+
+impl ChannelManager {
+    /// Hand messages the broker returned on the channel to the return callback, if registered.
+    async fn drain_returned(&self, chan: &lapin::Channel) {
+        let on_return = self
+            .on_return
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let Some(ReturnHandler(on_return)) = on_return else {
+            return;
+        };
+        match chan.wait_for_confirms().await {
+            Ok(returned) => returned.iter().for_each(|message| on_return(message)),
+            Err(err) => warn!("collecting returned messages failed: {err}"),
+        }
+    }
+
+    /// Close the channel if the [`ChannelRecycling`] policy retires it, discarding it from the
+    /// pool.
+    async fn retire(
+        &self,
+        chan: &mut lapin::Channel,
+        metrics: &Metrics,
+    ) -> Result<(), HookError<lapin::Error>> {
+        let recycling = *self
+            .recycling
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !recycling.is_due(metrics) {
+            return Ok(());
+        }
+        debug!(channel_id = chan.id(), "retiring pooled channel");
+        self.drain_returned(chan).await;
+        if let Err(err) = chan.close(REPLY_SUCCESS, "OK").await {
+            warn!("closing retired channel failed: {err}");
+        }
+        Err(HookError::Continue(Some(HookErrorCause::StaticMessage(
+            "channel retired",
+        ))))
+    }
+}
 
 #[async_trait]
 impl managed::Manager for ChannelManager {
@@ -70,20 +262,34 @@ impl managed::Manager for ChannelManager {
     type Error = lapin::Error;
 
     async fn create(&self) -> Result<Self::Type, Self::Error> {
-        self.0.create_channel().await
+        let chan = self.conn.current().create_channel().await?;
+        if self.confirm {
+            chan.confirm_select(ConfirmSelectOptions::default()).await?;
+        }
+        Ok(chan)
     }
 
     async fn recycle(
         &self,
         chan: &mut lapin::Channel,
     ) -> Result<(), managed::RecycleError<Self::Error>> {
-        if let lapin::ChannelState::Connected = chan.status().state() {
-            Ok(())
-        } else {
-            Err(managed::RecycleError::StaticMessage(
+        if !matches!(chan.status().state(), lapin::ChannelState::Connected) {
+            return Err(managed::RecycleError::StaticMessage(
                 "channel is not connected",
-            ))
+            ));
+        }
+        self.drain_returned(chan).await;
+        // Confirm mode can not be turned off again, it would change the behavior of publishes
+        // of the next user, so replace the channel with a fresh one.
+        if chan.status().confirm() && !self.confirm {
+            if let Err(err) = chan.close(REPLY_SUCCESS, "OK").await {
+                warn!("closing channel in confirm mode failed: {err}");
+            }
+            return Err(managed::RecycleError::StaticMessage(
+                "channel is in confirm mode",
+            ));
         }
+        Ok(())
     }
 }
 
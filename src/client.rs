@@ -1,18 +1,42 @@
 //! Main [`Client`] implementation.
-use std::ops::Deref;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
-use lapin::{Connection, ConnectionProperties, ExchangeKind};
-use tokio::sync::Mutex;
+use lapin::message::BasicReturnMessage;
+use lapin::options::ExchangeDeclareOptions;
+use lapin::publisher_confirm::PublisherConfirm;
+use lapin::types::{FieldTable, MessageCount};
+use lapin::uri::{AMQPUri, SASLMechanism};
+use lapin::{Connection, ConnectionProperties, ConnectionStatus, ExchangeKind};
+use serde::Serialize;
+use tokio::sync::{broadcast, Mutex, Semaphore};
 use tokio::task::JoinSet;
 use tracing::{error, info};
 
 use crate::prelude::*;
-use crate::basic_consume_builder::ConsumerTaskHandlerError;
+use crate::basic_consume_builder::{
+    ConsumeDefaults, ConsumeError, ConsumerTaskHandlerError, ConsumerTaskReport,
+};
+use crate::basic_publish_builder::{self, PublishError, Published};
+use crate::channel_pool::{self, ChannelRecycling};
+use crate::consumer_extract::Delivery;
+use crate::health::{self, ConnectionState, Health};
+use crate::observer::{NoopObserver, Observer};
+use crate::outbox::{self, OutboxError, OutboxStore};
+use crate::prioritized_consumer::{self, Fairness};
+use crate::queue_migration::{self, MigrateQueueError, MoveMessagesError};
+use crate::reconnect::{Connector, ReconnectError, ReconnectPolicy};
+use crate::runtime::{self, Runtime};
+use crate::tls::TlsConfig;
+use crate::topology::{DeclareTopologyError, TeardownError};
 use crate::{
-    BasicConsumeBuilder, BasicPublishBuilder, Channel, ChannelPool, ExchangeDeclareBuilder, Queue,
-    QueueBindBuilder, QueueDeclareBuilder, QueueDeleteBuilder, QueueName, QueuePurgeBuilder,
-    QueueUnbindBuilder, RpcBuilder,
+    dlx, message, util, BasicConsumeBuilder, BasicPublishBuilder, Channel, ChannelPool,
+    ConsumerHandler, ExchangeDeclareBuilder, Publisher, Queue, QueueBindBuilder,
+    QueueDeclareBuilder, QueueDeleteBuilder, QueueName, QueuePurgeBuilder, QueueUnbindBuilder,
+    ReconnectingPublisher, RpcBuilder, Topology,
 };
 
 /// Use this client to interface with the `RabbitMq`. This
@@ -23,16 +47,36 @@ pub struct Client<S>
 where
     S: Clone + Send + Sync + 'static,
 {
-    /// `AMQP` connection.
-    conn: Arc<Connection>,
+    /// `AMQP` connection, replaced once it failed if reconnecting is enabled.
+    pub(super) connector: Arc<Connector>,
     /// Channel Pool.
     chan_pool: ChannelPool,
     /// `AMQP` Consumers.
-    pub(super) consumer_set: Arc<Mutex<JoinSet<Result<(), ConsumerTaskHandlerError>>>>,
+    pub(super) consumer_set:
+        Arc<Mutex<JoinSet<Result<ConsumerTaskReport, ConsumerTaskHandlerError>>>>,
     /// App ID for naming connections etc.
     pub(super) app_id: Arc<String>,
+    /// Username the connection was authenticated with, if given in the uri, see
+    /// [`explicit_username`].
+    pub(super) username: Arc<Option<String>>,
     /// State to share with consumers.
     pub(super) state: Arc<S>,
+    /// Telemetry hooks.
+    pub(super) observer: Arc<dyn Observer>,
+    /// Spawns tasks and provides timers.
+    pub(super) runtime: Arc<dyn Runtime>,
+    /// Generates the ids of messages published without an explicit id.
+    pub(super) message_id_generator: message::IdGenerator,
+    /// Defaults of consumers created by the client.
+    pub(super) consume_defaults: ConsumeDefaults,
+    /// Number of consumers recovering from channel failures.
+    pub(super) recovering: Arc<AtomicUsize>,
+    /// Encoding of error data published to the DLX.
+    pub(super) error_data_encoding: dlx::ErrorDataEncoding,
+    /// Limits the number of concurrent in-flight RPC calls, if set.
+    pub(super) rpc_permits: Option<Arc<Semaphore>>,
+    /// Fail getting a pooled channel after waiting this long, if set.
+    pub(super) chan_pool_timeout: Option<Duration>,
 }
 
 impl<S> Client<S>
@@ -42,7 +86,8 @@ where
     /// Creates a new `RabbitMQ` client that connects to a `RabbitMQ` server via the provided
     /// connection string. It will hold the connection internally and create a pool of channels for
     /// you to use. You can temporarily get a pooled channel with the [`Self.get_channel()`]
-    /// method.
+    /// method. Declares the DLX exchange [`dlx::EXCHANGE_NAME`], so dead-lettering works out of
+    /// the box, use [`Self::new_without_dlx`] if the topology is managed externally.
     ///
     /// # Errors
     ///
@@ -57,58 +102,431 @@ where
         props: ConnectionProperties,
         app_id: &str,
         state: S,
+    ) -> Result<Self, NewError> {
+        Self::connect(uri.as_ref(), props, app_id, state, true, None).await
+    }
+
+    /// Creates a new `RabbitMQ` client like [`Self::new`], connecting over TLS with the
+    /// [`TlsConfig`] instead of the TLS defaults of lapin. The uri has to use the `amqps://`
+    /// scheme. The TLS settings are kept for reconnects, see [`Self::with_reconnect`].
+    ///
+    /// # Errors
+    ///
+    /// This function may return an error due to one of the following reasons:
+    ///
+    /// - A certificate or key of the [`TlsConfig`] can not be read, or no root certificates are
+    ///   found
+    /// - The uri does not use the `amqps://` scheme
+    /// - A connection to the `RabbitMQ` server can not be established
+    /// - The channel pool can not be created
+    /// - A channel from the channel pool can not be obtained
+    /// - The DLX exchange can not be declared
+    pub async fn new_with_tls(
+        uri: impl AsRef<str>,
+        props: ConnectionProperties,
+        app_id: &str,
+        state: S,
+        tls: &TlsConfig,
+    ) -> Result<Self, NewError> {
+        Self::connect(uri.as_ref(), props, app_id, state, true, Some(tls)).await
+    }
+
+    /// Creates a new `RabbitMQ` client like [`Self::new`], without declaring the DLX exchange,
+    /// for setups that manage their topology externally.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an error due to one of the following reasons:
+    ///
+    /// - A connection to the `RabbitMQ` server can not be established
+    /// - The channel pool can not be created
+    pub async fn new_without_dlx(
+        uri: impl AsRef<str>,
+        props: ConnectionProperties,
+        app_id: &str,
+        state: S,
+    ) -> Result<Self, NewError> {
+        Self::connect(uri.as_ref(), props, app_id, state, false, None).await
+    }
+
+    /// Connects to the `RabbitMQ` server, see [`Self::new`].
+    async fn connect(
+        uri: &str,
+        props: ConnectionProperties,
+        app_id: &str,
+        state: S,
+        declare_dlx: bool,
+        tls: Option<&TlsConfig>,
     ) -> Result<Self, NewError> {
         /// Prefix errors regarding the creation.
         const ERR_TRACE_PREFIX: &str = "RabbitMQ client failed";
 
-        let conn = Arc::new(
-            Connection::connect(uri.as_ref(), props.with_connection_name(app_id.into()))
+        let tls = tls
+            .map(TlsConfig::connector)
+            .transpose()
+            .map_err(|err| NewError::Tls(err.into()))
+            .on_err(|err| error!("{ERR_TRACE_PREFIX}: {err}"))?;
+        let connector = Arc::new(
+            Connector::connect(uri, props.with_connection_name(app_id.into()), tls)
                 .await
                 .map_err(|err| NewError::Connection(err.into()))
                 .on_err(|err| error!("{ERR_TRACE_PREFIX}: {err}"))?,
         );
 
-        let chan_pool = ChannelPool::new(conn.clone())
+        let chan_pool = ChannelPool::new(connector.clone())
             .map_err(|err| NewError::ChannelPool(err.into()))
             .on_err(|err| error!("{ERR_TRACE_PREFIX}: {err}"))?;
 
+        if declare_dlx {
+            let chan = chan_pool
+                .get()
+                .await
+                .map_err(|err| NewError::DlxExchangeDeclare(err.into()))
+                .on_err(|err| error!("{ERR_TRACE_PREFIX}: {err}"))?;
+            dlx::declare_exchange(&chan)
+                .await
+                .map_err(|err| NewError::DlxExchangeDeclare(err.into()))
+                .on_err(|err| error!("{ERR_TRACE_PREFIX}: {err}"))?;
+        }
+
+        let username = explicit_username(uri);
+
         info!(
             "RabbitMQ client started: connected to {}",
-            uri.as_ref().split('@').last().unwrap_or_default()
+            uri.split('@').next_back().unwrap_or_default()
         );
         Ok(Self {
-            conn,
+            connector,
             chan_pool,
             consumer_set: Arc::new(Mutex::new(JoinSet::new())),
             app_id: Arc::new(app_id.to_string()),
+            username: Arc::new(username),
             state: Arc::new(state),
+            observer: Arc::new(NoopObserver),
+            runtime: Arc::new(runtime::Tokio),
+            message_id_generator: message::new_v4_id,
+            consume_defaults: ConsumeDefaults::default(),
+            recovering: Arc::default(),
+            error_data_encoding: dlx::ErrorDataEncoding::Json,
+            rpc_permits: None,
+            chan_pool_timeout: None,
         })
     }
 
+    /// Sets the generator for the ids of messages published without an explicit id, defaults to
+    /// [`message::new_v4_id`]. Use [`message::new_v7_id`] for time-ordered ids.
+    #[must_use]
+    pub fn with_message_id_generator(mut self, message_id_generator: message::IdGenerator) -> Self {
+        self.message_id_generator = message_id_generator;
+        self
+    }
+
+    /// Sets the [`ChannelRecycling`] policy of the channel pool, defaults to reusing channels as
+    /// long as they are connected. The policy is enforced in the `pre_recycle` hook of the pool,
+    /// when an idle channel is taken out of the pool, so a retired channel stays open in the pool
+    /// until it would be handed out next. Unlike most settings, the pool is shared with all
+    /// clones of the client, so this also applies to clones created before.
+    #[must_use]
+    pub fn with_channel_recycling(self, recycling: ChannelRecycling) -> Self {
+        self.chan_pool.set_recycling(recycling);
+        self
+    }
+
+    /// Sets the maximum number of pooled channels, defaults to four times the number of CPUs.
+    /// Publishes wait for a channel once all of them are in use, raise it on busy services to
+    /// increase the publish throughput. The channels in confirm mode, see
+    /// [`BasicPublishBuilder::publish_confirmed`], are pooled separately with the same size.
+    /// Unlike most settings, the pools are shared with all clones of the client, so this also
+    /// resizes them for clones created before.
+    #[must_use]
+    pub fn with_channel_pool_size(self, max_size: usize) -> Self {
+        self.chan_pool.set_max_size(max_size);
+        self
+    }
+
+    /// Not set by default, getting a pooled channel waits until one is available. Fails
+    /// [`Self::get_channel`] with [`GetChannelError::Timeout`] after waiting `timeout`, e.g. to
+    /// shed load instead of queueing publishes once the pool is exhausted.
+    #[must_use]
+    pub fn with_channel_pool_timeout(mut self, timeout: Duration) -> Self {
+        self.chan_pool_timeout = Some(timeout);
+        self
+    }
+
+    /// Not set by default, once the connection failed [`Self::run`] fails with
+    /// [`JoinBasicConsumersError::Connection`]. Reconnects to the broker following the
+    /// [`ReconnectPolicy`] instead, with the uri and properties the client was created with.
+    /// Pooled channels of the failed connection are discarded, consumers recover on the new
+    /// connection within their [`BasicConsumeBuilder::recovery`] attempts. Deliveries that were
+    /// in-flight are redelivered by the broker. The connection is shared with all clones of the
+    /// client, so the policy also applies to clones created before.
+    #[must_use]
+    pub fn with_reconnect(self, policy: ReconnectPolicy) -> Self {
+        self.connector.set_policy(policy);
+        self
+    }
+
+    /// Sets the [`ConsumeDefaults`] every [`BasicConsumeBuilder`] created afterwards starts from.
+    #[must_use]
+    pub fn with_consume_defaults(mut self, consume_defaults: ConsumeDefaults) -> Self {
+        self.consume_defaults = consume_defaults;
+        self
+    }
+
+    /// Sets the encoding of the error data consumers publish to the DLX, defaults to
+    /// [`dlx::ErrorDataEncoding::Json`]. Binary encodings reduce the bandwidth of high-volume
+    /// error streams, the `dlx handler` needs to support them.
+    #[must_use]
+    pub fn with_error_data_encoding(mut self, encoding: dlx::ErrorDataEncoding) -> Self {
+        self.error_data_encoding = encoding;
+        self
+    }
+
+    /// Not set by default. Limits the number of concurrent in-flight RPC calls, each of which
+    /// declares its own response queue. Excess calls wait for a running call to finish, at most
+    /// for their timeout, before failing with
+    /// [`CallError::TooManyInFlight`](crate::rpc_builder::CallError::TooManyInFlight). Protects
+    /// the broker from a runaway caller declaring thousands of queues.
+    #[must_use]
+    pub fn with_max_in_flight_rpcs(mut self, max_in_flight_rpcs: usize) -> Self {
+        self.rpc_permits = Some(Arc::new(Semaphore::new(max_in_flight_rpcs)));
+        self
+    }
+
+    /// Registers the [`Runtime`] to spawn tasks and run timers on, defaults to
+    /// [`runtime::Tokio`]. Register it before creating consumers, as they hold on to the runtime
+    /// they were created with.
+    #[must_use]
+    pub fn with_runtime<R>(mut self, runtime: R) -> Self
+    where
+        R: Runtime,
+    {
+        self.runtime = Arc::new(runtime);
+        self
+    }
+
+    /// Registers an [`Observer`] whose hooks get invoked when publishing and consuming. Register
+    /// it before creating consumers, as they hold on to the observer they were created with.
+    #[must_use]
+    pub fn with_observer<O>(mut self, observer: O) -> Self
+    where
+        O: Observer,
+    {
+        self.observer = Arc::new(observer);
+        self
+    }
+
+    /// The current connection. Once it failed and the client reconnected, see
+    /// [`Self::with_reconnect`], the new connection is returned. Use it for the methods of
+    /// [`Connection`] the client used to dereference to, e.g. `client.connection().close(..)`
+    /// instead of `client.close(..)`.
+    #[must_use]
+    pub fn connection(&self) -> Arc<Connection> {
+        self.connector.current()
+    }
+
+    /// The status of the current connection.
+    #[must_use]
+    pub fn status(&self) -> ConnectionStatus {
+        self.connection().status().clone()
+    }
+
+    /// Creates a new channel outside of the pool, on the current connection. Reconnects first
+    /// if the connection failed and reconnecting is enabled, see [`Self::with_reconnect`].
+    ///
     /// # Errors
-    pub async fn get_channel(&self) -> Result<Channel, GetChannelError> {
-        self.chan_pool
-            .get()
+    /// See [`GetChannelError`].
+    pub async fn create_channel(&self) -> Result<lapin::Channel, GetChannelError> {
+        self.connector
+            .connected(&*self.runtime)
+            .await
+            .map_err(|err| GetChannelError::Other(err.into()))?
+            .create_channel()
             .await
             .map_err(|err| GetChannelError::Other(err.into()))
     }
 
+    /// Takes a channel from the pool, it is returned to the pool once dropped. Channels put into
+    /// confirm mode with [`lapin::Channel::confirm_select`] are not reused, as confirm mode can
+    /// not be turned off again.
+    ///
+    /// # Errors
+    /// See [`GetChannelError`], [`GetChannelError::Timeout`] if a timeout is set, see
+    /// [`Self::with_channel_pool_timeout`], and no channel became available in time.
+    pub async fn get_channel(&self) -> Result<Channel, GetChannelError> {
+        self.get_pooled(self.chan_pool.get()).await
+    }
+
+    /// Takes a channel in confirm mode from the pool, like [`Self::get_channel`] does.
+    ///
+    /// # Errors
+    /// See [`Self::get_channel`].
+    pub(super) async fn get_confirm_channel(&self) -> Result<Channel, GetChannelError> {
+        self.get_pooled(self.chan_pool.get_confirm()).await
+    }
+
+    /// Reconnect if needed, then take a channel from the pool with `get`, applying the pool
+    /// timeout.
+    ///
+    /// # Errors
+    /// See [`Self::get_channel`].
+    async fn get_pooled<F>(&self, get: F) -> Result<Channel, GetChannelError>
+    where
+        F: Future<Output = Result<Channel, channel_pool::GetError>>,
+    {
+        self.connector
+            .connected(&*self.runtime)
+            .await
+            .map_err(|err| GetChannelError::Other(err.into()))?;
+        let chan = match self.chan_pool_timeout {
+            Some(timeout) => runtime::timeout(&*self.runtime, timeout, get)
+                .await
+                .ok_or(GetChannelError::Timeout(timeout))?,
+            None => get.await,
+        };
+        chan.map_err(|err| GetChannelError::Other(err.into()))
+    }
+
+    /// Registers a callback invoked for every message the broker returned as unroutable, i.e.
+    /// published with the `mandatory` flag but not routed to any queue, across all publishes on
+    /// pooled channels. Replaces a previously registered callback. Use it to detect dead ends
+    /// centrally, e.g. for monitoring.
+    ///
+    /// Returns arrive asynchronously and are collected whenever the channel that received them
+    /// is taken from the pool again, so the callback may run well after the publish. Messages
+    /// published with [`BasicPublishBuilder::publish_mandatory`] use a dedicated channel and are
+    /// reported as [`Unroutable`](basic_publish_builder::Unroutable) to the publisher only,
+    /// never to this callback.
+    pub fn on_return<F>(&self, on_return: F)
+    where
+        F: Fn(&BasicReturnMessage) + Send + Sync + 'static,
+    {
+        self.chan_pool.on_return(Arc::new(on_return));
+    }
+
+    /// Leases one pooled channel for the duration of `f`, so a sequence of operations, e.g.
+    /// declaring, binding and publishing, runs on the same channel. The channel is returned to
+    /// the pool once `f` completes and drops it.
+    ///
+    /// # Errors
+    /// If no channel can be obtained, see [`GetChannelError`].
+    pub async fn channel_with<F, Fut, T>(&self, f: F) -> Result<T, GetChannelError>
+    where
+        F: FnOnce(Channel) -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let chan = self.get_channel().await?;
+        Ok(f(chan).await)
+    }
+
+    /// Checks whether the broker is responsive, e.g. for readiness probes. Obtains a channel and
+    /// passively declares a well-known exchange, which is a cheap round-trip to the broker.
+    ///
+    /// # Errors
+    /// See [`PingError`].
+    pub async fn ping(&self, timeout: Duration) -> Result<(), PingError> {
+        /// Exchange every `RabbitMQ` server declares by default.
+        const PING_EXCHANGE_NAME: &str = "amq.direct";
+
+        let ping = async {
+            let chan = self
+                .get_channel()
+                .await
+                .map_err(|err| PingError::Channel(err.into()))?;
+            chan.exchange_declare(
+                PING_EXCHANGE_NAME,
+                ExchangeKind::Direct,
+                ExchangeDeclareOptions {
+                    passive: true,
+                    ..ExchangeDeclareOptions::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .map_err(|err| PingError::Broker(err.into()))
+        };
+        runtime::timeout(&*self.runtime, timeout, ping)
+            .await
+            .ok_or(PingError::Timeout(timeout))?
+    }
+
+    /// The current [`ConnectionState`], e.g. for readiness probes. A cheap synchronous read,
+    /// unlike [`Self::ping`] it does not contact the broker.
+    #[must_use]
+    pub fn connection_state(&self) -> ConnectionState {
+        ConnectionState::new(
+            &self.status().state(),
+            self.recovering.load(Ordering::SeqCst),
+        )
+    }
+
+    /// The heartbeat interval negotiated with the broker, `None` if heartbeats are disabled.
+    #[must_use]
+    pub fn heartbeat_interval(&self) -> Option<Duration> {
+        match self.connection().configuration().heartbeat() {
+            0 => None,
+            heartbeat => Some(Duration::from_secs(heartbeat.into())),
+        }
+    }
+
+    /// Pings the broker every `interval` in the background and reports the [`Health`] of the
+    /// connection to `on_health`, e.g. to get an early warning of a degrading connection before
+    /// it fails. `lapin` does not expose missed heartbeats, use [`Self::heartbeat_interval`] as
+    /// the interval to approximate them. Reports [`Health::Closed`] while the connection is
+    /// closed, and stops once it is closed unless the client reconnects, see
+    /// [`Self::with_reconnect`].
+    pub fn watch_health<F>(&self, interval: Duration, on_health: F)
+    where
+        F: Fn(Health) + Send + Sync + 'static,
+    {
+        self.runtime
+            .spawn(Box::pin(health::watch(self.clone(), interval, on_health)));
+    }
+
     /// Creates an [`ExchangeDeclareBuilder`] to declare a new exchange.
     #[must_use]
     pub fn exchange_declare_builder<'a>(
         &'a self,
         name: &'a str,
         kind: ExchangeKind,
-    ) -> ExchangeDeclareBuilder<S> {
+    ) -> ExchangeDeclareBuilder<'a, S> {
         ExchangeDeclareBuilder::new(self, name, kind)
     }
 
     /// Creates a [`QueueDeclareBuilder`] to declare a new queue.
     #[must_use]
-    pub fn queue_declare_builder<'a>(&'a self, name: &'a str) -> QueueDeclareBuilder<S> {
+    pub fn queue_declare_builder<'a>(&'a self, name: &'a str) -> QueueDeclareBuilder<'a, S> {
         QueueDeclareBuilder::new(self, name)
     }
 
+    /// Declares the queue configured by `configure`, migrating it if it exists with different
+    /// arguments, e.g. after toggling dead lettering, which the broker refuses with a
+    /// precondition-failed error. A migration moves all messages to a temporary queue named
+    /// with the [`queue_migration::MIGRATION_QUEUE_SUFFIX`], deletes the queue, declares it with
+    /// the new arguments and moves the messages back. Messages are acknowledged only after the
+    /// broker confirmed their copy, a failed migration leaves them in one of both queues and can
+    /// be retried.
+    ///
+    /// Deleting the queue also deletes its bindings, which need to be declared again. Messages
+    /// published to the queue while it is deleted are lost, so stop the publishers first.
+    /// Requires `delete_confirmed` to acknowledge these risks. Queues with consumers are not
+    /// migrated, as deleting them would cancel the consumers, stop them first, including those
+    /// of other services.
+    ///
+    /// # Errors
+    /// See [`MigrateQueueError`].
+    pub async fn migrate_queue<F>(
+        &self,
+        name: &str,
+        configure: F,
+        delete_confirmed: bool,
+    ) -> Result<Queue, MigrateQueueError>
+    where
+        F: for<'b> Fn(QueueDeclareBuilder<'b, S>) -> QueueDeclareBuilder<'b, S>,
+    {
+        queue_migration::migrate(self, name, configure, delete_confirmed).await
+    }
+
     /// Creates a [`QueueBindBuilder`] to bind an existing queue to an exchange.
     #[must_use]
     pub fn queue_bind_builder<'a, N>(
@@ -116,7 +534,7 @@ where
         name: &'a N,
         exchange_name: &'a str,
         routing_key: &'a str,
-    ) -> QueueBindBuilder<S>
+    ) -> QueueBindBuilder<'a, S>
     where
         N: QueueName,
     {
@@ -130,7 +548,7 @@ where
         name: &'a N,
         exchange_name: &'a str,
         routing_key: &'a str,
-    ) -> QueueUnbindBuilder<S>
+    ) -> QueueUnbindBuilder<'a, S>
     where
         N: QueueName,
     {
@@ -139,7 +557,7 @@ where
 
     /// Creates a [`QueueDeleteBuilder`] to delete an existing queue.
     #[must_use]
-    pub fn queue_delete_builder<'a, N>(&'a self, name: &'a N) -> QueueDeleteBuilder<S>
+    pub fn queue_delete_builder<'a, N>(&'a self, name: &'a N) -> QueueDeleteBuilder<'a, S>
     where
         N: QueueName,
     {
@@ -148,89 +566,395 @@ where
 
     /// Creates a [`QueuePurgeBuilder`] to purge an existing queue.
     #[must_use]
-    pub fn queue_purge_builder<'a, N>(&'a self, name: &'a N) -> QueuePurgeBuilder<S>
+    pub fn queue_purge_builder<'a, N>(&'a self, name: &'a N) -> QueuePurgeBuilder<'a, S>
     where
         N: QueueName,
     {
         QueuePurgeBuilder::new(self, name)
     }
 
+    /// Creates a [`ReconnectingPublisher`], retrying publishes across channel failures.
+    #[must_use]
+    pub fn reconnecting_publisher(&self) -> ReconnectingPublisher<S> {
+        ReconnectingPublisher::new(self.clone())
+    }
+
+    /// Creates a [`Publisher`] publishing on a dedicated channel, preserving the order of its
+    /// publishes, see [`Publisher`].
+    ///
+    /// # Errors
+    /// If the channel can not be opened.
+    pub async fn dedicated_publisher(&self) -> Result<Publisher<S>, PublishError> {
+        Publisher::new(self.clone()).await
+    }
+
     /// Creates a [`BasicPublishBuilder`] to publish a message to an exchange.
     #[must_use]
     pub fn basic_publish_builder<'a>(
         &'a self,
         exchange_name: &'a str,
         routing_key: &'a str,
-    ) -> BasicPublishBuilder<S> {
+    ) -> BasicPublishBuilder<'a, S> {
         BasicPublishBuilder::new(self, exchange_name, routing_key)
     }
 
+    /// Creates a [`BasicPublishBuilder`] for a message caused by `delivery`, with the
+    /// `correlation id` and causation id derived from it, see [`BasicPublishBuilder::follows`].
+    #[must_use]
+    pub fn follow_up_builder<'a>(
+        &'a self,
+        delivery: &lapin::message::Delivery,
+        exchange_name: &'a str,
+        routing_key: &'a str,
+    ) -> BasicPublishBuilder<'a, S> {
+        self.basic_publish_builder(exchange_name, routing_key)
+            .follows(delivery)
+    }
+
+    /// Publishes the same payload to every `(exchange, routing key)` target. The payload is
+    /// serialized once and all messages are published on a shared channel with the same
+    /// properties, including the message id.
+    ///
+    /// # Errors
+    ///
+    /// If the channel can not be obtained or the payload can not be serialized. Failures of
+    /// individual targets are reported in the returned results, in the order of `targets`.
+    pub async fn publish_to_all<P>(
+        &self,
+        targets: &[(&str, &str)],
+        payload: P,
+    ) -> Result<Vec<Result<PublisherConfirm, PublishError>>, PublishError>
+    where
+        P: Serialize,
+    {
+        basic_publish_builder::publish_to_all(self, targets, payload).await
+    }
+
+    /// Stores the message in the [`OutboxStore`], without publishing it. Call it within the
+    /// database transaction that also writes the business data, so the message is only ever
+    /// published if the transaction committed. Stored messages are published by the flusher,
+    /// see [`Self::spawn_outbox_flusher`], or right after the commit with
+    /// [`Self::flush_outbox`]. Messages may be published more than once, e.g. when marking them
+    /// as sent fails, so consumers should deduplicate by the returned message id.
+    ///
+    /// # Errors
+    /// See [`OutboxError`].
+    pub async fn publish_via_outbox<O, P>(
+        &self,
+        store: &O,
+        exchange_name: &str,
+        routing_key: &str,
+        payload: P,
+    ) -> Result<message::Id, OutboxError>
+    where
+        O: OutboxStore + ?Sized,
+        P: Serialize,
+    {
+        outbox::insert(self, store, exchange_name, routing_key, payload).await
+    }
+
+    /// Publishes up to `batch_size` pending messages of the [`OutboxStore`] once, e.g. right
+    /// after the transaction that stored them committed. Messages are published on channels in
+    /// confirm mode and only marked as sent once the broker acknowledged them, failed publishes
+    /// stay pending. Returns the amount of messages published.
+    ///
+    /// # Errors
+    /// See [`OutboxError`], if the pending messages can not be loaded.
+    pub async fn flush_outbox<O>(&self, store: &O, batch_size: usize) -> Result<usize, OutboxError>
+    where
+        O: OutboxStore + ?Sized,
+    {
+        outbox::flush_once(self, store, batch_size).await
+    }
+
+    /// Publishes up to `batch_size` pending messages of the [`OutboxStore`] every `interval` in
+    /// the background, like [`Self::flush_outbox`], retrying messages whose publish failed, e.g.
+    /// while the connection is down. Stops once a stop signal is received.
+    pub fn spawn_outbox_flusher<O>(&self, store: Arc<O>, interval: Duration, batch_size: usize)
+    where
+        O: OutboxStore,
+    {
+        self.runtime.spawn(Box::pin(outbox::flush(
+            self.clone(),
+            store,
+            interval,
+            batch_size,
+        )));
+    }
+
+    /// Republishes a dead-lettered message to the exchange and routing key recorded in its
+    /// [`dlx::ErrorData`], e.g. after the cause of the failure was fixed. The original message
+    /// id is kept. Checks that the origin exchange still exists before publishing.
+    ///
+    /// # Errors
+    /// See [`dlx::ReplayError`].
+    pub async fn replay_dead_letter<P>(
+        &self,
+        error_data: &dlx::ErrorData,
+        payload: P,
+    ) -> Result<Published, dlx::ReplayError>
+    where
+        P: Serialize,
+    {
+        dlx::replay(self, error_data, payload).await
+    }
+
+    /// Moves up to `max_count` messages, all of them if not set, from the queue to the exchange
+    /// with the routing key, e.g. to replay the messages collected in a dead letter queue to a
+    /// work queue. Messages are moved one by one with their properties, each one is only removed
+    /// from the source queue once the broker confirmed routing its copy. Returns the number of
+    /// moved messages.
+    ///
+    /// Messages published to the source queue while moving are moved as well. Moving messages to
+    /// a queue they are routed back to from the destination moves them in circles until
+    /// `max_count` is reached.
+    ///
+    /// # Errors
+    /// See [`MoveMessagesError`], a message that failed to move stays in the source queue.
+    pub async fn move_messages(
+        &self,
+        from_queue: &str,
+        to_exchange: &str,
+        to_routing_key: &str,
+        max_count: Option<MessageCount>,
+    ) -> Result<MessageCount, MoveMessagesError> {
+        queue_migration::move_to(self, from_queue, to_exchange, to_routing_key, max_count).await
+    }
+
+    /// Publishes error data to the DLX with [`dlx::ROUTING_KEY_ERROR`], as if a consumer failed,
+    /// e.g. to seed the DLX when testing error handling consumers or to dead-letter a message
+    /// manually. The error data is validated and encoded like the one of failing consumers.
+    ///
+    /// # Errors
+    /// See [`dlx::PublishDeadLetterError`].
+    pub async fn publish_dead_letter(
+        &self,
+        error_data: &dlx::ErrorData,
+    ) -> Result<Published, dlx::PublishDeadLetterError> {
+        dlx::publish_dead_letter(self, error_data).await
+    }
+
+    /// Declares a durable queue without dead lettering and binds it to the parking lot, which
+    /// collects [`dlx::ParkedData`] of permanently failed messages, see
+    /// [`BasicConsumeBuilder::max_deliveries`]. Consume it with the
+    /// [`Json`](crate::consumer_extract::Json) extractor, e.g. to alert a human.
+    ///
+    /// # Errors
+    /// See [`ParkingLotError`].
+    pub async fn parking_lot_queue(&self, queue_name: &str) -> Result<Queue, ParkingLotError> {
+        let queue = self
+            .queue_declare_builder(queue_name)
+            .dead_lettering(false)
+            .declare()
+            .await
+            .map_err(|err| ParkingLotError(err.into()))?;
+        self.queue_bind_builder(&queue, dlx::EXCHANGE_NAME, dlx::ROUTING_KEY_PARKED)
+            .bind()
+            .await
+            .map_err(|err| ParkingLotError(err.into()))?;
+        Ok(queue)
+    }
+
     /// Creates a [`BasicConsumeBuilder`] to consume messages from a queue.
     #[must_use]
     pub fn basic_consume_builder<'a>(
         &'a self,
         queue: &'a Queue,
         consumer_tag: &'a str,
-    ) -> BasicConsumeBuilder<S> {
+    ) -> BasicConsumeBuilder<'a, S> {
         BasicConsumeBuilder::new(self, queue, consumer_tag)
     }
 
+    /// Consumes the queue and rebroadcasts every delivery to any number of in-process receivers,
+    /// e.g. to bridge the queue to an internal event bus. Subscribe more receivers with
+    /// [`broadcast::Receiver::resubscribe`]. Deliveries are consumed in order and acknowledged as
+    /// soon as they were broadcast, if no receiver is left they are nacked and dead-lettered.
+    ///
+    /// The channel holds `capacity` deliveries. A receiver falling further behind misses the
+    /// oldest deliveries and gets a [`broadcast::error::RecvError::Lagged`], as they were
+    /// acknowledged already the broker does not redeliver them.
+    ///
+    /// # Errors
+    /// See [`ConsumeError`].
+    ///
+    /// # Panics
+    /// If `capacity` is `0`.
+    pub async fn consume_to_broadcast(
+        &self,
+        queue: &Queue,
+        consumer_tag: &str,
+        capacity: usize,
+    ) -> Result<broadcast::Receiver<Delivery>, ConsumeError> {
+        let (sender, receiver) = broadcast::channel(capacity);
+        self.basic_consume_builder(queue, consumer_tag)
+            .in_parallel(false)
+            .consume(move |delivery: Delivery| {
+                let sender = sender.clone();
+                async move {
+                    sender
+                        .send(delivery)
+                        .map(|_| ())
+                        .map_err(|_| anyhow::format_err!("no broadcast receiver left"))
+                }
+            })
+            .await?;
+        Ok(receiver)
+    }
+
+    /// Consumes several queues with one `handler`, always handling the waiting delivery of the
+    /// queue with the highest priority first, e.g. to drain a queue of urgent jobs before
+    /// regular ones, across separately bound queues a single priority queue can not cover.
+    /// Deliveries are handled one at a time and acknowledged like [`BasicConsumeBuilder::consume`]
+    /// does, every queue holds back at most one unacknowledged delivery.
+    ///
+    /// With [`Fairness::Strict`] lower-priority queues starve as long as higher-priority queues
+    /// keep receiving messages, use [`Fairness::Every`] to handle them every now and then. The
+    /// consumers are not recovered after a channel failure and stop with the client.
+    ///
+    /// # Errors
+    /// See [`ConsumeError`]. Also if `queues` is empty.
+    pub async fn consume_prioritized<H, T>(
+        &self,
+        queues: &[(&Queue, u8)],
+        fairness: Fairness,
+        handler: H,
+    ) -> Result<(), ConsumeError>
+    where
+        H: ConsumerHandler<S, T>,
+        T: 'static,
+    {
+        prioritized_consumer::consume(self, queues, fairness, handler).await
+    }
+
     /// Creates an [`RpcBuilder`] to execute a remote procedure call to the specififed queue.
     #[must_use]
-    pub fn rpc_builder<'a>(&'a self, request_queue_name: &'a str) -> RpcBuilder<S> {
+    pub fn rpc_builder<'a>(&'a self, request_queue_name: &'a str) -> RpcBuilder<'a, S> {
         RpcBuilder::new(self, request_queue_name)
     }
 
-    /// Joins the previously created basic consumers. Note that consumers, which are created after
-    /// this method was called will not be joined.
+    /// Declares all exchanges, queues and bindings of a [`Topology`].
     ///
     /// # Errors
+    /// See [`DeclareTopologyError`].
+    pub async fn declare_topology(&self, topology: &Topology) -> Result<(), DeclareTopologyError> {
+        topology.declare(self).await
+    }
+
+    /// Unbinds and deletes everything a [`Topology`] consists of, in reverse dependency order.
+    /// Entities that are already gone are ignored, which makes this handy for self-cleaning
+    /// integration tests.
     ///
-    /// # Panics
-    /// If the lapin connection errors.
-    pub async fn run(&self) -> Result<(), JoinBasicConsumersError> {
+    /// # Errors
+    /// See [`TeardownError`].
+    pub async fn teardown(&self, topology: &Topology) -> Result<(), TeardownError> {
+        topology.teardown(self).await
+    }
+
+    /// Joins the previously created basic consumers. Note that consumers, which are created after
+    /// this method was called will not be joined. Returns a [`CompletionReport`] once all
+    /// consumers have shut down. Without any consumers, blocks until the stop signal instead.
+    ///
+    /// # Errors
+    pub async fn run(&self) -> Result<CompletionReport, JoinBasicConsumersError> {
         /// Prefix for errors happening here duh
         const ERR_TRACE_PREFIX: &str = "a RabbitMQ client consumer failed";
 
         let (conn_error_sender, mut conn_error_receiver) =
             tokio::sync::mpsc::unbounded_channel::<lapin::Error>();
-        self.conn.on_error(move |e| {
-            conn_error_sender
-                .send(e)
-                .expect("connection error receiver dropped.");
-        });
+        // Also registered on connections replacing the current one, whoever reconnected.
+        self.connector.on_error(Arc::new(move |e| {
+            // The receiver is gone once all consumers have shut down.
+            std::mem::drop(conn_error_sender.send(e));
+        }));
 
         let mut consumer_set = std::mem::take(&mut *self.consumer_set.lock().await);
+        let mut report = CompletionReport {
+            consumers_joined: 0,
+            deliveries: 0,
+            graceful: true,
+        };
 
-        loop {
+        // Without consumers there is nothing to shut down, so wait for the stop signal directly.
+        let idle = consumer_set.is_empty();
+        let stop = util::signal_stop();
+        tokio::pin!(stop);
+
+        while idle || !consumer_set.is_empty() {
             tokio::select! {
+                stop_result = &mut stop, if idle => {
+                    stop_result.map_err(JoinBasicConsumersError::StopSignal)?;
+                    break;
+                },
                 conn_error_opt = conn_error_receiver.recv() => {
                     let Some(conn_err) = conn_error_opt else {
                         tracing::warn!("lapin connection error sender dropped");
                         return Err(JoinBasicConsumersError::ConnectionErrorReceiverDropped);
                     };
                     tracing::error!("received lapin connection error: {:?}", conn_err);
-                    return Err(JoinBasicConsumersError::Connection(conn_err));
+                    if !self.connector.reconnects() {
+                        return Err(JoinBasicConsumersError::Connection(conn_err));
+                    }
+                    self.connector
+                        .reconnect(&*self.runtime)
+                        .await
+                        .map_err(JoinBasicConsumersError::Reconnect)
+                        .on_err(|err| error!("{ERR_TRACE_PREFIX}: {err}"))?;
                 },
-                join_result_opt = consumer_set.join_next(), if !consumer_set.is_empty() => {
+                join_result_opt = consumer_set.join_next(), if !idle => {
                     let Some(join_result) = join_result_opt else {
                         continue;
                     };
                     let delivery_result = join_result
                         .map_err(|err| JoinBasicConsumersError::JoinTask(err.into()))
                         .on_err(|err| error!("{ERR_TRACE_PREFIX}: {err}"))?;
-                    delivery_result
+                    let consumer_report = delivery_result
                         .map_err(|err| JoinBasicConsumersError::Consumer(err.into()))
                         .on_err(|err| error!("{ERR_TRACE_PREFIX}: {err}"))?;
+                    report.consumers_joined += 1;
+                    report.deliveries += consumer_report.deliveries;
+                    report.graceful &= consumer_report.graceful;
                 }
             }
         }
+
+        info!(
+            consumers_joined = report.consumers_joined,
+            deliveries = report.deliveries,
+            graceful = report.graceful,
+            "RabbitMQ client consumers shut down"
+        );
+        Ok(report)
     }
 }
 
+/// The username the uri authenticates with, `None` if the uri does not name a user, as lapin
+/// then falls back to `guest`, or if authentication is delegated to the transport with the
+/// `EXTERNAL` mechanism, e.g. with client certificates. The broker rejects publishes whose
+/// `user-id` does not match the authenticated user, so it is only stamped if known for sure.
+fn explicit_username(uri: &str) -> Option<String> {
+    let parsed = AMQPUri::from_str(uri).ok()?;
+    let named = url::Url::parse(uri).is_ok_and(|url| !url.username().is_empty());
+    (named && parsed.query.auth_mechanism != Some(SASLMechanism::External))
+        .then_some(parsed.authority.userinfo.username)
+}
+
+/// Summary of [`Client::run`], once all consumers have shut down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompletionReport {
+    /// Amount of consumers that were joined.
+    pub consumers_joined: usize,
+    /// Amount of deliveries the consumers handed to their handlers.
+    pub deliveries: u64,
+    /// Whether every consumer shut down due to a stop signal.
+    pub graceful: bool,
+}
+
 /// Possible errors when creating the [`Client`].
 #[derive(Debug, thiserror::Error)]
 pub enum NewError {
+    /// Setting up TLS failed.
+    #[error("setting up TLS failed: {0}")]
+    Tls(anyhow::Error),
     /// Connecting to the `RabbitMq` failed.
     #[error("connecting to RabbitMQ server failed: {0}")]
     Connection(anyhow::Error),
@@ -242,14 +966,37 @@ pub enum NewError {
     DlxExchangeDeclare(anyhow::Error),
 }
 
+/// Errors when pinging the broker.
+#[derive(Debug, thiserror::Error)]
+pub enum PingError {
+    /// Obtaining a channel failed.
+    #[error("getting a channel failed: {0}")]
+    Channel(anyhow::Error),
+    /// The broker responded with an error.
+    #[error("broker responded with an error: {0}")]
+    Broker(anyhow::Error),
+    /// The broker did not respond in time.
+    #[error("timeout of {0:?} reached")]
+    Timeout(Duration),
+}
+
 /// Errors when requesting a [`lapin::Channel`].
 #[derive(Debug, thiserror::Error)]
 pub enum GetChannelError {
+    /// No pooled channel became available within the timeout, see
+    /// [`Client::with_channel_pool_timeout`].
+    #[error("no channel became available within {0:?}")]
+    Timeout(Duration),
     /// Any error.
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
 
+/// Errors that can occur when declaring the parking lot queue.
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct ParkingLotError(#[from] anyhow::Error);
+
 /// Errors when joining/starting bound consumers.
 #[derive(Debug, thiserror::Error)]
 pub enum JoinBasicConsumersError {
@@ -267,15 +1014,10 @@ pub enum JoinBasicConsumersError {
     /// example because it was interrupted.
     #[error(transparent)]
     Connection(#[from] lapin::Error),
-}
-
-impl<T> Deref for Client<T>
-where
-    T: Clone + Send + Sync + 'static,
-{
-    type Target = Connection;
-
-    fn deref(&self) -> &Self::Target {
-        &self.conn
-    }
+    /// The connection failed and reconnecting did not succeed, see [`Client::with_reconnect`].
+    #[error(transparent)]
+    Reconnect(ReconnectError),
+    /// Listening for the stop signal failed, while waiting without any consumers.
+    #[error("listening for the stop signal failed: {0}")]
+    StopSignal(std::io::Error),
 }
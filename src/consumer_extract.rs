@@ -1,13 +1,17 @@
 //! [`FromDeliveryData`] extractor implementations.
+use std::io::{self, BufRead, Read};
 use std::ops::{Deref, DerefMut};
 use std::str::FromStr;
 use std::sync::Arc;
 
 use async_trait::async_trait;
 use bytes::Bytes;
+use lapin::options::{BasicAckOptions, BasicNackOptions, BasicRejectOptions};
+use lapin::types::{AMQPValue, DeliveryTag, FieldTable, ShortString};
 use serde::de::DeserializeOwned;
 use uuid::Uuid;
 
+use crate::basic_consume_builder::ConsumerContext;
 use crate::{message, Client};
 
 /// Extractor trait for [`Delivery`] objects. Extract data from a [`Delivery`] for
@@ -20,6 +24,7 @@ where
     /// Extract Self from a [`Delivery`].
     async fn from_delivery_data(
         client: &Client<S>,
+        ctx: &ConsumerContext,
         delivery: &Delivery,
     ) -> Result<Self, anyhow::Error>;
 }
@@ -33,9 +38,10 @@ where
 {
     async fn from_delivery_data(
         client: &Client<S>,
+        ctx: &ConsumerContext,
         delivery: &Delivery,
     ) -> Result<Self, anyhow::Error> {
-        Ok(T::from_delivery_data(client, delivery)
+        Ok(T::from_delivery_data(client, ctx, delivery)
             .await
             .map_err(E::from))
     }
@@ -49,9 +55,10 @@ where
 {
     async fn from_delivery_data(
         client: &Client<S>,
+        ctx: &ConsumerContext,
         delivery: &Delivery,
     ) -> Result<Self, anyhow::Error> {
-        Ok(T::from_delivery_data(client, delivery).await.ok())
+        Ok(T::from_delivery_data(client, ctx, delivery).await.ok())
     }
 }
 
@@ -60,7 +67,11 @@ impl<S> FromDeliveryData<S> for Client<S>
 where
     S: Clone + Send + Sync + 'static,
 {
-    async fn from_delivery_data(client: &Client<S>, _: &Delivery) -> Result<Self, anyhow::Error> {
+    async fn from_delivery_data(
+        client: &Client<S>,
+        _: &ConsumerContext,
+        _: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
         Ok(client.clone())
     }
 }
@@ -106,6 +117,7 @@ where
 {
     async fn from_delivery_data(
         client: &Client<InnerS>,
+        _: &ConsumerContext,
         _: &Delivery,
     ) -> Result<Self, anyhow::Error> {
         Ok(Self(OuterS::from_ref(&client.state)))
@@ -120,7 +132,11 @@ impl<S> FromDeliveryData<S> for Delivery
 where
     S: Clone + Send + Sync + 'static,
 {
-    async fn from_delivery_data(_: &Client<S>, delivery: &Delivery) -> Result<Self, anyhow::Error> {
+    async fn from_delivery_data(
+        _: &Client<S>,
+        _: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
         Ok(delivery.clone())
     }
 }
@@ -148,7 +164,11 @@ impl<S> FromDeliveryData<S> for AppId<String>
 where
     S: Clone + Send + Sync + 'static,
 {
-    async fn from_delivery_data(_: &Client<S>, delivery: &Delivery) -> Result<Self, anyhow::Error> {
+    async fn from_delivery_data(
+        _: &Client<S>,
+        _: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
         let app_id_short_str = delivery
             .properties
             .app_id()
@@ -182,7 +202,11 @@ impl<S> FromDeliveryData<S> for MessageId<String>
 where
     S: Clone + Send + Sync + 'static,
 {
-    async fn from_delivery_data(_: &Client<S>, delivery: &Delivery) -> Result<Self, anyhow::Error> {
+    async fn from_delivery_data(
+        _: &Client<S>,
+        _: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
         let msg_id_short_str = delivery
             .properties
             .message_id()
@@ -198,7 +222,11 @@ impl<S> FromDeliveryData<S> for MessageId<Uuid>
 where
     S: Clone + Send + Sync + 'static,
 {
-    async fn from_delivery_data(_: &Client<S>, delivery: &Delivery) -> Result<Self, anyhow::Error> {
+    async fn from_delivery_data(
+        _: &Client<S>,
+        _: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
         let msg_id_short_str = delivery
             .properties
             .message_id()
@@ -215,7 +243,11 @@ impl<S> FromDeliveryData<S> for message::Id
 where
     S: Clone + Send + Sync + 'static,
 {
-    async fn from_delivery_data(_: &Client<S>, delivery: &Delivery) -> Result<Self, anyhow::Error> {
+    async fn from_delivery_data(
+        _: &Client<S>,
+        _: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
         let msg_id_short_str = delivery
             .properties
             .message_id()
@@ -231,7 +263,11 @@ impl<S> FromDeliveryData<S> for Vec<u8>
 where
     S: Clone + Send + Sync + 'static,
 {
-    async fn from_delivery_data(_: &Client<S>, delivery: &Delivery) -> Result<Self, anyhow::Error> {
+    async fn from_delivery_data(
+        _: &Client<S>,
+        _: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
         Ok(delivery.data.clone())
     }
 }
@@ -241,11 +277,272 @@ impl<S> FromDeliveryData<S> for Bytes
 where
     S: Clone + Send + Sync + 'static,
 {
-    async fn from_delivery_data(_: &Client<S>, delivery: &Delivery) -> Result<Self, anyhow::Error> {
+    async fn from_delivery_data(
+        _: &Client<S>,
+        _: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
         Ok(Self::copy_from_slice(&delivery.data))
     }
 }
 
+/// `BodyReader` extractor. Reads the body of the delivery through [`Read`] and [`BufRead`],
+/// e.g. for streaming parsers or decompressors. Shares the body with the delivery instead of
+/// copying it like `Vec<u8>` and [`Bytes`].
+#[derive(Debug, Clone)]
+pub struct BodyReader {
+    /// The delivery holding the body.
+    delivery: Delivery,
+    /// Number of bytes already read.
+    position: usize,
+}
+
+impl BodyReader {
+    /// The whole body, regardless of how much of it was read.
+    #[must_use]
+    pub fn get_ref(&self) -> &[u8] {
+        &self.delivery.data
+    }
+
+    /// The part of the body not read yet.
+    #[must_use]
+    pub fn remaining(&self) -> &[u8] {
+        &self.delivery.data[self.position..]
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.remaining().read(buf)?;
+        self.position += read;
+        Ok(read)
+    }
+}
+
+impl BufRead for BodyReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        Ok(self.remaining())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.position = (self.position + amt).min(self.delivery.data.len());
+    }
+}
+
+#[async_trait]
+impl<S> FromDeliveryData<S> for BodyReader
+where
+    S: Clone + Send + Sync + 'static,
+{
+    async fn from_delivery_data(
+        _: &Client<S>,
+        _: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            delivery: delivery.clone(),
+            position: 0,
+        })
+    }
+}
+
+/// `CausationId` extractor. The id of the message that caused the delivery, read from the
+/// [`message::CAUSATION_ID_HEADER`] header. Fails if the header is missing, use
+/// `Option<CausationId>` for deliveries that may not have one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CausationId(pub String);
+
+impl Deref for CausationId {
+    type Target = String;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl<S> FromDeliveryData<S> for CausationId
+where
+    S: Clone + Send + Sync + 'static,
+{
+    async fn from_delivery_data(
+        _: &Client<S>,
+        _: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
+        let headers = Headers(delivery.properties.headers().clone().unwrap_or_default());
+        let causation_id = headers
+            .get_str(message::CAUSATION_ID_HEADER)
+            .ok_or(anyhow::format_err!("causation ID is missing"))?;
+        Ok(Self(causation_id.to_string()))
+    }
+}
+
+/// `MessageType` extractor. The `type` property of the delivery, see
+/// [`crate::basic_publish_builder::BasicPublishBuilder::message_type`]. Fails if the property
+/// is missing, use `Option<MessageType>` for deliveries that may not have one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageType(pub String);
+
+impl Deref for MessageType {
+    type Target = String;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl<S> FromDeliveryData<S> for MessageType
+where
+    S: Clone + Send + Sync + 'static,
+{
+    async fn from_delivery_data(
+        _: &Client<S>,
+        _: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
+        let message_type = delivery
+            .properties
+            .kind()
+            .as_ref()
+            .ok_or(anyhow::format_err!("message type is missing"))?;
+        Ok(Self(message_type.to_string()))
+    }
+}
+
+/// `TypedBody` extractor. Deserializes deliveries of a queue carrying several message types
+/// into `E`, dispatching on the type, see [`message::MessageTypes`]. The type is read from the
+/// `type` property, see [`MessageType`], falling back to the [`message::MESSAGE_TYPE_HEADER`]
+/// header. Fails for missing and unknown types, so these deliveries are dead-lettered with the
+/// type in the error.
+#[derive(Debug, Clone)]
+pub struct TypedBody<E>(pub E);
+
+impl<E> Deref for TypedBody<E> {
+    type Target = E;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl<S, E> FromDeliveryData<S> for TypedBody<E>
+where
+    S: Clone + Send + Sync + 'static,
+    E: message::MessageTypes,
+{
+    async fn from_delivery_data(
+        _: &Client<S>,
+        _: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
+        let headers = Headers(delivery.properties.headers().clone().unwrap_or_default());
+        let message_type = delivery
+            .properties
+            .kind()
+            .as_ref()
+            .map(ShortString::as_str)
+            .or_else(|| headers.get_str(message::MESSAGE_TYPE_HEADER))
+            .ok_or(anyhow::format_err!("message type is missing"))?;
+        E::from_typed_body(message_type, &delivery.data)
+            .ok_or(anyhow::format_err!("unknown message type {message_type}"))?
+            .map(Self)
+            .map_err(|err| {
+                anyhow::format_err!("message of type {message_type} not deserializable: {err}")
+            })
+    }
+}
+
+/// `ConsumerTag` extractor. The tag of the consumer the delivery was consumed by, including
+/// its label, see [`crate::basic_consume_builder::BasicConsumeBuilder::identified_by`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsumerTag(pub String);
+
+impl Deref for ConsumerTag {
+    type Target = String;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl<S> FromDeliveryData<S> for ConsumerTag
+where
+    S: Clone + Send + Sync + 'static,
+{
+    async fn from_delivery_data(
+        _: &Client<S>,
+        ctx: &ConsumerContext,
+        _: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self(ctx.consumer_tag.clone()))
+    }
+}
+
+/// `Priority` extractor. The priority the message was published with, `0` if it has none, see
+/// [`crate::BasicPublishBuilder::priority`]. Not to be confused with [`crate::Priority`], which
+/// configures priority queues.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Priority(pub u8);
+
+impl Deref for Priority {
+    type Target = u8;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl<S> FromDeliveryData<S> for Priority
+where
+    S: Clone + Send + Sync + 'static,
+{
+    async fn from_delivery_data(
+        _: &Client<S>,
+        _: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self(delivery.properties.priority().unwrap_or_default()))
+    }
+}
+
+/// `ContentType` extractor. The `content_type` property of the delivery, `None` if it has none,
+/// e.g. to pick a codec for the raw body from [`Bytes`] or `Vec<u8>` in the handler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType(pub Option<String>);
+
+impl Deref for ContentType {
+    type Target = Option<String>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl<S> FromDeliveryData<S> for ContentType
+where
+    S: Clone + Send + Sync + 'static,
+{
+    async fn from_delivery_data(
+        _: &Client<S>,
+        _: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self(
+            delivery
+                .properties
+                .content_type()
+                .as_ref()
+                .map(ToString::to_string),
+        ))
+    }
+}
+
 /// Json extractor.
 #[derive(Debug, Clone)]
 pub struct Json<T>(pub T);
@@ -270,8 +567,12 @@ where
     S: Clone + Send + Sync + 'static,
     T: DeserializeOwned,
 {
-    async fn from_delivery_data(_: &Client<S>, delivery: &Delivery) -> Result<Self, anyhow::Error> {
-        let payload = serde_json::from_slice(&delivery.data)
+    async fn from_delivery_data(
+        _: &Client<S>,
+        _: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
+        let payload = message::from_json_slice(&delivery.data)
             .map_err(|err| anyhow::format_err!("message payload not deserializable: {err}"))?;
         Ok(Self(payload))
     }
@@ -301,7 +602,11 @@ where
     S: Clone + Send + Sync + 'static,
     T: prost::Message + Default,
 {
-    async fn from_delivery_data(_: &Client<S>, delivery: &Delivery) -> Result<Self, anyhow::Error> {
+    async fn from_delivery_data(
+        _: &Client<S>,
+        _: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
         let payload = T::decode(delivery.data.as_slice())
             .map_err(|err| anyhow::format_err!("message payload not decodeable: {err}"))?;
         Ok(Self(payload))
@@ -314,8 +619,684 @@ where
     S: Clone + Send + Sync + 'static,
     T: DeserializeOwned,
 {
-    async fn from_delivery_data(_: &Client<S>, delivery: &Delivery) -> Result<Self, anyhow::Error> {
+    async fn from_delivery_data(
+        _: &Client<S>,
+        _: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
         Self::deserialize(delivery.data.as_slice())
             .map_err(|err| anyhow::format_err!("message payload not deserialize: {err}"))
     }
 }
+
+#[async_trait]
+impl<S, T> FromDeliveryData<S> for message::Envelope<T>
+where
+    S: Clone + Send + Sync + 'static,
+    T: DeserializeOwned,
+{
+    async fn from_delivery_data(
+        _: &Client<S>,
+        ctx: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
+        if let Some((kind, version)) = &ctx.envelope {
+            validate_envelope_headers(delivery, kind, *version)?;
+        }
+        let envelope: Self = message::from_json_slice(&delivery.data)
+            .map_err(|err| anyhow::format_err!("message envelope not deserializable: {err}"))?;
+        if let Some((kind, version)) = &ctx.envelope {
+            envelope.validate(kind, *version)?;
+        }
+        Ok(envelope)
+    }
+}
+
+/// Validate the type and version headers of an [`message::Envelope`] before deserializing its
+/// body, if the publisher set them.
+fn validate_envelope_headers(
+    delivery: &Delivery,
+    kind: &str,
+    version: u32,
+) -> Result<(), message::EnvelopeMismatchError> {
+    let headers = Headers(delivery.properties.headers().clone().unwrap_or_default());
+    let header_kind = headers
+        .get_str(message::MESSAGE_TYPE_HEADER)
+        .unwrap_or(kind);
+    let header_version = headers
+        .get_i64(message::SCHEMA_VERSION_HEADER)
+        .and_then(|version| u32::try_from(version).ok())
+        .unwrap_or(version);
+    if header_kind != kind || header_version != version {
+        return Err(message::EnvelopeMismatchError {
+            expected_kind: kind.to_string(),
+            expected_version: version,
+            kind: header_kind.to_string(),
+            version: header_version,
+        });
+    }
+    Ok(())
+}
+
+/// Headers extractor. Provides typed reads of the [`FieldTable`] headers of a delivery, an empty
+/// table is used when the delivery has no headers.
+#[derive(Debug, Clone, Default)]
+pub struct Headers(pub FieldTable);
+
+impl Deref for Headers {
+    type Target = FieldTable;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for Headers {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl Headers {
+    /// Get a raw header value.
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&AMQPValue> {
+        self.0.inner().get(key)
+    }
+
+    /// Get a `string` header, if it is a valid UTF-8 short or long string.
+    #[must_use]
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        match self.get(key)? {
+            AMQPValue::ShortString(value) => Some(value.as_str()),
+            AMQPValue::LongString(value) => std::str::from_utf8(value.as_bytes()).ok(),
+            _ => None,
+        }
+    }
+
+    /// Get a boolean header.
+    #[must_use]
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.get(key)?.as_bool()
+    }
+
+    /// Get an integer header, any integer type that fits into an `i64` is accepted.
+    #[must_use]
+    pub fn get_i64(&self, key: &str) -> Option<i64> {
+        as_i64(self.get(key)?)
+    }
+
+    /// Get a floating point header, `f32` values are widened.
+    #[must_use]
+    pub fn get_f64(&self, key: &str) -> Option<f64> {
+        match *self.get(key)? {
+            AMQPValue::Float(value) => Some(value.into()),
+            AMQPValue::Double(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Get a timestamp header.
+    #[must_use]
+    pub fn get_timestamp(&self, key: &str) -> Option<u64> {
+        self.get(key)?.as_timestamp()
+    }
+
+    /// Get a nested table header.
+    #[must_use]
+    pub fn get_table(&self, key: &str) -> Option<&FieldTable> {
+        self.get(key)?.as_field_table()
+    }
+
+    /// Read and convert a header, see [`FromHeader`].
+    ///
+    /// # Errors
+    /// See [`HeadersError`].
+    pub fn extract<T>(&self, key: &str) -> Result<T, HeadersError>
+    where
+        T: FromHeader,
+    {
+        match self.get(key) {
+            Some(value) => {
+                T::from_header(value).ok_or_else(|| HeadersError::Invalid(key.to_string()))
+            }
+            None => T::from_missing().ok_or_else(|| HeadersError::Missing(key.to_string())),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromDeliveryData<S> for Headers
+where
+    S: Clone + Send + Sync + 'static,
+{
+    async fn from_delivery_data(
+        _: &Client<S>,
+        _: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self(
+            delivery.properties.headers().clone().unwrap_or_default(),
+        ))
+    }
+}
+
+/// Widen any integer [`AMQPValue`] to an `i64`.
+fn as_i64(value: &AMQPValue) -> Option<i64> {
+    match *value {
+        AMQPValue::ShortShortInt(value) => Some(value.into()),
+        AMQPValue::ShortShortUInt(value) => Some(value.into()),
+        AMQPValue::ShortInt(value) => Some(value.into()),
+        AMQPValue::ShortUInt(value) => Some(value.into()),
+        AMQPValue::LongInt(value) => Some(value.into()),
+        AMQPValue::LongUInt(value) => Some(value.into()),
+        AMQPValue::LongLongInt(value) => Some(value),
+        _ => None,
+    }
+}
+
+/// A value that can be read from a single header, see [`Headers::extract`].
+pub trait FromHeader: Sized {
+    /// Convert the header value, `None` if it has an unexpected type or is out of range.
+    fn from_header(value: &AMQPValue) -> Option<Self>;
+
+    /// The value if the header is missing, `None` if it is required.
+    #[must_use]
+    fn from_missing() -> Option<Self> {
+        None
+    }
+}
+
+impl<T> FromHeader for Option<T>
+where
+    T: FromHeader,
+{
+    fn from_header(value: &AMQPValue) -> Option<Self> {
+        T::from_header(value).map(Some)
+    }
+
+    fn from_missing() -> Option<Self> {
+        Some(None)
+    }
+}
+
+impl FromHeader for String {
+    fn from_header(value: &AMQPValue) -> Option<Self> {
+        match value {
+            AMQPValue::ShortString(value) => Some(value.to_string()),
+            AMQPValue::LongString(value) => String::from_utf8(value.as_bytes().to_vec()).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl FromHeader for bool {
+    fn from_header(value: &AMQPValue) -> Option<Self> {
+        value.as_bool()
+    }
+}
+
+impl FromHeader for f64 {
+    fn from_header(value: &AMQPValue) -> Option<Self> {
+        match *value {
+            AMQPValue::Float(value) => Some(value.into()),
+            AMQPValue::Double(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl FromHeader for FieldTable {
+    fn from_header(value: &AMQPValue) -> Option<Self> {
+        value.as_field_table().cloned()
+    }
+}
+
+/// Implement [`FromHeader`] for integers, accepting any integer header that fits.
+macro_rules! impl_from_header_for_int {
+    ($($int:ty),*) => {
+        $(
+            impl FromHeader for $int {
+                fn from_header(value: &AMQPValue) -> Option<Self> {
+                    <$int>::try_from(as_i64(value)?).ok()
+                }
+            }
+        )*
+    };
+}
+
+impl_from_header_for_int!(i8, i16, i32, i64, u8, u16, u32, u64, usize);
+
+/// A type that can be read from the headers of a delivery, see [`TypedHeaders`]. Derive it
+/// with `#[derive(hare::FromHeaders)]`, which requires the `macros` feature, to read every
+/// field from the header of the same name, or the name set with `#[header(rename = "...")]`.
+/// Fields of type `Option` may be missing.
+pub trait FromHeaders: Sized {
+    /// Read `Self` from the headers.
+    ///
+    /// # Errors
+    /// See [`HeadersError`].
+    fn from_headers(headers: &Headers) -> Result<Self, HeadersError>;
+}
+
+/// Errors that can occur when reading typed headers.
+#[derive(Debug, thiserror::Error)]
+pub enum HeadersError {
+    /// A required header is missing.
+    #[error("header {0} is missing")]
+    Missing(String),
+    /// A header has an unexpected type or its value is out of range.
+    #[error("header {0} has an unexpected type or value")]
+    Invalid(String),
+}
+
+/// `TypedHeaders` extractor. Reads `T` from the headers of a delivery, see [`FromHeaders`].
+/// Fails naming the header that is missing or could not be converted.
+#[derive(Debug, Clone)]
+pub struct TypedHeaders<T>(pub T);
+
+impl<T> Deref for TypedHeaders<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl<S, T> FromDeliveryData<S> for TypedHeaders<T>
+where
+    S: Clone + Send + Sync + 'static,
+    T: FromHeaders,
+{
+    async fn from_delivery_data(
+        _: &Client<S>,
+        _: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
+        let headers = Headers(delivery.properties.headers().clone().unwrap_or_default());
+        Ok(Self(T::from_headers(&headers)?))
+    }
+}
+
+/// Schema version extractor. Reads the [`message::SCHEMA_VERSION_HEADER`] header, e.g. to
+/// dispatch to different handler logic during rolling upgrades. Fails if the header is missing
+/// or not an unsigned 32 bit integer. See
+/// [`crate::basic_consume_builder::BasicConsumeBuilder::supported_versions`] to reject
+/// unsupported versions before the handler runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SchemaVersion(pub u32);
+
+impl SchemaVersion {
+    /// Read the schema version from the headers of a delivery.
+    pub(super) fn from_delivery(
+        delivery: &lapin::message::Delivery,
+    ) -> Result<Self, anyhow::Error> {
+        let value = delivery
+            .properties
+            .headers()
+            .as_ref()
+            .and_then(|headers| headers.inner().get(message::SCHEMA_VERSION_HEADER))
+            .ok_or_else(|| {
+                anyhow::format_err!("{} header missing", message::SCHEMA_VERSION_HEADER)
+            })?;
+        as_i64(value)
+            .and_then(|version| u32::try_from(version).ok())
+            .map(Self)
+            .ok_or_else(|| {
+                anyhow::format_err!(
+                    "{} header is not an unsigned 32 bit integer: {value:?}",
+                    message::SCHEMA_VERSION_HEADER
+                )
+            })
+    }
+}
+
+#[async_trait]
+impl<S> FromDeliveryData<S> for SchemaVersion
+where
+    S: Clone + Send + Sync + 'static,
+{
+    async fn from_delivery_data(
+        _: &Client<S>,
+        _: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
+        Self::from_delivery(delivery)
+    }
+}
+
+/// Span extractor. Provides a child span of the delivery span, carrying metadata of the
+/// delivery, to instrument the handler with.
+#[derive(Debug, Clone)]
+pub struct Span(pub tracing::Span);
+
+impl Deref for Span {
+    type Target = tracing::Span;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for Span {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+#[async_trait]
+impl<S> FromDeliveryData<S> for Span
+where
+    S: Clone + Send + Sync + 'static,
+{
+    async fn from_delivery_data(
+        _: &Client<S>,
+        ctx: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self(tracing::debug_span!(
+            parent: tracing::Span::current(),
+            "handler",
+            queue_name = %ctx.queue_name,
+            consumer_tag = %ctx.consumer_tag,
+            routing_key = %delivery.routing_key,
+            redelivered = %delivery.redelivered,
+        )))
+    }
+}
+
+/// Ack token extractor. Hands the acknowledgement of a delivery over to the handler, e.g. to
+/// acknowledge it from another task once processing has finished there. The delivery is not
+/// acknowledged automatically when the handler succeeds, but it is still nacked if the handler
+/// fails, using the token afterwards fails.
+///
+/// Delivery tags are scoped to the channel of the consumer, so the token can only be used while
+/// that channel is open. After the consumer was recovered on a new channel, outstanding tokens
+/// fail and the deliveries are redelivered by the broker. Not available when acknowledging in
+/// batches.
+#[derive(Debug, Clone)]
+pub struct AckToken(
+    /// Settles the delivery.
+    lapin::acker::Acker,
+    /// The delivery tag of the delivery.
+    DeliveryTag,
+    /// Whether [`Self::nack_multiple`] is allowed, as no other delivery is in flight.
+    bool,
+);
+
+impl AckToken {
+    /// The delivery tag of the delivery.
+    #[must_use]
+    pub fn delivery_tag(&self) -> DeliveryTag {
+        self.1
+    }
+
+    /// Acknowledge the delivery.
+    ///
+    /// # Errors
+    /// See [`AckTokenError`].
+    pub async fn ack(self) -> Result<(), AckTokenError> {
+        self.0
+            .ack(BasicAckOptions::default())
+            .await
+            .map_err(|err| AckTokenError(err.into()))
+    }
+
+    /// Negatively acknowledge the delivery.
+    ///
+    /// # Errors
+    /// See [`AckTokenError`].
+    pub async fn nack(self, requeue: bool) -> Result<(), AckTokenError> {
+        self.0
+            .nack(BasicNackOptions {
+                requeue,
+                ..BasicNackOptions::default()
+            })
+            .await
+            .map_err(|err| AckTokenError(err.into()))
+    }
+
+    /// Negatively acknowledge the delivery together with every earlier delivery of the channel
+    /// that is not settled yet, i.e. all unsettled deliveries up to and including this one, with
+    /// a single `multiple` nack. Use it to fail a whole batch of deliveries at once, handing in
+    /// the token of the latest one. Tokens of the other deliveries must not be used afterwards,
+    /// settling a delivery twice is a protocol error that closes the channel.
+    ///
+    /// Only available with [`crate::BasicConsumeBuilder::manual_ack`] and without
+    /// [`crate::BasicConsumeBuilder::in_parallel`], otherwise the nack would also cover
+    /// deliveries the consumer still settles itself.
+    ///
+    /// # Errors
+    /// See [`AckTokenError`], also if the consumer does not allow it.
+    pub async fn nack_multiple(self, requeue: bool) -> Result<(), AckTokenError> {
+        if !self.2 {
+            return Err(AckTokenError(anyhow::format_err!(
+                "nack_multiple requires manual_ack without in_parallel"
+            )));
+        }
+        self.0
+            .nack(BasicNackOptions {
+                multiple: true,
+                requeue,
+            })
+            .await
+            .map_err(|err| AckTokenError(err.into()))
+    }
+}
+
+/// Errors that can occur when using an [`AckToken`].
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct AckTokenError(#[from] anyhow::Error);
+
+#[async_trait]
+impl<S> FromDeliveryData<S> for AckToken
+where
+    S: Clone + Send + Sync + 'static,
+{
+    async fn from_delivery_data(
+        _: &Client<S>,
+        ctx: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
+        if ctx.batch_ack.is_some() {
+            return Err(anyhow::format_err!(
+                "ack tokens are not available when acknowledging in batches"
+            ));
+        }
+        ctx.hand_out_ack_token(delivery.delivery_tag);
+        // Batch acks are excluded above.
+        let multiple = ctx.manual_ack && !ctx.in_parallel;
+        Ok(Self(
+            delivery.acker.clone(),
+            delivery.delivery_tag,
+            multiple,
+        ))
+    }
+}
+
+/// Acker extractor. Settles the delivery from within the handler, requires
+/// [`crate::BasicConsumeBuilder::manual_ack`]. Every delivery can only be settled once, further
+/// attempts fail with [`AckerError::AlreadySettled`].
+#[derive(Debug, Clone)]
+pub struct Acker(lapin::acker::Acker);
+
+impl Acker {
+    /// Acknowledge the delivery.
+    ///
+    /// # Errors
+    /// See [`AckerError`].
+    pub async fn ack(&self) -> Result<(), AckerError> {
+        self.check_unsettled()?;
+        self.0
+            .ack(BasicAckOptions::default())
+            .await
+            .map_err(|err| AckerError::Settle(err.into()))
+    }
+
+    /// Negatively acknowledge the delivery, it is dead-lettered unless `requeue` is set.
+    ///
+    /// # Errors
+    /// See [`AckerError`].
+    pub async fn nack(&self, requeue: bool) -> Result<(), AckerError> {
+        self.check_unsettled()?;
+        self.0
+            .nack(BasicNackOptions {
+                requeue,
+                ..BasicNackOptions::default()
+            })
+            .await
+            .map_err(|err| AckerError::Settle(err.into()))
+    }
+
+    /// Reject the delivery, it is dead-lettered unless `requeue` is set.
+    ///
+    /// # Errors
+    /// See [`AckerError`].
+    pub async fn reject(&self, requeue: bool) -> Result<(), AckerError> {
+        self.check_unsettled()?;
+        self.0
+            .reject(BasicRejectOptions { requeue })
+            .await
+            .map_err(|err| AckerError::Settle(err.into()))
+    }
+
+    /// Whether the delivery was settled already.
+    #[must_use]
+    pub fn is_settled(&self) -> bool {
+        self.0.used()
+    }
+
+    /// Fail if the delivery was settled already.
+    fn check_unsettled(&self) -> Result<(), AckerError> {
+        if self.is_settled() {
+            return Err(AckerError::AlreadySettled);
+        }
+        Ok(())
+    }
+}
+
+/// Errors that can occur when using an [`Acker`].
+#[derive(Debug, thiserror::Error)]
+pub enum AckerError {
+    /// The delivery was acknowledged, nacked or rejected before.
+    #[error("the delivery was settled already")]
+    AlreadySettled,
+    /// Settling the delivery failed.
+    #[error("settling the delivery failed: {0}")]
+    Settle(anyhow::Error),
+}
+
+#[async_trait]
+impl<S> FromDeliveryData<S> for Acker
+where
+    S: Clone + Send + Sync + 'static,
+{
+    async fn from_delivery_data(
+        _: &Client<S>,
+        ctx: &ConsumerContext,
+        delivery: &Delivery,
+    ) -> Result<Self, anyhow::Error> {
+        if !ctx.manual_ack {
+            return Err(anyhow::format_err!(
+                "the acker extractor requires manual acknowledgement"
+            ));
+        }
+        Ok(Self(delivery.acker.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lapin::types::{AMQPValue, FieldTable, LongString, ShortString};
+
+    use super::{FromHeader, Headers, HeadersError};
+
+    /// Headers containing the given values.
+    fn headers(values: &[(&str, AMQPValue)]) -> Headers {
+        let mut table = FieldTable::default();
+        for (key, value) in values {
+            table.insert((*key).into(), value.clone());
+        }
+        Headers(table)
+    }
+
+    #[test]
+    fn integers_are_converted_if_in_range() {
+        assert_eq!(u8::from_header(&AMQPValue::LongInt(255)), Some(255));
+        assert_eq!(u8::from_header(&AMQPValue::LongInt(256)), None);
+        assert_eq!(u8::from_header(&AMQPValue::ShortShortInt(-1)), None);
+        assert_eq!(i8::from_header(&AMQPValue::ShortShortUInt(128)), None);
+        assert_eq!(i16::from_header(&AMQPValue::ShortInt(-300)), Some(-300));
+        assert_eq!(
+            u32::from_header(&AMQPValue::LongLongInt(i64::from(u32::MAX))),
+            Some(u32::MAX)
+        );
+        assert_eq!(
+            u32::from_header(&AMQPValue::LongLongInt(i64::from(u32::MAX) + 1)),
+            None
+        );
+        assert_eq!(u64::from_header(&AMQPValue::LongLongInt(-1)), None);
+        assert_eq!(
+            i64::from_header(&AMQPValue::LongUInt(u32::MAX)),
+            Some(i64::from(u32::MAX))
+        );
+    }
+
+    #[test]
+    fn integers_are_not_converted_from_other_types() {
+        assert_eq!(i64::from_header(&AMQPValue::Double(1.0)), None);
+        assert_eq!(i64::from_header(&AMQPValue::Boolean(true)), None);
+        assert_eq!(
+            i64::from_header(&AMQPValue::LongString(LongString::from("1"))),
+            None
+        );
+    }
+
+    #[test]
+    fn strings_are_converted_from_valid_utf8() {
+        assert_eq!(
+            String::from_header(&AMQPValue::ShortString(ShortString::from("short"))),
+            Some("short".to_string())
+        );
+        assert_eq!(
+            String::from_header(&AMQPValue::LongString(LongString::from("long"))),
+            Some("long".to_string())
+        );
+        assert_eq!(
+            String::from_header(&AMQPValue::LongString(LongString::from(vec![0xff, 0xfe]))),
+            None
+        );
+    }
+
+    #[test]
+    fn optional_headers_may_be_missing_but_not_invalid() {
+        let headers = headers(&[
+            ("present", AMQPValue::LongInt(1)),
+            ("invalid", AMQPValue::Boolean(true)),
+        ]);
+        assert_eq!(
+            headers.extract::<Option<u32>>("present").ok(),
+            Some(Some(1))
+        );
+        assert_eq!(headers.extract::<Option<u32>>("missing").ok(), Some(None));
+        assert!(matches!(
+            headers.extract::<Option<u32>>("invalid"),
+            Err(HeadersError::Invalid(key)) if key == "invalid"
+        ));
+    }
+
+    #[test]
+    fn required_headers_fail_naming_the_header() {
+        let headers = headers(&[("invalid", AMQPValue::LongInt(-1))]);
+        assert!(matches!(
+            headers.extract::<u32>("missing"),
+            Err(HeadersError::Missing(key)) if key == "missing"
+        ));
+        assert!(matches!(
+            headers.extract::<u32>("invalid"),
+            Err(HeadersError::Invalid(key)) if key == "invalid"
+        ));
+    }
+}
@@ -0,0 +1,167 @@
+//! [`ConsumerHandle`] definition and implementation.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+
+use lapin::options::{BasicCancelOptions, BasicRecoverOptions};
+use lapin::protocol::constants::REPLY_SUCCESS;
+use lapin::Consumer;
+use tokio::sync::Notify;
+use tracing::warn;
+
+/// Handle to a consumer created with
+/// [`crate::basic_consume_builder::BasicConsumeBuilder::consume`]. Always refers to the current
+/// channel of the consumer, even after it was recovered on a new channel.
+#[derive(Debug, Clone)]
+pub struct ConsumerHandle(Arc<ConsumerHandleInner>);
+
+/// Shared state of a [`ConsumerHandle`].
+#[derive(Debug)]
+struct ConsumerHandleInner {
+    /// The current channel and consumer.
+    current: RwLock<ConsumerChannel>,
+    /// Whether the consumer is paused.
+    paused: AtomicBool,
+    /// Whether the current consumer was canceled by [`ConsumerHandle::pause`] and the consumer
+    /// task did not notice yet. Kept apart from `paused`, as the consumer may be resumed before
+    /// the task noticed the cancellation.
+    canceled: AtomicBool,
+    /// Wakes the consumer task once the consumer is resumed.
+    resumed: Notify,
+}
+
+/// The channel a consumer is consuming on.
+#[derive(Debug, Clone)]
+struct ConsumerChannel {
+    /// The consumers dedicated channel.
+    chan: lapin::Channel,
+    /// The lapin consumer.
+    consumer: Consumer,
+}
+
+impl ConsumerHandle {
+    /// Create a new `ConsumerHandle`.
+    pub(super) fn new(chan: lapin::Channel, consumer: Consumer) -> Self {
+        Self(Arc::new(ConsumerHandleInner {
+            current: RwLock::new(ConsumerChannel { chan, consumer }),
+            paused: AtomicBool::new(false),
+            canceled: AtomicBool::new(false),
+            resumed: Notify::new(),
+        }))
+    }
+
+    /// Replace the channel and consumer, after the consumer was recovered.
+    pub(super) fn replace(&self, chan: lapin::Channel, consumer: Consumer) {
+        *self
+            .0
+            .current
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = ConsumerChannel { chan, consumer };
+    }
+
+    /// The current lapin [`Consumer`].
+    #[must_use]
+    pub fn consumer(&self) -> Consumer {
+        self.0
+            .current
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .consumer
+            .clone()
+    }
+
+    /// The channel the consumer is currently consuming on.
+    #[must_use]
+    pub fn channel(&self) -> lapin::Channel {
+        self.0
+            .current
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .chan
+            .clone()
+    }
+
+    /// Pause the consumer by canceling it, no further deliveries are pulled from the queue
+    /// while the connection and channel stay open. In-flight deliveries are still handled. See
+    /// [`Self::resume`].
+    ///
+    /// # Errors
+    /// See [`PauseError`].
+    pub async fn pause(&self) -> Result<(), PauseError> {
+        if self.0.paused.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let consumer = self.consumer();
+        // Set before canceling, the consumer task may notice the cancellation right away.
+        self.0.canceled.store(true, Ordering::SeqCst);
+        if let Err(err) = self
+            .channel()
+            .basic_cancel(consumer.tag().as_str(), BasicCancelOptions::default())
+            .await
+        {
+            self.0.canceled.store(false, Ordering::SeqCst);
+            self.0.paused.store(false, Ordering::SeqCst);
+            return Err(PauseError(err.into()));
+        }
+        Ok(())
+    }
+
+    /// Resume a paused consumer. The consumer task consumes the queue again on the same channel,
+    /// with the same consumer tag, options and handler. Failing to consume again is handled like
+    /// a failed channel, by recovering the consumer.
+    pub fn resume(&self) {
+        if self.0.paused.swap(false, Ordering::SeqCst) {
+            self.0.resumed.notify_one();
+        }
+    }
+
+    /// Whether the consumer is paused, see [`Self::pause`].
+    #[must_use]
+    pub fn is_paused(&self) -> bool {
+        self.0.paused.load(Ordering::SeqCst)
+    }
+
+    /// Whether the consumer stopped because [`Self::pause`] canceled it, resetting the flag.
+    /// Unlike [`Self::is_paused`], this stays set if the consumer was resumed in the meantime.
+    pub(super) fn take_canceled(&self) -> bool {
+        self.0.canceled.swap(false, Ordering::SeqCst)
+    }
+
+    /// Wait until the consumer is resumed.
+    pub(super) async fn resumed(&self) {
+        self.0.resumed.notified().await;
+    }
+
+    /// Close the channel the consumer is currently consuming on, if it is still open.
+    pub(super) async fn close(&self) {
+        let chan = self.channel();
+        if !chan.status().connected() {
+            return;
+        }
+        if let Err(err) = chan.close(REPLY_SUCCESS, "consumer shut down").await {
+            warn!("closing the consumer channel failed: {err}");
+        }
+    }
+
+    /// Asks the broker to redeliver all unacknowledged messages of the consumers channel. Note
+    /// that `RabbitMQ` only supports redelivering with `requeue` set to `true`.
+    ///
+    /// # Errors
+    /// See [`RecoverError`].
+    pub async fn basic_recover(&self, requeue: bool) -> Result<(), RecoverError> {
+        self.channel()
+            .basic_recover(BasicRecoverOptions { requeue })
+            .await
+            .map_err(|err| RecoverError(err.into()))
+    }
+}
+
+/// Errors that can occur when pausing a consumer.
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct PauseError(#[from] anyhow::Error);
+
+/// Errors that can occur when asking the broker to redeliver unacknowledged messages.
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct RecoverError(#[from] anyhow::Error);
@@ -3,9 +3,11 @@
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Instant;
 
 use lapin::message::Delivery;
 
+use crate::basic_consume_builder::ConsumerContext;
 use crate::{
     Client, ConsumerHandlerError, ConsumerHandlerResult, FromDeliveryData,
     IntoConsumerHandlerResult,
@@ -43,12 +45,20 @@ pub trait ConsumerHandler<S, T>: Clone + Send + 'static
 where
     S: Clone + Send + Sync + 'static,
 {
+    /// The value the handler produces on success, `()` for most handlers.
+    type Value: Send + 'static;
+
     /// Functions implementing `ConsumerHandler` have to yield [`Future`]s resulting
     /// in [`ConsumerHandlerResult`]s.
-    type Future: Future<Output = ConsumerHandlerResult> + Send + 'static;
+    type Future: Future<Output = ConsumerHandlerResult<Self::Value>> + Send + 'static;
 
     /// Call executes the consumer handler for a [`Delivery`].
-    fn call(self, client: Client<S>, delivery: Arc<Delivery>) -> Self::Future;
+    fn call(
+        self,
+        client: Client<S>,
+        ctx: Arc<ConsumerContext>,
+        delivery: Arc<Delivery>,
+    ) -> Self::Future;
 }
 
 impl<S, F, Fut, Res> ConsumerHandler<S, ()> for F
@@ -58,10 +68,11 @@ where
     Fut: Future<Output = Res> + Send,
     Res: IntoConsumerHandlerResult,
 {
-    type Future = Pin<Box<dyn Future<Output = ConsumerHandlerResult> + Send>>;
+    type Value = Res::Value;
+    type Future = Pin<Box<dyn Future<Output = ConsumerHandlerResult<Res::Value>> + Send>>;
 
-    fn call(self, _: Client<S>, _: Arc<Delivery>) -> Self::Future {
-        Box::pin(async move { self().await.into_consumer_handler_result() })
+    fn call(self, _: Client<S>, _: Arc<ConsumerContext>, _: Arc<Delivery>) -> Self::Future {
+        Box::pin(async move { timed(Instant::now(), self()).await })
     }
 }
 
@@ -73,16 +84,23 @@ where
     Res: IntoConsumerHandlerResult,
     P1: FromDeliveryData<S> + Send,
 {
-    type Future = Pin<Box<dyn Future<Output = ConsumerHandlerResult> + Send>>;
+    type Value = Res::Value;
+    type Future = Pin<Box<dyn Future<Output = ConsumerHandlerResult<Res::Value>> + Send>>;
 
-    fn call(self, client: Client<S>, delivery: Arc<Delivery>) -> Self::Future {
+    fn call(
+        self,
+        client: Client<S>,
+        ctx: Arc<ConsumerContext>,
+        delivery: Arc<Delivery>,
+    ) -> Self::Future {
         Box::pin(async move {
-            let p1 = P1::from_delivery_data(&client, &delivery)
+            let extraction_start = Instant::now();
+            let p1 = P1::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P1>().to_string(), err)
                 })?;
-            self(p1).await.into_consumer_handler_result()
+            timed(extraction_start, self(p1)).await
         })
     }
 }
@@ -96,21 +114,28 @@ where
     P1: FromDeliveryData<S> + Send,
     P2: FromDeliveryData<S> + Send,
 {
-    type Future = Pin<Box<dyn Future<Output = ConsumerHandlerResult> + Send>>;
+    type Value = Res::Value;
+    type Future = Pin<Box<dyn Future<Output = ConsumerHandlerResult<Res::Value>> + Send>>;
 
-    fn call(self, client: Client<S>, delivery: Arc<Delivery>) -> Self::Future {
+    fn call(
+        self,
+        client: Client<S>,
+        ctx: Arc<ConsumerContext>,
+        delivery: Arc<Delivery>,
+    ) -> Self::Future {
         Box::pin(async move {
-            let p1 = P1::from_delivery_data(&client, &delivery)
+            let extraction_start = Instant::now();
+            let p1 = P1::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P1>().to_string(), err)
                 })?;
-            let p2 = P2::from_delivery_data(&client, &delivery)
+            let p2 = P2::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P2>().to_string(), err)
                 })?;
-            self(p1, p2).await.into_consumer_handler_result()
+            timed(extraction_start, self(p1, p2)).await
         })
     }
 }
@@ -125,26 +150,33 @@ where
     P2: FromDeliveryData<S> + Send,
     P3: FromDeliveryData<S> + Send,
 {
-    type Future = Pin<Box<dyn Future<Output = ConsumerHandlerResult> + Send>>;
+    type Value = Res::Value;
+    type Future = Pin<Box<dyn Future<Output = ConsumerHandlerResult<Res::Value>> + Send>>;
 
-    fn call(self, client: Client<S>, delivery: Arc<Delivery>) -> Self::Future {
+    fn call(
+        self,
+        client: Client<S>,
+        ctx: Arc<ConsumerContext>,
+        delivery: Arc<Delivery>,
+    ) -> Self::Future {
         Box::pin(async move {
-            let p1 = P1::from_delivery_data(&client, &delivery)
+            let extraction_start = Instant::now();
+            let p1 = P1::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P1>().to_string(), err)
                 })?;
-            let p2 = P2::from_delivery_data(&client, &delivery)
+            let p2 = P2::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P2>().to_string(), err)
                 })?;
-            let p3 = P3::from_delivery_data(&client, &delivery)
+            let p3 = P3::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P3>().to_string(), err)
                 })?;
-            self(p1, p2, p3).await.into_consumer_handler_result()
+            timed(extraction_start, self(p1, p2, p3)).await
         })
     }
 }
@@ -160,31 +192,38 @@ where
     P3: FromDeliveryData<S> + Send,
     P4: FromDeliveryData<S> + Send,
 {
-    type Future = Pin<Box<dyn Future<Output = ConsumerHandlerResult> + Send>>;
+    type Value = Res::Value;
+    type Future = Pin<Box<dyn Future<Output = ConsumerHandlerResult<Res::Value>> + Send>>;
 
-    fn call(self, client: Client<S>, delivery: Arc<Delivery>) -> Self::Future {
+    fn call(
+        self,
+        client: Client<S>,
+        ctx: Arc<ConsumerContext>,
+        delivery: Arc<Delivery>,
+    ) -> Self::Future {
         Box::pin(async move {
-            let p1 = P1::from_delivery_data(&client, &delivery)
+            let extraction_start = Instant::now();
+            let p1 = P1::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P1>().to_string(), err)
                 })?;
-            let p2 = P2::from_delivery_data(&client, &delivery)
+            let p2 = P2::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P2>().to_string(), err)
                 })?;
-            let p3 = P3::from_delivery_data(&client, &delivery)
+            let p3 = P3::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P3>().to_string(), err)
                 })?;
-            let p4 = P4::from_delivery_data(&client, &delivery)
+            let p4 = P4::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P4>().to_string(), err)
                 })?;
-            self(p1, p2, p3, p4).await.into_consumer_handler_result()
+            timed(extraction_start, self(p1, p2, p3, p4)).await
         })
     }
 }
@@ -201,38 +240,43 @@ where
     P4: FromDeliveryData<S> + Send,
     P5: FromDeliveryData<S> + Send,
 {
-    type Future = Pin<Box<dyn Future<Output = ConsumerHandlerResult> + Send>>;
+    type Value = Res::Value;
+    type Future = Pin<Box<dyn Future<Output = ConsumerHandlerResult<Res::Value>> + Send>>;
 
-    fn call(self, client: Client<S>, delivery: Arc<Delivery>) -> Self::Future {
+    fn call(
+        self,
+        client: Client<S>,
+        ctx: Arc<ConsumerContext>,
+        delivery: Arc<Delivery>,
+    ) -> Self::Future {
         Box::pin(async move {
-            let p1 = P1::from_delivery_data(&client, &delivery)
+            let extraction_start = Instant::now();
+            let p1 = P1::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P1>().to_string(), err)
                 })?;
-            let p2 = P2::from_delivery_data(&client, &delivery)
+            let p2 = P2::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P2>().to_string(), err)
                 })?;
-            let p3 = P3::from_delivery_data(&client, &delivery)
+            let p3 = P3::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P3>().to_string(), err)
                 })?;
-            let p4 = P4::from_delivery_data(&client, &delivery)
+            let p4 = P4::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P4>().to_string(), err)
                 })?;
-            let p5 = P5::from_delivery_data(&client, &delivery)
+            let p5 = P5::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P5>().to_string(), err)
                 })?;
-            self(p1, p2, p3, p4, p5)
-                .await
-                .into_consumer_handler_result()
+            timed(extraction_start, self(p1, p2, p3, p4, p5)).await
         })
     }
 }
@@ -250,43 +294,48 @@ where
     P5: FromDeliveryData<S> + Send,
     P6: FromDeliveryData<S> + Send,
 {
-    type Future = Pin<Box<dyn Future<Output = ConsumerHandlerResult> + Send>>;
+    type Value = Res::Value;
+    type Future = Pin<Box<dyn Future<Output = ConsumerHandlerResult<Res::Value>> + Send>>;
 
-    fn call(self, client: Client<S>, delivery: Arc<Delivery>) -> Self::Future {
+    fn call(
+        self,
+        client: Client<S>,
+        ctx: Arc<ConsumerContext>,
+        delivery: Arc<Delivery>,
+    ) -> Self::Future {
         Box::pin(async move {
-            let p1 = P1::from_delivery_data(&client, &delivery)
+            let extraction_start = Instant::now();
+            let p1 = P1::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P1>().to_string(), err)
                 })?;
-            let p2 = P2::from_delivery_data(&client, &delivery)
+            let p2 = P2::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P2>().to_string(), err)
                 })?;
-            let p3 = P3::from_delivery_data(&client, &delivery)
+            let p3 = P3::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P3>().to_string(), err)
                 })?;
-            let p4 = P4::from_delivery_data(&client, &delivery)
+            let p4 = P4::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P4>().to_string(), err)
                 })?;
-            let p5 = P5::from_delivery_data(&client, &delivery)
+            let p5 = P5::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P5>().to_string(), err)
                 })?;
-            let p6 = P6::from_delivery_data(&client, &delivery)
+            let p6 = P6::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P6>().to_string(), err)
                 })?;
-            self(p1, p2, p3, p4, p5, p6)
-                .await
-                .into_consumer_handler_result()
+            timed(extraction_start, self(p1, p2, p3, p4, p5, p6)).await
         })
     }
 }
@@ -306,48 +355,53 @@ where
     P6: FromDeliveryData<S> + Send,
     P7: FromDeliveryData<S> + Send,
 {
-    type Future = Pin<Box<dyn Future<Output = ConsumerHandlerResult> + Send>>;
+    type Value = Res::Value;
+    type Future = Pin<Box<dyn Future<Output = ConsumerHandlerResult<Res::Value>> + Send>>;
 
-    fn call(self, client: Client<S>, delivery: Arc<Delivery>) -> Self::Future {
+    fn call(
+        self,
+        client: Client<S>,
+        ctx: Arc<ConsumerContext>,
+        delivery: Arc<Delivery>,
+    ) -> Self::Future {
         Box::pin(async move {
-            let p1 = P1::from_delivery_data(&client, &delivery)
+            let extraction_start = Instant::now();
+            let p1 = P1::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P1>().to_string(), err)
                 })?;
-            let p2 = P2::from_delivery_data(&client, &delivery)
+            let p2 = P2::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P2>().to_string(), err)
                 })?;
-            let p3 = P3::from_delivery_data(&client, &delivery)
+            let p3 = P3::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P3>().to_string(), err)
                 })?;
-            let p4 = P4::from_delivery_data(&client, &delivery)
+            let p4 = P4::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P4>().to_string(), err)
                 })?;
-            let p5 = P5::from_delivery_data(&client, &delivery)
+            let p5 = P5::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P5>().to_string(), err)
                 })?;
-            let p6 = P6::from_delivery_data(&client, &delivery)
+            let p6 = P6::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P6>().to_string(), err)
                 })?;
-            let p7 = P7::from_delivery_data(&client, &delivery)
+            let p7 = P7::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P7>().to_string(), err)
                 })?;
-            self(p1, p2, p3, p4, p5, p6, p7)
-                .await
-                .into_consumer_handler_result()
+            timed(extraction_start, self(p1, p2, p3, p4, p5, p6, p7)).await
         })
     }
 }
@@ -368,53 +422,105 @@ where
     P7: FromDeliveryData<S> + Send,
     P8: FromDeliveryData<S> + Send,
 {
-    type Future = Pin<Box<dyn Future<Output = ConsumerHandlerResult> + Send>>;
+    type Value = Res::Value;
+    type Future = Pin<Box<dyn Future<Output = ConsumerHandlerResult<Res::Value>> + Send>>;
 
-    fn call(self, client: Client<S>, delivery: Arc<Delivery>) -> Self::Future {
+    fn call(
+        self,
+        client: Client<S>,
+        ctx: Arc<ConsumerContext>,
+        delivery: Arc<Delivery>,
+    ) -> Self::Future {
         Box::pin(async move {
-            let p1 = P1::from_delivery_data(&client, &delivery)
+            let extraction_start = Instant::now();
+            let p1 = P1::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P1>().to_string(), err)
                 })?;
-            let p2 = P2::from_delivery_data(&client, &delivery)
+            let p2 = P2::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P2>().to_string(), err)
                 })?;
-            let p3 = P3::from_delivery_data(&client, &delivery)
+            let p3 = P3::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P3>().to_string(), err)
                 })?;
-            let p4 = P4::from_delivery_data(&client, &delivery)
+            let p4 = P4::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P4>().to_string(), err)
                 })?;
-            let p5 = P5::from_delivery_data(&client, &delivery)
+            let p5 = P5::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P5>().to_string(), err)
                 })?;
-            let p6 = P6::from_delivery_data(&client, &delivery)
+            let p6 = P6::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P6>().to_string(), err)
                 })?;
-            let p7 = P7::from_delivery_data(&client, &delivery)
+            let p7 = P7::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P7>().to_string(), err)
                 })?;
-            let p8 = P8::from_delivery_data(&client, &delivery)
+            let p8 = P8::from_delivery_data(&client, &ctx, &delivery)
                 .await
                 .map_err(|err| {
                     ConsumerHandlerError::Extractor(std::any::type_name::<P8>().to_string(), err)
                 })?;
-            self(p1, p2, p3, p4, p5, p6, p7, p8)
-                .await
-                .into_consumer_handler_result()
+            timed(extraction_start, self(p1, p2, p3, p4, p5, p6, p7, p8)).await
         })
     }
 }
+
+/// Extract `P` from the delivery, for handlers generated by the `consumer` macro.
+///
+/// # Errors
+/// If the extractor failed.
+#[cfg(feature = "macros")]
+#[allow(private_bounds)]
+pub async fn extract<S, P>(
+    client: &Client<S>,
+    ctx: &ConsumerContext,
+    delivery: &Arc<Delivery>,
+) -> ConsumerHandlerResult<P>
+where
+    S: Clone + Send + Sync + 'static,
+    P: FromDeliveryData<S>,
+{
+    P::from_delivery_data(client, ctx, delivery)
+        .await
+        .map_err(|err| ConsumerHandlerError::Extractor(std::any::type_name::<P>().to_string(), err))
+}
+
+/// Records how long the extractors took since `extraction_start` on the delivery span, then
+/// runs the handler and records how long it took. Recording is a no-op if the span is disabled.
+///
+/// # Errors
+/// If the handler failed.
+pub async fn timed<Fut, Res>(
+    extraction_start: Instant,
+    handler: Fut,
+) -> ConsumerHandlerResult<Res::Value>
+where
+    Fut: Future<Output = Res>,
+    Res: IntoConsumerHandlerResult,
+{
+    let span = tracing::Span::current();
+    span.record(
+        "extraction_time",
+        tracing::field::debug(extraction_start.elapsed()),
+    );
+    let handler_start = Instant::now();
+    let res = handler.await.into_consumer_handler_result();
+    span.record(
+        "handler_time",
+        tracing::field::debug(handler_start.elapsed()),
+    );
+    res
+}
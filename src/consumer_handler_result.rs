@@ -7,34 +7,72 @@ pub enum ConsumerHandlerError {
     /// An extractor has failed.
     #[error("{0} extractor failed: {1}")]
     Extractor(String, anyhow::Error),
+    /// Transforming the delivery body before extraction has failed.
+    #[error("transforming the body failed: {0}")]
+    Transform(anyhow::Error),
+    /// Committing the value produced by the handler has failed, see
+    /// [`crate::basic_consume_builder::BasicConsumeBuilder::consume_with_commit`].
+    #[error("commit failed: {0}")]
+    Commit(anyhow::Error),
+    /// The delivery is older than the maximum message age, see
+    /// [`crate::basic_consume_builder::BasicConsumeBuilder::max_message_age`].
+    #[error("stale delivery: {0}")]
+    Stale(anyhow::Error),
     /// The consumer has timed out.
     #[error("timeout of {0:?} reached")]
     Timeout(Duration),
+    /// The handler panicked, with the panic message if it was a string.
+    #[error("handler panicked: {0}")]
+    Panicked(String),
     /// Something else went wrong.
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
 
-/// Concrete [`ConsumerHandlerResult`].
-pub(super) type ConsumerHandlerResult = Result<(), ConsumerHandlerError>;
+impl ConsumerHandlerError {
+    /// The failed extractor or check and its error, if the delivery is quarantined instead of
+    /// dead-lettered, see [`crate::basic_consume_builder::BasicConsumeBuilder::quarantine_queue`].
+    pub(super) fn extraction_failure(&self) -> Option<(&str, &anyhow::Error)> {
+        match self {
+            Self::Extractor(extractor, err) => Some((extractor, err)),
+            Self::Transform(err) => Some(("transform_body", err)),
+            Self::Stale(err) => Some(("max_message_age", err)),
+            Self::Commit(_) | Self::Timeout(_) | Self::Panicked(_) | Self::Other(_) => None,
+        }
+    }
+}
+
+/// Concrete [`ConsumerHandlerResult`], `T` is the value a handler produces.
+pub(super) type ConsumerHandlerResult<T = ()> = Result<T, ConsumerHandlerError>;
 
 /// Transform other values into a [`ConsumerHandlerResult`].
-pub(super) trait IntoConsumerHandlerResult {
+pub trait IntoConsumerHandlerResult {
+    /// The value a handler produces on success.
+    type Value: Send + 'static;
+
     /// Transform other values into a [`ConsumerHandlerResult`].
-    fn into_consumer_handler_result(self) -> ConsumerHandlerResult;
+    ///
+    /// # Errors
+    /// If the value represents a failed handler.
+    fn into_consumer_handler_result(self) -> ConsumerHandlerResult<Self::Value>;
 }
 
 impl IntoConsumerHandlerResult for () {
+    type Value = ();
+
     fn into_consumer_handler_result(self) -> ConsumerHandlerResult {
         Ok(self)
     }
 }
 
-impl<E> IntoConsumerHandlerResult for Result<(), E>
+impl<T, E> IntoConsumerHandlerResult for Result<T, E>
 where
+    T: Send + 'static,
     E: Into<anyhow::Error>,
 {
-    fn into_consumer_handler_result(self) -> ConsumerHandlerResult {
+    type Value = T;
+
+    fn into_consumer_handler_result(self) -> ConsumerHandlerResult<T> {
         self.map_err(|err| ConsumerHandlerError::Other(err.into()))
     }
 }
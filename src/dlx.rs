@@ -1,18 +1,140 @@
 //! Deadlettering implementation.
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+
 use lapin::message::Delivery;
+use lapin::options::ExchangeDeclareOptions;
+use lapin::types::{AMQPValue, FieldTable};
+use lapin::ExchangeKind;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+use crate::basic_publish_builder::{PublishError, Published};
+use crate::message::{self, DeserializeError, SerializeError};
+use crate::Client;
+
 /// Deadlettering exchange, the `dlx handler` is subscribed to.
 pub const EXCHANGE_NAME: &str = "amq.direct";
 /// Routing key for dead messages the `dlx` handler is subscribed to.
 pub const ROUTING_KEY_DEAD_LETTER: &str = "dlx.dead_letter";
 /// Routing key for message errors the `dlx` handler is subscribed to.
 pub const ROUTING_KEY_ERROR: &str = "dlx.error";
+/// Routing key for messages that permanently failed, after exceeding the maximum number of
+/// deliveries, see [`crate::BasicConsumeBuilder::max_deliveries`].
+pub const ROUTING_KEY_PARKED: &str = "dlx.parked";
+/// Header quorum queues count previous deliveries of a message in.
+pub const HEADER_DELIVERY_COUNT: &str = "x-delivery-count";
 /// Argument for queues to set their deadletter exchange.
 pub const QUEUE_ARGUMENT_KEY_EXCHANGE_NAME: &str = "x-dead-letter-exchange";
 /// Argument for queues to set their deadletter routing key.
 pub const QUEUE_ARGUMENT_KEY_ROUTING_KEY: &str = "x-dead-letter-routing-key";
 
+/// Declares the deadlettering exchange [`EXCHANGE_NAME`], see [`Client::new`].
+///
+/// # Errors
+/// If the broker refuses the declaration, e.g. because the exchange exists with different
+/// properties.
+pub(super) async fn declare_exchange(chan: &lapin::Channel) -> Result<(), lapin::Error> {
+    chan.exchange_declare(
+        EXCHANGE_NAME,
+        ExchangeKind::Direct,
+        ExchangeDeclareOptions {
+            durable: true,
+            ..ExchangeDeclareOptions::default()
+        },
+        FieldTable::default(),
+    )
+    .await
+}
+
+/// Encoding of the [`ErrorData`] and [`ParkedData`] published to the `dlx`, see
+/// [`Client::with_error_data_encoding`]. The `content-type` property of the published message
+/// names the encoding, so the `dlx handler` can decode it with [`Self::from_content_type`].
+/// All encodings are self-describing, so the tagged [`ErrorDataError`] survives them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorDataEncoding {
+    /// `application/json`, the default.
+    #[default]
+    Json,
+    /// `application/cbor`, compact binary encoding.
+    #[cfg(feature = "cbor")]
+    Cbor,
+    /// `application/msgpack`, compact binary encoding. Structs are encoded as maps with their
+    /// field names, as tagged enums require.
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+}
+
+impl ErrorDataEncoding {
+    /// The `content-type` of payloads in this encoding.
+    #[must_use]
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => message::JSON_CONTENT_TYPE,
+            #[cfg(feature = "cbor")]
+            Self::Cbor => "application/cbor",
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => "application/msgpack",
+        }
+    }
+
+    /// The encoding for a `content-type`, payloads without one are JSON.
+    #[must_use]
+    pub fn from_content_type(content_type: Option<&str>) -> Option<Self> {
+        match content_type {
+            None | Some(message::JSON_CONTENT_TYPE) => Some(Self::Json),
+            #[cfg(feature = "cbor")]
+            Some("application/cbor") => Some(Self::Cbor),
+            #[cfg(feature = "msgpack")]
+            Some("application/msgpack") => Some(Self::MessagePack),
+            Some(_) => None,
+        }
+    }
+
+    /// Encode a value.
+    ///
+    /// # Errors
+    /// If the value can not be serialized.
+    pub fn encode<T>(self, value: &T) -> Result<Vec<u8>, SerializeError>
+    where
+        T: Serialize,
+    {
+        match self {
+            Self::Json => message::Payload(value).serialize(),
+            #[cfg(feature = "cbor")]
+            Self::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(value, &mut bytes)
+                    .map_err(|err| SerializeError::from(anyhow::Error::from(err)))?;
+                Ok(bytes)
+            }
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => rmp_serde::to_vec_named(value)
+                .map_err(|err| SerializeError::from(anyhow::Error::from(err))),
+        }
+    }
+
+    /// Decode a value.
+    ///
+    /// # Errors
+    /// If the bytes can not be deserialized into `T`.
+    pub fn decode<T>(self, bytes: &[u8]) -> Result<T, DeserializeError>
+    where
+        T: DeserializeOwned,
+    {
+        match self {
+            Self::Json => message::from_json_slice(bytes),
+            #[cfg(feature = "cbor")]
+            Self::Cbor => ciborium::from_reader(bytes)
+                .map_err(|err| DeserializeError::from(anyhow::Error::from(err))),
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => rmp_serde::from_slice(bytes)
+                .map_err(|err| DeserializeError::from(anyhow::Error::from(err))),
+        }
+    }
+}
+
 /// Payload for error messages for the `dlx handler` to pick up.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -40,6 +162,8 @@ impl ErrorData {
             origin: ErrorDataOrigin {
                 message_id,
                 queue_name,
+                exchange: Some(delivery.exchange.to_string()),
+                routing_key: Some(delivery.routing_key.to_string()),
             },
             error: ErrorDataError::Rs {
                 name: std::any::type_name::<E>().to_string(),
@@ -47,6 +171,121 @@ impl ErrorData {
             },
         }
     }
+
+    /// Checks that the error data names the queue and the error, as the `dlx handler` expects.
+    ///
+    /// # Errors
+    /// See [`InvalidErrorDataError`].
+    pub fn validate(&self) -> Result<(), InvalidErrorDataError> {
+        if self.origin.queue_name.is_empty() {
+            return Err(InvalidErrorDataError("the origin queue name is empty"));
+        }
+        let (ErrorDataError::Rs { name, message } | ErrorDataError::Js { name, message, .. }) =
+            &self.error;
+        if name.is_empty() {
+            return Err(InvalidErrorDataError("the error name is empty"));
+        }
+        if message.is_empty() {
+            return Err(InvalidErrorDataError("the error message is empty"));
+        }
+        Ok(())
+    }
+}
+
+/// When [`ErrorData`] is missing required information.
+#[derive(Debug, thiserror::Error)]
+#[error("invalid error data: {0}")]
+pub struct InvalidErrorDataError(&'static str);
+
+/// Payload for messages that permanently failed, published to the parking lot instead of the
+/// error stream, see [`ROUTING_KEY_PARKED`]. Unlike [`ErrorData`], these need a human to look
+/// at them, as retrying did not help.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParkedData {
+    /// Message id and origin queue of the message that permanently failed.
+    pub origin: ErrorDataOrigin,
+    /// The error of the last delivery.
+    pub error: ErrorDataError,
+    /// How often the message was delivered.
+    pub deliveries: u64,
+}
+
+impl ParkedData {
+    /// Create a new instance.
+    pub fn new<E>(queue_name: String, delivery: &Delivery, err: &E, deliveries: u64) -> Self
+    where
+        E: std::error::Error,
+    {
+        let ErrorData { origin, error } = ErrorData::new(queue_name, delivery, err);
+        Self {
+            origin,
+            error,
+            deliveries,
+        }
+    }
+}
+
+/// Maximum number of messages [`FailureCounter`] tracks, it is reset once exceeded.
+const MAX_TRACKED_FAILURES: usize = 10_000;
+
+/// Counts failed deliveries per message id for queues that do not count deliveries themselves,
+/// like classic queues, see [`deliveries`].
+#[derive(Debug, Default)]
+pub(super) struct FailureCounter(
+    /// Failed deliveries by message id.
+    Mutex<HashMap<String, u64>>,
+);
+
+impl FailureCounter {
+    /// Record a failed delivery of the message, returns how often it failed so far.
+    fn record(&self, message_id: &str) -> u64 {
+        let mut failures = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        // Messages that succeeded after failing are never removed, bound the memory instead.
+        if failures.len() >= MAX_TRACKED_FAILURES && !failures.contains_key(message_id) {
+            failures.clear();
+        }
+        let count = failures.entry(message_id.to_string()).or_default();
+        *count += 1;
+        *count
+    }
+
+    /// Forget the failures of the message, once it is not retried anymore.
+    pub(super) fn forget(&self, message_id: &str) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(message_id);
+    }
+}
+
+/// How often a message was delivered, including the given failed delivery. Quorum queues count
+/// previous deliveries in the [`HEADER_DELIVERY_COUNT`] header. On other queues, like classic
+/// queues, the failures of a message are counted by its message id in `failures`, local to the
+/// consumer. A redelivered message without a message id can not be counted, it counts as `max`.
+pub(super) fn deliveries(delivery: &Delivery, max: u64, failures: &FailureCounter) -> u64 {
+    let count = delivery
+        .properties
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.inner().get(HEADER_DELIVERY_COUNT))
+        .and_then(|value| match value {
+            AMQPValue::LongLongInt(count) => u64::try_from(*count).ok(),
+            AMQPValue::LongInt(count) => u64::try_from(*count).ok(),
+            AMQPValue::LongUInt(count) => Some(u64::from(*count)),
+            _ => None,
+        });
+    if let Some(count) = count {
+        return count.saturating_add(1);
+    }
+    match delivery.properties.message_id() {
+        // A redelivery failed at least once before, maybe on another consumer.
+        Some(message_id) => failures
+            .record(message_id.as_str())
+            .max(if delivery.redelivered { 2 } else { 1 }),
+        None if delivery.redelivered => max,
+        None => 1,
+    }
 }
 
 /// Describe where an error originated from.
@@ -57,6 +296,12 @@ pub struct ErrorDataOrigin {
     pub message_id: Option<String>,
     /// Queue the message got consumed from.
     pub queue_name: String,
+    /// Exchange the message was originally published to, missing in error data of older
+    /// versions.
+    pub exchange: Option<String>,
+    /// Routing key the message was originally published with, missing in error data of older
+    /// versions.
+    pub routing_key: Option<String>,
 }
 
 /// Language specific error data.
@@ -83,3 +328,198 @@ pub enum ErrorDataError {
         stacktrace: String,
     },
 }
+
+/// Publish error data to the `dlx` with the routing key, in the encoding of the client.
+pub(super) async fn publish_error_data<S, T>(
+    client: &Client<S>,
+    routing_key: &str,
+    error_data: &T,
+) -> Result<Published, PublishError>
+where
+    S: Clone + Send + Sync + 'static,
+    T: Serialize,
+{
+    let encoding = client.error_data_encoding;
+    let payload = encoding.encode(error_data).map_err(anyhow::Error::from)?;
+    client
+        .basic_publish_builder(EXCHANGE_NAME, routing_key)
+        .props(|props| props.with_content_type(encoding.content_type().into()))
+        .publish_bytes(&payload)
+        .await
+}
+
+/// Publishes error data to the `dlx` as if a consumer failed, see
+/// [`Client::publish_dead_letter`].
+pub(super) async fn publish_dead_letter<S>(
+    client: &Client<S>,
+    error_data: &ErrorData,
+) -> Result<Published, PublishDeadLetterError>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    error_data.validate()?;
+    publish_error_data(client, ROUTING_KEY_ERROR, error_data)
+        .await
+        .map_err(|err| PublishDeadLetterError::Publish(err.into()))
+}
+
+/// Republishes the payload of a dead-lettered message to the exchange and routing key it was
+/// originally published to, see [`Client::replay_dead_letter`].
+pub(super) async fn replay<S, P>(
+    client: &Client<S>,
+    error_data: &ErrorData,
+    payload: P,
+) -> Result<Published, ReplayError>
+where
+    S: Clone + Send + Sync + 'static,
+    P: Serialize,
+{
+    let (Some(exchange_name), Some(routing_key)) =
+        (&error_data.origin.exchange, &error_data.origin.routing_key)
+    else {
+        return Err(ReplayError::MissingOrigin);
+    };
+
+    // The default exchange always exists and can not be declared.
+    if !exchange_name.is_empty() {
+        let chan = client
+            .get_channel()
+            .await
+            .map_err(|err| ReplayError::Channel(err.into()))?;
+        chan.exchange_declare(
+            exchange_name,
+            ExchangeKind::Direct,
+            ExchangeDeclareOptions {
+                passive: true,
+                ..ExchangeDeclareOptions::default()
+            },
+            FieldTable::default(),
+        )
+        .await
+        .map_err(|err| ReplayError::ExchangeNotFound(exchange_name.clone(), err.into()))?;
+    }
+
+    let mut builder = client.basic_publish_builder(exchange_name, routing_key);
+    if let Some(message_id) = &error_data.origin.message_id {
+        builder = builder.props(|props| props.with_message_id(message_id.as_str().into()));
+    }
+    builder
+        .publish(payload)
+        .await
+        .map_err(|err| ReplayError::Publish(err.into()))
+}
+
+/// Errors that can occur when publishing error data to the `dlx` directly.
+#[derive(Debug, thiserror::Error)]
+pub enum PublishDeadLetterError {
+    /// The error data is invalid.
+    #[error(transparent)]
+    Invalid(#[from] InvalidErrorDataError),
+    /// Publishing the error data failed.
+    #[error("publishing the error data failed: {0}")]
+    Publish(anyhow::Error),
+}
+
+/// Errors that can occur when replaying a dead-lettered message.
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayError {
+    /// The error data does not record the origin exchange and routing key.
+    #[error("error data is missing the origin exchange or routing key")]
+    MissingOrigin,
+    /// Getting a channel failed.
+    #[error("getting a channel failed: {0}")]
+    Channel(anyhow::Error),
+    /// The origin exchange does not exist (anymore).
+    #[error("origin exchange {0} does not exist: {1}")]
+    ExchangeNotFound(String, anyhow::Error),
+    /// Publishing the message failed.
+    #[error("republishing the message failed: {0}")]
+    Publish(anyhow::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ErrorData, ErrorDataEncoding, ErrorDataError, ErrorDataOrigin};
+
+    /// Every encoding enabled by the features of the build.
+    fn encodings() -> Vec<ErrorDataEncoding> {
+        vec![
+            ErrorDataEncoding::Json,
+            #[cfg(feature = "cbor")]
+            ErrorDataEncoding::Cbor,
+            #[cfg(feature = "msgpack")]
+            ErrorDataEncoding::MessagePack,
+        ]
+    }
+
+    /// Error data with a JavaScript error.
+    fn js_error_data() -> ErrorData {
+        ErrorData {
+            origin: ErrorDataOrigin {
+                message_id: Some("message".into()),
+                queue_name: "queue".into(),
+                exchange: None,
+                routing_key: Some("key".into()),
+            },
+            error: ErrorDataError::Js {
+                name: "TypeError".into(),
+                message: "failed".into(),
+                stacktrace: "at handler".into(),
+            },
+        }
+    }
+
+    #[test]
+    fn tagged_errors_survive_every_encoding() {
+        for encoding in encodings() {
+            let bytes = encoding.encode(&js_error_data()).expect("encoding failed");
+            let decoded: ErrorData = encoding.decode(&bytes).expect("decoding failed");
+
+            assert_eq!(decoded.origin.message_id.as_deref(), Some("message"));
+            assert_eq!(decoded.origin.queue_name, "queue");
+            assert_eq!(decoded.origin.exchange, None);
+            assert_eq!(decoded.origin.routing_key.as_deref(), Some("key"));
+            let ErrorDataError::Js {
+                name,
+                message,
+                stacktrace,
+            } = decoded.error
+            else {
+                panic!("{encoding:?} lost the error language");
+            };
+            assert_eq!(
+                (name.as_str(), message.as_str(), stacktrace.as_str()),
+                ("TypeError", "failed", "at handler")
+            );
+        }
+    }
+
+    #[test]
+    fn json_error_data_is_tagged_with_the_language() {
+        let bytes = ErrorDataEncoding::Json
+            .encode(&js_error_data())
+            .expect("encoding failed");
+        let value: serde_json::Value = serde_json::from_slice(&bytes).expect("invalid JSON");
+
+        assert_eq!(value["error"]["lang"], "js");
+        assert_eq!(value["origin"]["queueName"], "queue");
+    }
+
+    #[test]
+    fn encodings_round_trip_their_content_type() {
+        for encoding in encodings() {
+            assert_eq!(
+                ErrorDataEncoding::from_content_type(Some(encoding.content_type())),
+                Some(encoding)
+            );
+        }
+        assert_eq!(
+            ErrorDataEncoding::from_content_type(None),
+            Some(ErrorDataEncoding::Json)
+        );
+        assert_eq!(
+            ErrorDataEncoding::from_content_type(Some("text/plain")),
+            None
+        );
+    }
+}
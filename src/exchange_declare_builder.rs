@@ -21,6 +21,8 @@ where
     opts: ExchangeDeclareOptions,
     /// Additional declare args.
     args: FieldTable,
+    /// Verify the exchange after declaring with `nowait`.
+    verify: bool,
 }
 
 impl<'a, S> ExchangeDeclareBuilder<'a, S>
@@ -42,6 +44,7 @@ where
                 passive: false,
             },
             args: FieldTable::default(),
+            verify: false,
         }
     }
 
@@ -66,13 +69,23 @@ where
         self
     }
 
-    /// Defaults to `false`.
+    /// Defaults to `false`. When `false`, [`Self::declare`] waits for the broker to confirm the
+    /// declaration, when `true` the declaration is fire-and-forget and errors are not surfaced.
     #[must_use]
     pub fn nowait(mut self, nowait: bool) -> Self {
         self.opts.nowait = nowait;
         self
     }
 
+    /// Defaults to `false`. When set together with `nowait`, the exchange is verified by a passive
+    /// declare on the same channel, waiting for the broker to confirm it exists. This surfaces
+    /// errors of the initial declare, while keeping the declare itself fire-and-forget.
+    #[must_use]
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
     /// Defaults to `false`.
     #[must_use]
     pub fn passive(mut self, passive: bool) -> Self {
@@ -99,9 +112,23 @@ where
             .get_channel()
             .await
             .map_err(|err| DeclareError(err.into()))?;
-        chan.exchange_declare(self.name, self.kind, self.opts, self.args)
+        chan.exchange_declare(self.name, self.kind.clone(), self.opts, self.args)
+            .await
+            .map_err(|err| DeclareError(err.into()))?;
+        if self.verify && self.opts.nowait {
+            chan.exchange_declare(
+                self.name,
+                self.kind,
+                ExchangeDeclareOptions {
+                    passive: true,
+                    ..ExchangeDeclareOptions::default()
+                },
+                FieldTable::default(),
+            )
             .await
-            .map_err(|err| DeclareError(err.into()))
+            .map_err(|err| DeclareError(anyhow::format_err!("verifying exchange failed: {err}")))?;
+        }
+        Ok(())
     }
 }
 
@@ -110,7 +137,7 @@ where
 #[error(transparent)]
 pub struct DeclareError(#[from] anyhow::Error);
 
-impl<'a, S> BuilderArgs for ExchangeDeclareBuilder<'a, S>
+impl<S> BuilderArgs for ExchangeDeclareBuilder<'_, S>
 where
     S: Clone + Send + Sync + 'static,
 {
@@ -0,0 +1,113 @@
+//! [`Health`] reports of the connection, see [`Client::watch_health`].
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::client::PingError;
+use crate::Client;
+
+/// Health of the connection, reported periodically by [`Client::watch_health`].
+#[derive(Debug)]
+pub enum Health {
+    /// The broker answered the last ping.
+    Healthy {
+        /// Round-trip time of the ping.
+        latency: Duration,
+    },
+    /// The broker did not answer the last pings in time, or they failed.
+    Degraded {
+        /// Consecutive failed pings.
+        missed: u32,
+        /// Why the last ping failed.
+        error: PingError,
+    },
+    /// The connection is closed. Reported before every ping while the client reconnects, see
+    /// [`Client::with_reconnect`], otherwise it is the last report.
+    Closed,
+}
+
+/// State of the connection, see [`Client::connection_state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// The connection is open and no consumer is recovering.
+    Connected,
+    /// The connection is being established, or consumers are recovering from channel failures.
+    Recovering,
+    /// The connection is closed or failed. If the client reconnects, see
+    /// [`Client::with_reconnect`], it is replaced on the next use of the client, otherwise it
+    /// stays closed and a new client has to be created.
+    Closed,
+}
+
+impl ConnectionState {
+    /// The state of the connection, given the `lapin` state and the number of recovering
+    /// consumers.
+    pub(super) fn new(state: &lapin::ConnectionState, recovering: usize) -> Self {
+        match state {
+            lapin::ConnectionState::Connected if recovering == 0 => Self::Connected,
+            lapin::ConnectionState::Initial
+            | lapin::ConnectionState::Connecting
+            | lapin::ConnectionState::Connected => Self::Recovering,
+            lapin::ConnectionState::Closing
+            | lapin::ConnectionState::Closed
+            | lapin::ConnectionState::Error => Self::Closed,
+        }
+    }
+}
+
+/// Counts a consumer as recovering until dropped, see [`ConnectionState::Recovering`].
+pub(super) struct Recovering(
+    /// Number of recovering consumers of the client.
+    Arc<AtomicUsize>,
+);
+
+impl Recovering {
+    /// Count a consumer as recovering.
+    pub(super) fn start(recovering: &Arc<AtomicUsize>) -> Self {
+        recovering.fetch_add(1, Ordering::SeqCst);
+        Self(recovering.clone())
+    }
+}
+
+impl Drop for Recovering {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Ping the broker every `interval` and report the [`Health`] of the connection. Stops once the
+/// connection is closed, unless the client reconnects.
+pub(super) async fn watch<S, F>(client: Client<S>, interval: Duration, on_health: F)
+where
+    S: Clone + Send + Sync + 'static,
+    F: Fn(Health) + Send + Sync + 'static,
+{
+    let mut missed = 0;
+    loop {
+        let start = Instant::now();
+        if !client.status().connected() {
+            on_health(Health::Closed);
+            // Without reconnecting the connection never comes back, otherwise the ping
+            // reconnects.
+            if !client.connector.reconnects() {
+                return;
+            }
+        }
+        match client.ping(interval).await {
+            Ok(()) => {
+                missed = 0;
+                on_health(Health::Healthy {
+                    latency: start.elapsed(),
+                });
+            }
+            Err(error) => {
+                missed += 1;
+                on_health(Health::Degraded { missed, error });
+            }
+        }
+        client
+            .runtime
+            .sleep(interval.saturating_sub(start.elapsed()))
+            .await;
+    }
+}
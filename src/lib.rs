@@ -9,6 +9,8 @@
 )]
 #![allow(clippy::unused_async)]
 
+mod batch_ack;
+mod batch_handler;
 mod builder_arguments;
 mod consumer_handler;
 mod consumer_handler_result;
@@ -16,23 +18,49 @@ pub mod basic_consume_builder;
 pub mod basic_publish_builder;
 pub mod channel_pool;
 pub mod client;
+pub mod consumer_handle;
 pub mod consumer_extract;
 pub mod dlx;
 pub mod exchange_declare_builder;
+pub mod health;
 pub mod message;
+pub mod observer;
+pub mod outbox;
+pub mod priority;
+pub mod prioritized_consumer;
+pub mod publisher;
 pub mod queue;
 pub mod queue_bind_builder;
 pub mod queue_declare_builder;
 pub mod queue_delete_builder;
+pub mod queue_migration;
 pub mod queue_purge_builder;
 pub mod queue_unbind_builder;
+pub mod rate_limiter;
+pub mod reconnect;
+pub mod reconnecting_publisher;
 pub mod rpc_builder;
+pub mod runtime;
+#[cfg(feature = "testcontainers")]
+pub mod testing;
+pub mod tls;
+pub mod topology;
 pub use builder_arguments::BuilderArgs;
 pub use channel_pool::Channel;
 pub use client::Client;
+pub use consumer_handle::ConsumerHandle;
 pub use consumer_handler::ConsumerHandler;
 pub use lapin;
+pub use observer::Observer;
+pub use priority::Priority;
+pub use publisher::Publisher;
 pub use queue::Queue;
+pub use rate_limiter::RateLimiter;
+pub use reconnecting_publisher::ReconnectingPublisher;
+pub use runtime::Runtime;
+pub use topology::Topology;
+#[cfg(feature = "macros")]
+pub use hare_macros::{consumer, FromHeaders};
 use basic_consume_builder::BasicConsumeBuilder;
 use basic_publish_builder::BasicPublishBuilder;
 use channel_pool::ChannelPool;
@@ -49,6 +77,16 @@ use rpc_builder::RpcBuilder;
 
 pub mod util;
 
+/// Items the macros of the `macros` feature expand to, not part of the public API.
+#[cfg(feature = "macros")]
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::basic_consume_builder::ConsumerContext;
+    pub use crate::consumer_handler::{extract, timed};
+    pub use crate::consumer_handler_result::{ConsumerHandlerError, IntoConsumerHandlerResult};
+    pub use lapin::message::Delivery;
+}
+
 /// Prelude
 pub mod prelude {
     pub use crate::BuilderArgs as _;
@@ -7,6 +7,39 @@ use uuid::Uuid;
 /// Message id.
 pub type Id = Uuid;
 
+/// Generates the ids of messages published without an explicit id, see
+/// [`crate::Client::with_message_id_generator`].
+pub type IdGenerator = fn() -> Id;
+
+/// Random `UUIDv4` message ids, the default [`IdGenerator`].
+#[must_use]
+pub fn new_v4_id() -> Id {
+    Id::new_v4()
+}
+
+/// Time-ordered `UUIDv7` message ids, sortable by creation time, which improves the index
+/// locality of stored messages.
+#[must_use]
+pub fn new_v7_id() -> Id {
+    Id::now_v7()
+}
+
+/// Header carrying the schema version of the payload, see
+/// [`crate::consumer_extract::SchemaVersion`].
+pub const SCHEMA_VERSION_HEADER: &str = "schema-version";
+
+/// Header carrying the id of the message that caused a message, see
+/// [`crate::basic_publish_builder::BasicPublishBuilder::follows`].
+pub const CAUSATION_ID_HEADER: &str = "causation-id";
+
+/// Header carrying the type of an [`Envelope`]d payload, see
+/// [`crate::basic_publish_builder::BasicPublishBuilder::envelope`].
+pub const MESSAGE_TYPE_HEADER: &str = "message-type";
+
+/// `content-type` of [`Payload`]s, which are serialized as JSON, see
+/// [`crate::basic_publish_builder::BasicPublishBuilder::content_type`].
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
 /// Message payload.
 #[derive(Debug, Clone)]
 pub struct Payload<T>(pub T);
@@ -21,11 +54,37 @@ where
     ///
     /// Possible errors originate from `serde_json`.
     pub fn deserialize(bytes: &'de [u8]) -> Result<Self, DeserializeError> {
-        let inner = serde_json::from_slice(bytes).map_err(|err| DeserializeError(err.into()))?;
+        let inner = from_json_slice(bytes)?;
         Ok(Self(inner))
     }
 }
 
+/// Deserializes a value of type `T` from JSON bytes. With the `serde_path_to_error` feature
+/// enabled, errors include the path of the field that failed to deserialize.
+///
+/// # Errors
+///
+/// Possible errors originate from `serde_json`.
+pub fn from_json_slice<'de, T>(bytes: &'de [u8]) -> Result<T, DeserializeError>
+where
+    T: Deserialize<'de>,
+{
+    #[cfg(feature = "serde_path_to_error")]
+    {
+        let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+        let value = serde_path_to_error::deserialize(&mut deserializer)
+            .map_err(|err| DeserializeError(err.into()))?;
+        deserializer
+            .end()
+            .map_err(|err| DeserializeError(err.into()))?;
+        Ok(value)
+    }
+    #[cfg(not(feature = "serde_path_to_error"))]
+    {
+        serde_json::from_slice(bytes).map_err(|err| DeserializeError(err.into()))
+    }
+}
+
 /// When deserializing message payload fails.
 #[derive(Debug, thiserror::Error)]
 #[error(transparent)]
@@ -45,11 +104,71 @@ where
     }
 }
 
+/// Several message types sharing a queue, usually an enum with a variant per type, see
+/// [`crate::consumer_extract::TypedBody`].
+pub trait MessageTypes: Sized {
+    /// Deserialize the body of a message of the given type into the matching variant, e.g. with
+    /// [`from_json_slice`]. Returns `None` if the type is unknown.
+    fn from_typed_body(message_type: &str, body: &[u8]) -> Option<Result<Self, DeserializeError>>;
+}
+
 /// When serializing message payload fails.
 #[derive(Debug, thiserror::Error)]
 #[error(transparent)]
 pub struct SerializeError(#[from] anyhow::Error);
 
+/// Common message envelope, wrapping a payload with its type and version.
+///
+/// ```json
+/// { "type": "user.created", "version": 1, "payload": { ... } }
+/// ```
+///
+/// When used as extractor, `type` and `version` are validated against the expectations set with
+/// [`crate::basic_consume_builder::BasicConsumeBuilder::envelope`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Envelope<T> {
+    /// Type of the payload.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Version of the payloads schema.
+    pub version: u32,
+    /// The actual payload.
+    pub payload: T,
+}
+
+impl<T> Envelope<T> {
+    /// Validate the envelopes type and version against the expected ones.
+    ///
+    /// # Errors
+    ///
+    /// If the type or version do not match.
+    pub fn validate(&self, kind: &str, version: u32) -> Result<(), EnvelopeMismatchError> {
+        if self.kind != kind || self.version != version {
+            return Err(EnvelopeMismatchError {
+                expected_kind: kind.to_string(),
+                expected_version: version,
+                kind: self.kind.clone(),
+                version: self.version,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// When the envelopes type or version do not match the expected ones.
+#[derive(Debug, thiserror::Error)]
+#[error("expected envelope {expected_kind} v{expected_version}, got {kind} v{version}")]
+pub struct EnvelopeMismatchError {
+    /// Expected type.
+    pub expected_kind: String,
+    /// Expected version.
+    pub expected_version: u32,
+    /// Actual type.
+    pub kind: String,
+    /// Actual version.
+    pub version: u32,
+}
+
 impl<T> Deref for Payload<T> {
     type Target = T;
 
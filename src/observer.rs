@@ -0,0 +1,39 @@
+//! [`Observer`] trait definition, integrate custom telemetry.
+
+use std::time::Duration;
+
+use lapin::message::Delivery;
+use lapin::BasicProperties;
+
+/// Hooks that get invoked by the [`crate::Client`] at relevant sites, register an implementation
+/// with [`crate::Client::with_observer`]. Every hook is a no-op by default, so only the relevant
+/// ones need to be implemented.
+pub trait Observer: std::fmt::Debug + Send + Sync + 'static {
+    /// A message was published.
+    fn on_publish(&self, _exchange_name: &str, _routing_key: &str, _props: &BasicProperties) {}
+
+    /// A consumer started processing a delivery.
+    fn on_consume_start(&self, _queue_name: &str, _delivery: &Delivery) {}
+
+    /// A consumer finished processing a delivery.
+    fn on_consume_end(
+        &self,
+        _queue_name: &str,
+        _delivery: &Delivery,
+        _latency: Duration,
+        _was_acked: bool,
+    ) {
+    }
+
+    /// A delivery was nacked, because its handler failed.
+    fn on_nack(&self, _queue_name: &str, _delivery: &Delivery, _err: &dyn std::error::Error) {}
+
+    /// Error data of a failed delivery was published to the dead letter exchange.
+    fn on_dead_letter(&self, _queue_name: &str, _delivery: &Delivery) {}
+}
+
+/// [`Observer`] that does nothing, used by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopObserver;
+
+impl Observer for NoopObserver {}
@@ -0,0 +1,175 @@
+//! Transactional outbox, see [`Client::publish_via_outbox`].
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use lapin::options::BasicPublishOptions;
+use lapin::publisher_confirm::Confirmation;
+use lapin::BasicProperties;
+use serde::Serialize;
+use tracing::{error, warn};
+
+use crate::basic_publish_builder::BasicPublishBuilder;
+use crate::{message, util, Client};
+
+/// A message stored in an [`OutboxStore`], waiting to be published.
+#[derive(Debug, Clone)]
+pub struct OutboxMessage {
+    /// The message id, also set as the `message_id` property.
+    pub id: message::Id,
+    /// The exchange to publish the message on.
+    pub exchange_name: String,
+    /// The routing key to publish the message with.
+    pub routing_key: String,
+    /// The serialized payload.
+    pub payload: Vec<u8>,
+    /// The message properties.
+    pub props: BasicProperties,
+}
+
+/// Persists outgoing messages until they are published, e.g. in a table of the database the
+/// application writes to. Inserting the message in the same transaction as the business data
+/// ensures a message is published if and only if the transaction committed.
+#[async_trait]
+pub trait OutboxStore: Send + Sync + 'static {
+    /// Store a message that is about to be published.
+    async fn insert(&self, message: &OutboxMessage) -> Result<(), anyhow::Error>;
+
+    /// Mark a message as published, it must no longer be returned by [`Self::pending`].
+    async fn mark_sent(&self, id: message::Id) -> Result<(), anyhow::Error>;
+
+    /// At most `limit` messages that were not marked as sent yet, oldest first.
+    async fn pending(&self, limit: usize) -> Result<Vec<OutboxMessage>, anyhow::Error>;
+}
+
+/// Store a message in the outbox, see [`Client::publish_via_outbox`].
+pub(super) async fn insert<S, O, P>(
+    client: &Client<S>,
+    store: &O,
+    exchange_name: &str,
+    routing_key: &str,
+    payload: P,
+) -> Result<message::Id, OutboxError>
+where
+    S: Clone + Send + Sync + 'static,
+    O: OutboxStore + ?Sized,
+    P: Serialize,
+{
+    let id = (client.message_id_generator)();
+    let message = OutboxMessage {
+        id,
+        exchange_name: exchange_name.to_string(),
+        routing_key: routing_key.to_string(),
+        payload: message::Payload(payload)
+            .serialize()
+            .map_err(|err| OutboxError::Serialize(err.into()))?,
+        props: BasicPublishBuilder::default_props(client).with_message_id(id.to_string().into()),
+    };
+    store.insert(&message).await.map_err(OutboxError::Store)?;
+    Ok(id)
+}
+
+/// Publish up to `batch_size` pending messages of the outbox once, see
+/// [`Client::flush_outbox`]. Returns the amount of messages published.
+///
+/// # Errors
+/// If the pending messages can not be loaded. Failed publishes are logged and left for the next
+/// flush.
+pub(super) async fn flush_once<S, O>(
+    client: &Client<S>,
+    store: &O,
+    batch_size: usize,
+) -> Result<usize, OutboxError>
+where
+    S: Clone + Send + Sync + 'static,
+    O: OutboxStore + ?Sized,
+{
+    let messages = store
+        .pending(batch_size)
+        .await
+        .map_err(OutboxError::Store)?;
+    let mut sent = 0;
+    for message in messages {
+        match send(client, store, &message).await {
+            Ok(()) => sent += 1,
+            Err(err) => warn!(id = %message.id, "publishing outbox message failed: {err:#}"),
+        }
+    }
+    Ok(sent)
+}
+
+/// Publish pending messages of the outbox every `interval`, until a stop signal is received, see
+/// [`Client::spawn_outbox_flusher`].
+pub(super) async fn flush<S, O>(
+    client: Client<S>,
+    store: Arc<O>,
+    interval: Duration,
+    batch_size: usize,
+) where
+    S: Clone + Send + Sync + 'static,
+    O: OutboxStore,
+{
+    let flushing = async {
+        loop {
+            // Publishing fails while the connection is down, the messages stay pending and are
+            // retried on the next tick, e.g. once the client reconnected.
+            if let Err(err) = flush_once(&client, &*store, batch_size).await {
+                error!("loading pending outbox messages failed: {err:#}");
+            }
+            client.runtime.sleep(interval).await;
+        }
+    };
+    tokio::select! {
+        () = flushing => {}
+        signal_stop_result = util::signal_stop() => {
+            if let Err(err) = signal_stop_result {
+                error!("outbox flusher stop signal failed: {err}");
+            }
+        }
+    }
+}
+
+/// Publish a stored message on a channel in confirm mode, and mark it as sent once the broker
+/// acknowledged it.
+async fn send<S, O>(
+    client: &Client<S>,
+    store: &O,
+    message: &OutboxMessage,
+) -> Result<(), anyhow::Error>
+where
+    S: Clone + Send + Sync + 'static,
+    O: OutboxStore + ?Sized,
+{
+    let chan = client.get_confirm_channel().await?;
+    client
+        .observer
+        .on_publish(&message.exchange_name, &message.routing_key, &message.props);
+    let confirmation = chan
+        .basic_publish(
+            &message.exchange_name,
+            &message.routing_key,
+            BasicPublishOptions::default(),
+            &message.payload,
+            message.props.clone(),
+        )
+        .await?
+        .await?;
+    match confirmation {
+        Confirmation::Ack(None) => store.mark_sent(message.id).await,
+        other => Err(anyhow::format_err!(
+            "broker did not acknowledge the message: {other:?}"
+        )),
+    }
+}
+
+/// Errors that can occur when publishing via the outbox.
+#[derive(Debug, thiserror::Error)]
+pub enum OutboxError {
+    /// The payload could not be serialized.
+    #[error("serializing the payload failed: {0}")]
+    Serialize(anyhow::Error),
+    /// The [`OutboxStore`] failed, e.g. the message could not be stored, so it is never
+    /// published.
+    #[error("outbox store failed: {0}")]
+    Store(anyhow::Error),
+}
@@ -0,0 +1,207 @@
+//! Consume several queues in priority order, see [`Client::consume_prioritized`].
+
+use std::cmp::Reverse;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::prelude::*;
+use futures::stream::Peekable;
+use lapin::Consumer;
+use tracing::{error, info, Instrument};
+
+use crate::prelude::*;
+use crate::basic_consume_builder::{
+    self, ConsumeError, ConsumerContext, ConsumerTaskHandlerError, ConsumerTaskReport,
+};
+use crate::{util, Client, ConsumerHandler, Queue};
+
+/// How strictly [`Client::consume_prioritized`] prefers higher-priority queues.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Fairness {
+    /// Always handle a delivery of the highest-priority queue that has one. Lower-priority
+    /// queues starve as long as higher-priority queues keep receiving messages.
+    #[default]
+    Strict,
+    /// After handling this many deliveries of higher-priority queues in a row while
+    /// lower-priority queues had deliveries waiting, handle one delivery of the next
+    /// lower-priority queue that has one, so no queue starves.
+    Every(u32),
+}
+
+/// A consumer of one of the prioritized queues.
+struct PrioritizedConsumer {
+    /// Settings of the consumer.
+    ctx: Arc<ConsumerContext>,
+    /// The lapin consumer, peekable to find out whether a delivery is waiting.
+    consumer: Peekable<Consumer>,
+}
+
+/// Start consuming the queues in priority order, see [`Client::consume_prioritized`].
+pub(super) async fn consume<S, H, T>(
+    client: &Client<S>,
+    queues: &[(&Queue, u8)],
+    fairness: Fairness,
+    handler: H,
+) -> Result<(), ConsumeError>
+where
+    S: Clone + Send + Sync + 'static,
+    H: ConsumerHandler<S, T>,
+    T: 'static,
+{
+    if queues.is_empty() {
+        return Err(anyhow::format_err!("no queues to consume").into());
+    }
+    let mut queues = queues.to_vec();
+    queues.sort_by_key(|(_, priority)| Reverse(*priority));
+
+    let mut consumers = Vec::with_capacity(queues.len());
+    for (queue, _) in queues {
+        // A prefetch count of one keeps lower-priority queues from piling up deliveries, that
+        // would be handled before later deliveries of higher-priority queues.
+        let opened = client
+            .basic_consume_builder(queue, "")
+            .in_parallel(false)
+            .prefetch_count(1)
+            .open()
+            .await;
+        let (builder, chan, consumer) = match opened {
+            Ok(opened) => opened,
+            Err(err) => {
+                close(&consumers).await;
+                return Err(err);
+            }
+        };
+        let (_, ctx) = builder.into_context(&chan, &consumer);
+        consumers.push(PrioritizedConsumer {
+            ctx,
+            consumer: consumer.peekable(),
+        });
+    }
+
+    util::spawn_named_in(
+        &mut *client.consumer_set.lock().await,
+        "hare prioritized consumer",
+        consumer_task_handler(client.clone(), consumers, fairness, handler).in_current_span(),
+    );
+    Ok(())
+}
+
+/// Consumer task, handle incoming deliveries and close the consumers channels once done.
+async fn consumer_task_handler<S, H, T>(
+    client: Client<S>,
+    mut consumers: Vec<PrioritizedConsumer>,
+    fairness: Fairness,
+    handler: H,
+) -> Result<ConsumerTaskReport, ConsumerTaskHandlerError>
+where
+    S: Clone + Send + Sync + 'static,
+    H: ConsumerHandler<S, T>,
+    T: 'static,
+{
+    let result = consume_deliveries(client, &mut consumers, fairness, handler).await;
+    close(&consumers).await;
+    result
+}
+
+/// Handle deliveries of the highest-priority queue that has one, until a consumer fails or a
+/// signal like sigterm is received.
+async fn consume_deliveries<S, H, T>(
+    client: Client<S>,
+    consumers: &mut [PrioritizedConsumer],
+    fairness: Fairness,
+    handler: H,
+) -> Result<ConsumerTaskReport, ConsumerTaskHandlerError>
+where
+    S: Clone + Send + Sync + 'static,
+    H: ConsumerHandler<S, T>,
+    T: 'static,
+{
+    /// Tracing prefix for errors.
+    const ERR_TRACE_PREFIX: &str = "prioritized consumer failed";
+
+    info!(queues = consumers.len(), "prioritized consumer started");
+
+    let mut report = ConsumerTaskReport::default();
+    let mut preferred_in_row = 0;
+    loop {
+        let waiting = consumers
+            .iter_mut()
+            .map(|prioritized| Pin::new(&mut prioritized.consumer).peek());
+        tokio::select! {
+            signal_stop_result = util::signal_stop() => match signal_stop_result {
+                Ok(()) => {
+                    report.graceful = true;
+                    break;
+                }
+                Err(err) => {
+                    return Err(ConsumerTaskHandlerError::StopSignal(err.into()))
+                        .on_err(|err| error!("{ERR_TRACE_PREFIX}: {err}"));
+                }
+            },
+            _ = future::select_all(waiting) => (),
+        }
+
+        // Peeking again resolves right away for every consumer with a delivery waiting.
+        let ready: Vec<usize> = consumers
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, prioritized)| {
+                let waiting = Pin::new(&mut prioritized.consumer).peek().now_or_never();
+                waiting.map(|_| index)
+            })
+            .collect();
+        let index = match (fairness, ready.as_slice()) {
+            (Fairness::Every(every), [_, lower, ..]) if preferred_in_row >= every => {
+                preferred_in_row = 0;
+                *lower
+            }
+            (_, [preferred, _, ..]) => {
+                preferred_in_row += 1;
+                *preferred
+            }
+            (_, [preferred]) => {
+                preferred_in_row = 0;
+                *preferred
+            }
+            (_, []) => continue,
+        };
+
+        let PrioritizedConsumer { ctx, consumer } = &mut consumers[index];
+        let delivery = match consumer.next().await {
+            Some(Ok(delivery)) => delivery,
+            Some(Err(err)) => {
+                return Err(ConsumerTaskHandlerError::Consumer(err.into()))
+                    .on_err(|err| error!("{ERR_TRACE_PREFIX}: {err}"));
+            }
+            None => {
+                let err = anyhow::format_err!("consumer of {} has stopped", ctx.queue_name);
+                return Err(ConsumerTaskHandlerError::Consumer(err))
+                    .on_err(|err| error!("{ERR_TRACE_PREFIX}: {err}"));
+            }
+        };
+        report.deliveries += 1;
+        let delivery_span = basic_consume_builder::delivery_span(&delivery);
+        basic_consume_builder::delivery_task_handler(
+            client.clone(),
+            ctx.clone(),
+            handler.clone(),
+            None,
+            delivery,
+        )
+        .instrument(delivery_span)
+        .await;
+    }
+
+    info!(
+        deliveries = report.deliveries,
+        "prioritized consumer shut down"
+    );
+    Ok(report)
+}
+
+/// Close the channels of the consumers.
+async fn close(consumers: &[PrioritizedConsumer]) {
+    for prioritized in consumers {
+        prioritized.ctx.handle.close().await;
+    }
+}
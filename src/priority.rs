@@ -0,0 +1,106 @@
+//! [`Priority`] definition and implementation.
+use tracing::warn;
+
+/// Largest maximum message priority `RabbitMQ` recommends, higher ones cost memory and CPU for
+/// little benefit.
+pub const RECOMMENDED_MAX_MESSAGE_PRIORITY: u8 = 10;
+
+/// Priorities of a queue and the consumers on it, validated together.
+///
+/// `RabbitMQ` has two unrelated priority concepts. The maximum message priority turns a queue
+/// into a priority queue, which delivers messages with a higher `priority` property first. The
+/// consumer priority decides which consumer of a queue receives deliveries first. Consumer
+/// priorities work on any queue, but queues declared without a maximum message priority ignore
+/// the priority of messages.
+///
+/// Pass it to [`crate::queue_declare_builder::QueueDeclareBuilder::priority`], consumers of the
+/// declared [`crate::Queue`] use its consumer priority.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Priority {
+    /// Maximum message priority of the queue.
+    max_message_priority: Option<u8>,
+    /// Priority of the consumers on the queue.
+    consumer_priority: Option<i32>,
+}
+
+impl Priority {
+    /// Create a new `Priority` without any priorities.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Not set by default, the queue is not a priority queue. Must be at least `1`, at most
+    /// [`RECOMMENDED_MAX_MESSAGE_PRIORITY`] is recommended.
+    #[must_use]
+    pub fn max_message_priority(mut self, max_message_priority: u8) -> Self {
+        self.max_message_priority = Some(max_message_priority);
+        self
+    }
+
+    /// Not set by default, the broker defaults to `0`. Consumers with a higher priority receive
+    /// deliveries first, as long as they have spare prefetch capacity.
+    #[must_use]
+    pub fn consumer_priority(mut self, consumer_priority: i32) -> Self {
+        self.consumer_priority = Some(consumer_priority);
+        self
+    }
+
+    /// The maximum message priority of the queue, if it is a priority queue.
+    #[must_use]
+    pub fn get_max_message_priority(&self) -> Option<u8> {
+        self.max_message_priority
+    }
+
+    /// The priority of the consumers on the queue, if set.
+    #[must_use]
+    pub fn get_consumer_priority(&self) -> Option<i32> {
+        self.consumer_priority
+    }
+
+    /// Validate the priorities, warns about likely mistaken ones.
+    ///
+    /// # Errors
+    /// If the maximum message priority is `0`.
+    pub fn validate(&self) -> Result<(), PriorityError> {
+        match self.max_message_priority {
+            Some(0) => Err(PriorityError::ZeroMaxMessagePriority),
+            Some(max) if max > RECOMMENDED_MAX_MESSAGE_PRIORITY => {
+                warn!(
+                    "max message priority {max} exceeds the recommended maximum of \
+                     {RECOMMENDED_MAX_MESSAGE_PRIORITY}"
+                );
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Validate the priority of a message published to the queue, e.g. before passing it to
+    /// [`crate::basic_publish_builder::BasicPublishBuilder::priority`].
+    ///
+    /// # Errors
+    /// If the queue is not a priority queue, as the priority would be ignored, or if the
+    /// priority exceeds the maximum message priority, as the broker would cap it silently.
+    pub fn message_priority(&self, priority: u8) -> Result<u8, PriorityError> {
+        match self.max_message_priority {
+            None => Err(PriorityError::NotAPriorityQueue),
+            Some(max) if priority > max => Err(PriorityError::ExceedsMax(priority, max)),
+            Some(_) => Ok(priority),
+        }
+    }
+}
+
+/// Invalid [`Priority`] configurations.
+#[derive(Debug, thiserror::Error)]
+pub enum PriorityError {
+    /// The maximum message priority is `0`.
+    #[error("max message priority must be at least 1")]
+    ZeroMaxMessagePriority,
+    /// A message priority was used for a queue without a maximum message priority.
+    #[error("message priorities are ignored by queues without a max message priority")]
+    NotAPriorityQueue,
+    /// A message priority exceeds the maximum message priority of the queue.
+    #[error("message priority {0} exceeds the max message priority {1}")]
+    ExceedsMax(u8, u8),
+}
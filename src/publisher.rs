@@ -0,0 +1,104 @@
+//! [`Publisher`] implementation, publishing on a dedicated channel.
+
+use std::sync::Arc;
+
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::basic_publish_builder::{PublishError, Published};
+use crate::{BasicPublishBuilder, Client};
+
+/// Publishes messages on a dedicated, long-lived channel instead of taking a pooled channel per
+/// publish, see [`Client::dedicated_publisher`].
+///
+/// Publishes are serialized on the channel, so messages are handed to the broker in the order
+/// they were published, even across clones of the publisher. This is by design: it suits a
+/// single stream of ordered messages and avoids contention on the pool, but concurrent
+/// publishers wait for each other. Use the pooled [`Client::basic_publish_builder`] for
+/// throughput.
+///
+/// If the channel was closed, e.g. by the broker after a failed publish, a new one is opened
+/// on the next publish.
+#[derive(Debug, Clone)]
+pub struct Publisher<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    /// The [`Client`] to publish with.
+    client: Client<S>,
+    /// The dedicated channel, locked for the duration of a publish.
+    chan: Arc<Mutex<lapin::Channel>>,
+}
+
+impl<S> Publisher<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    /// Create a new `Publisher`, opening its channel.
+    ///
+    /// # Errors
+    /// If the channel can not be opened.
+    pub async fn new(client: Client<S>) -> Result<Self, PublishError> {
+        let chan = client
+            .create_channel()
+            .await
+            .map_err(|err| PublishError::Io(err.into()))?;
+        Ok(Self {
+            client,
+            chan: Arc::new(Mutex::new(chan)),
+        })
+    }
+
+    /// Publish the payload on the dedicated channel.
+    ///
+    /// # Errors
+    /// See [`PublishError`].
+    pub async fn publish<P>(
+        &self,
+        exchange_name: &str,
+        routing_key: &str,
+        payload: P,
+    ) -> Result<Published, PublishError>
+    where
+        P: Serialize,
+    {
+        self.publish_with(exchange_name, routing_key, |builder| builder, payload)
+            .await
+    }
+
+    /// Publish the payload on the dedicated channel, configuring the message with the
+    /// [`BasicPublishBuilder`] first, e.g. to set its properties.
+    ///
+    /// # Errors
+    /// See [`PublishError`].
+    pub async fn publish_with<P, F>(
+        &self,
+        exchange_name: &str,
+        routing_key: &str,
+        configure: F,
+        payload: P,
+    ) -> Result<Published, PublishError>
+    where
+        P: Serialize,
+        F: for<'b> FnOnce(BasicPublishBuilder<'b, S>) -> BasicPublishBuilder<'b, S>,
+    {
+        let mut builder = configure(
+            self.client
+                .basic_publish_builder(exchange_name, routing_key),
+        );
+        builder.throttle().await;
+        let mut chan = self.chan.lock().await;
+        if !chan.status().connected() {
+            warn!("dedicated publish channel is closed, opening a new one");
+            *chan = self
+                .client
+                .create_channel()
+                .await
+                .map_err(|err| PublishError::Io(err.into()))?;
+        }
+        builder
+            .publish_on(&chan, exchange_name, routing_key, payload)
+            .await
+    }
+}
@@ -2,6 +2,8 @@
 
 use std::ops::Deref;
 
+use crate::Priority;
+
 /// Queue, wrapper for [`lapin::Queue`].
 #[derive(Debug, Clone)]
 pub struct Queue {
@@ -9,6 +11,17 @@ pub struct Queue {
     pub inner: lapin::Queue,
     /// Enable or disable dead lettering.
     pub(super) dead_lettering: bool,
+    /// Priorities the queue was declared with.
+    pub(super) priority: Priority,
+}
+
+impl Queue {
+    /// Priorities the queue was declared with, see
+    /// [`crate::queue_declare_builder::QueueDeclareBuilder::priority`].
+    #[must_use]
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
 }
 
 impl Deref for Queue {
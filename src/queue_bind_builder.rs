@@ -22,6 +22,10 @@ where
     opts: QueueBindOptions,
     /// Additional arguments.
     args: FieldTable,
+    /// Verify the binding after binding with `nowait`.
+    verify: bool,
+    /// Validate the routing key as a topic pattern.
+    validate_topic: bool,
 }
 
 impl<'a, S> QueueBindBuilder<'a, S>
@@ -46,16 +50,37 @@ where
             routing_key,
             opts: QueueBindOptions { nowait: false },
             args: FieldTable::default(),
+            verify: false,
+            validate_topic: false,
         }
     }
 
-    /// Defaults to `false`.
+    /// Defaults to `false`. When `false`, [`Self::bind`] waits for the broker to confirm the
+    /// binding, when `true` the bind is fire-and-forget and errors are not surfaced.
     #[must_use]
     pub fn nowait(mut self, nowait: bool) -> Self {
         self.opts.nowait = nowait;
         self
     }
 
+    /// Defaults to `false`. When set together with `nowait`, the binding is verified by
+    /// re-binding on the same channel and waiting for the broker to confirm it. As binding is
+    /// idempotent this surfaces errors of the initial bind, without changing the topology.
+    #[must_use]
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Defaults to `false`. Enable when binding to a topic exchange, to validate that the routing
+    /// key is a well-formed topic pattern before contacting the broker: `.`-delimited, non-empty
+    /// words, where `*` and `#` only appear as whole words.
+    #[must_use]
+    pub fn validate_topic(mut self, validate_topic: bool) -> Self {
+        self.validate_topic = validate_topic;
+        self
+    }
+
     /// Add additional options.
     #[must_use]
     pub fn opts<F>(mut self, f: F) -> Self
@@ -70,29 +95,78 @@ where
     /// # Errors
     /// See [`BindError`].
     pub async fn bind(self) -> Result<(), BindError> {
+        if self.validate_topic {
+            validate_topic_pattern(self.routing_key)?;
+        }
+
         let chan = self
             .client
             .get_channel()
             .await
-            .map_err(|err| BindError(err.into()))?;
+            .map_err(|err| BindError::Other(err.into()))?;
         chan.queue_bind(
             self.name,
             self.exchange_name,
             self.routing_key,
             self.opts,
-            self.args,
+            self.args.clone(),
         )
         .await
-        .map_err(|err| BindError(err.into()))
+        .map_err(|err| BindError::Other(err.into()))?;
+
+        if self.verify && self.opts.nowait {
+            chan.queue_bind(
+                self.name,
+                self.exchange_name,
+                self.routing_key,
+                QueueBindOptions { nowait: false },
+                self.args,
+            )
+            .await
+            .map_err(|err| {
+                BindError::Other(anyhow::format_err!("verifying binding failed: {err}"))
+            })?;
+        }
+        Ok(())
     }
 }
 
+/// Check that `routing_key` is a well-formed topic pattern.
+fn validate_topic_pattern(routing_key: &str) -> Result<(), BindError> {
+    /// Maximum length of a routing key in bytes.
+    const MAX_ROUTING_KEY_LEN: usize = 255;
+
+    let invalid =
+        |reason: &str| BindError::InvalidRoutingKey(routing_key.to_string(), reason.into());
+    if routing_key.len() > MAX_ROUTING_KEY_LEN {
+        return Err(invalid("longer than 255 bytes"));
+    }
+    if routing_key.is_empty() {
+        return Ok(());
+    }
+    for word in routing_key.split('.') {
+        if word.is_empty() {
+            return Err(invalid("contains an empty word"));
+        }
+        if word != "*" && word != "#" && word.contains(['*', '#']) {
+            return Err(invalid("wildcards must be whole words"));
+        }
+    }
+    Ok(())
+}
+
 /// Errors that can occur when binding a queue.
 #[derive(Debug, thiserror::Error)]
-#[error(transparent)]
-pub struct BindError(#[from] anyhow::Error);
+pub enum BindError {
+    /// The routing key is not a well-formed topic pattern.
+    #[error("invalid topic routing key {0:?}: {1}")]
+    InvalidRoutingKey(String, String),
+    /// Any other error.
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
 
-impl<'a, S> BuilderArgs for QueueBindBuilder<'a, S>
+impl<S> BuilderArgs for QueueBindBuilder<'_, S>
 where
     S: Clone + Send + Sync + 'static,
 {
@@ -3,12 +3,63 @@
 use std::time::Duration;
 
 use lapin::options::QueueDeclareOptions;
+use lapin::protocol::{AMQPErrorKind, AMQPSoftError};
 use lapin::types::FieldTable;
+use tracing::warn;
 
-use crate::{dlx, BuilderArgs, Client, Queue};
+use crate::{dlx, BuilderArgs, Client, Priority, Queue};
 
 /// Key name for queue expiry.
 const XARGS_EXPIRES: &str = "x-expires";
+/// Key name for the queue overflow behaviour.
+const XARGS_OVERFLOW: &str = "x-overflow";
+/// Key name for the maximum message priority.
+const XARGS_MAX_PRIORITY: &str = "x-max-priority";
+/// Key name for the queue type.
+const XARGS_QUEUE_TYPE: &str = "x-queue-type";
+/// Key name for the leader placement of quorum queues.
+const XARGS_QUEUE_LEADER_LOCATOR: &str = "x-queue-leader-locator";
+
+/// What happens when a queue reaches its maximum length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Drop or dead-letter the oldest messages.
+    DropHead,
+    /// Reject new publishes.
+    RejectPublish,
+    /// Reject new publishes and dead-letter them, requires dead lettering.
+    RejectPublishDlx,
+}
+
+impl Overflow {
+    /// The value of the `x-overflow` argument.
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::DropHead => "drop-head",
+            Self::RejectPublish => "reject-publish",
+            Self::RejectPublishDlx => "reject-publish-dlx",
+        }
+    }
+}
+
+/// Where the leader of a quorum queue is placed in the cluster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaderLocator {
+    /// On the node the declaring client is connected to.
+    ClientLocal,
+    /// On the node hosting the least leaders, or a random node in large clusters.
+    Balanced,
+}
+
+impl LeaderLocator {
+    /// The value of the `x-queue-leader-locator` argument.
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::ClientLocal => "client-local",
+            Self::Balanced => "balanced",
+        }
+    }
+}
 
 /// Declare a queue.
 pub struct QueueDeclareBuilder<'a, S>
@@ -25,6 +76,18 @@ where
     args: FieldTable,
     /// Enable or disable dead lettering.
     dead_lettering: bool,
+    /// Overflow behaviour.
+    overflow: Option<Overflow>,
+    /// Exchange and routing key to dead-letter to, instead of the defaults.
+    dlx_to: Option<(&'a str, &'a str)>,
+    /// Priorities of the queue and its consumers.
+    priority: Priority,
+    /// Verify the queue after declaring with `nowait`.
+    verify: bool,
+    /// Declare a quorum queue.
+    quorum: bool,
+    /// Leader placement of the quorum queue.
+    leader_locator: Option<LeaderLocator>,
 }
 
 impl<'a, S> QueueDeclareBuilder<'a, S>
@@ -46,6 +109,12 @@ where
             },
             args: FieldTable::default(),
             dead_lettering: true,
+            overflow: None,
+            dlx_to: None,
+            priority: Priority::default(),
+            verify: false,
+            quorum: false,
+            leader_locator: None,
         }
     }
 
@@ -70,13 +139,24 @@ where
         self
     }
 
-    /// Defaults to `false`.
+    /// Defaults to `false`. When `false`, [`Self::declare`] waits for the broker to confirm the
+    /// declaration, when `true` the declaration is fire-and-forget, errors are not surfaced and
+    /// the message and consumer counts of the returned [`Queue`] are always `0`.
     #[must_use]
     pub fn nowait(mut self, nowait: bool) -> Self {
         self.opts.nowait = nowait;
         self
     }
 
+    /// Defaults to `false`. When set together with `nowait`, the queue is verified by a passive
+    /// declare on the same channel, waiting for the broker to confirm it exists. This surfaces
+    /// errors of the initial declare, while keeping the declare itself fire-and-forget.
+    #[must_use]
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
     /// Defaults to `false`.
     #[must_use]
     pub fn passive(mut self, passive: bool) -> Self {
@@ -91,6 +171,49 @@ where
         self
     }
 
+    /// Not set by default, dead-letters to [`dlx::EXCHANGE_NAME`] with
+    /// [`dlx::ROUTING_KEY_DEAD_LETTER`]. Dead-letters messages of this queue to another exchange
+    /// and routing key instead. Has no effect if dead lettering is disabled.
+    #[must_use]
+    pub fn with_dlx_to(mut self, exchange_name: &'a str, routing_key: &'a str) -> Self {
+        self.dlx_to = Some((exchange_name, routing_key));
+        self
+    }
+
+    /// Not set by default, the broker defaults to [`Overflow::DropHead`]. Only takes effect if a
+    /// maximum length is set. [`Overflow::RejectPublishDlx`] requires dead lettering, see
+    /// [`Self::declare`].
+    #[must_use]
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = Some(overflow);
+        self
+    }
+
+    /// Defaults to `false`. Declares a replicated quorum queue instead of a classic queue.
+    /// Quorum queues need to be durable.
+    #[must_use]
+    pub fn quorum(mut self, quorum: bool) -> Self {
+        self.quorum = quorum;
+        self
+    }
+
+    /// Not set by default, the broker decides. Places the leader of the quorum queue, e.g. on the
+    /// node the client is connected to, so it is close to the consumers. Requires
+    /// [`Self::quorum`], see [`Self::declare`].
+    #[must_use]
+    pub fn leader_locator(mut self, leader_locator: LeaderLocator) -> Self {
+        self.leader_locator = Some(leader_locator);
+        self
+    }
+
+    /// Not set by default. Sets the maximum message priority of the queue, consumers of the
+    /// declared [`Queue`] use the consumer priority. Validated in [`Self::declare`].
+    #[must_use]
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
     /// Provide aditional [`QueueDeclareOptions`].
     #[must_use]
     pub fn opts<F>(mut self, f: F) -> Self
@@ -114,15 +237,43 @@ where
 
     /// Declare the queue.
     /// # Errors
-    /// See #[`DeclareError`].
+    /// See #[`DeclareError`]. Fails without contacting the broker if the overflow is
+    /// [`Overflow::RejectPublishDlx`] while dead lettering is disabled, as rejected messages would
+    /// silently vanish, if the [`Priority`] is invalid, or if a [`LeaderLocator`] is set for a
+    /// classic queue.
     pub async fn declare(mut self) -> Result<Queue, DeclareError> {
+        self.priority
+            .validate()
+            .map_err(|err| DeclareError(err.into()))?;
+        if let Some(max_message_priority) = self.priority.get_max_message_priority() {
+            self = self.arg_u8(XARGS_MAX_PRIORITY, max_message_priority);
+        }
+        if let Some(overflow) = self.overflow {
+            if overflow == Overflow::RejectPublishDlx && !self.dead_lettering {
+                return Err(DeclareError(anyhow::format_err!(
+                    "overflow reject-publish-dlx requires dead lettering to be enabled"
+                )));
+            }
+            self = self.arg_str(XARGS_OVERFLOW, overflow.as_str());
+        }
+        if self.quorum {
+            self = self.arg_str(XARGS_QUEUE_TYPE, "quorum");
+        }
+        if let Some(leader_locator) = self.leader_locator {
+            if !self.quorum {
+                return Err(DeclareError(anyhow::format_err!(
+                    "a leader locator requires a quorum queue"
+                )));
+            }
+            self = self.arg_str(XARGS_QUEUE_LEADER_LOCATOR, leader_locator.as_str());
+        }
         if self.dead_lettering {
+            let (exchange_name, routing_key) = self
+                .dlx_to
+                .unwrap_or((dlx::EXCHANGE_NAME, dlx::ROUTING_KEY_DEAD_LETTER));
             self = self
-                .arg_str(dlx::QUEUE_ARGUMENT_KEY_EXCHANGE_NAME, dlx::EXCHANGE_NAME)
-                .arg_str(
-                    dlx::QUEUE_ARGUMENT_KEY_ROUTING_KEY,
-                    dlx::ROUTING_KEY_DEAD_LETTER,
-                );
+                .arg_str(dlx::QUEUE_ARGUMENT_KEY_EXCHANGE_NAME, exchange_name)
+                .arg_str(dlx::QUEUE_ARGUMENT_KEY_ROUTING_KEY, routing_key);
         }
 
         let chan = self
@@ -130,14 +281,68 @@ where
             .get_channel()
             .await
             .map_err(|err| DeclareError(err.into()))?;
-        let queue = chan
+        let mut queue = chan
             .queue_declare(self.name, self.opts, self.args)
             .await
             .map_err(|err| DeclareError(err.into()))?;
+        if self.verify && self.opts.nowait {
+            queue = chan
+                .queue_declare(
+                    self.name,
+                    QueueDeclareOptions {
+                        passive: true,
+                        ..QueueDeclareOptions::default()
+                    },
+                    FieldTable::default(),
+                )
+                .await
+                .map_err(|err| {
+                    DeclareError(anyhow::format_err!("verifying queue failed: {err}"))
+                })?;
+        }
 
         Ok(Queue {
             inner: queue,
             dead_lettering: self.dead_lettering,
+            priority: self.priority,
+        })
+    }
+
+    /// Declare the queue like [`Self::declare`], tolerating an existing queue with different
+    /// arguments, e.g. one declared slightly differently by another service. If the broker
+    /// refuses the declaration for that reason, a warning is logged and the queue is only
+    /// verified to exist by a passive declare, keeping its existing arguments.
+    ///
+    /// # Errors
+    /// See [`Self::declare`], also fails if the passive declare does not find the queue.
+    pub async fn declare_or_skip(self) -> Result<Queue, DeclareError> {
+        let (client, name) = (self.client, self.name);
+        let (dead_lettering, priority) = (self.dead_lettering, self.priority);
+        let err = match self.declare().await {
+            Err(err) if err.is_precondition_failed() => err,
+            declared => return declared,
+        };
+        warn!("queue {name} exists with different arguments, keeping them: {err}");
+
+        let chan = client
+            .get_channel()
+            .await
+            .map_err(|err| DeclareError(err.into()))?;
+        let queue = chan
+            .queue_declare(
+                name,
+                QueueDeclareOptions {
+                    passive: true,
+                    ..QueueDeclareOptions::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .map_err(|err| DeclareError(anyhow::format_err!("verifying queue failed: {err}")))?;
+        Ok(Queue {
+            inner: queue,
+            dead_lettering,
+            priority,
         })
     }
 }
@@ -147,7 +352,20 @@ where
 #[error(transparent)]
 pub struct DeclareError(#[from] anyhow::Error);
 
-impl<'a, S> BuilderArgs for QueueDeclareBuilder<'a, S>
+impl DeclareError {
+    /// Whether the broker refused the declaration, because the queue exists with different
+    /// arguments, see [`Client::migrate_queue`].
+    #[must_use]
+    pub fn is_precondition_failed(&self) -> bool {
+        matches!(
+            self.0.downcast_ref::<lapin::Error>(),
+            Some(lapin::Error::ProtocolError(err))
+                if *err.kind() == AMQPErrorKind::Soft(AMQPSoftError::PRECONDITIONFAILED)
+        )
+    }
+}
+
+impl<S> BuilderArgs for QueueDeclareBuilder<'_, S>
 where
     S: Clone + Send + Sync + 'static,
 {
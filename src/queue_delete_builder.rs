@@ -53,7 +53,9 @@ where
         self
     }
 
-    /// Defaults to `false`.
+    /// Defaults to `false`. When `false`, [`Self::delete`] waits for the broker to confirm the
+    /// deletion, when `true` the deletion is fire-and-forget, errors are not surfaced and the
+    /// returned message count is always `0`.
     #[must_use]
     pub fn nowait(mut self, nowait: bool) -> Self {
         self.opts.nowait = nowait;
@@ -0,0 +1,234 @@
+//! Migrate a queue to arguments it can not be redeclared with, see [`Client::migrate_queue`],
+//! and move messages between queues, see [`Client::move_messages`].
+
+use lapin::options::{
+    BasicAckOptions, BasicGetOptions, BasicPublishOptions, ConfirmSelectOptions, QueueDeleteOptions,
+};
+use lapin::protocol::constants::REPLY_SUCCESS;
+use lapin::publisher_confirm::Confirmation;
+use lapin::types::MessageCount;
+use tracing::{info, warn};
+
+use crate::queue_declare_builder::QueueDeclareBuilder;
+use crate::{Client, Queue};
+
+/// Suffix of the temporary queue messages are parked in during a migration.
+pub const MIGRATION_QUEUE_SUFFIX: &str = ".migration";
+
+/// Declare the queue with the new configuration, migrating it if it exists with different
+/// arguments, see [`Client::migrate_queue`].
+pub(super) async fn migrate<S, F>(
+    client: &Client<S>,
+    name: &str,
+    configure: F,
+    delete_confirmed: bool,
+) -> Result<Queue, MigrateQueueError>
+where
+    S: Clone + Send + Sync + 'static,
+    F: for<'b> Fn(QueueDeclareBuilder<'b, S>) -> QueueDeclareBuilder<'b, S>,
+{
+    match configure(client.queue_declare_builder(name))
+        .declare()
+        .await
+    {
+        Ok(queue) => return Ok(queue),
+        Err(err) if !err.is_precondition_failed() => {
+            return Err(MigrateQueueError::Declare(err.into()));
+        }
+        Err(_) if !delete_confirmed => return Err(MigrateQueueError::ConfirmationRequired),
+        Err(err) => info!("migrating queue {name}: {err}"),
+    }
+
+    let consumer_count = client
+        .queue_declare_builder(name)
+        .passive(true)
+        .declare()
+        .await
+        .map_err(|err| MigrateQueueError::Declare(err.into()))?
+        .consumer_count();
+    if consumer_count > 0 {
+        return Err(MigrateQueueError::InUse(consumer_count));
+    }
+
+    let migration_name = format!("{name}{MIGRATION_QUEUE_SUFFIX}");
+    client
+        .queue_declare_builder(&migration_name)
+        .dead_lettering(false)
+        .declare()
+        .await
+        .map_err(|err| MigrateQueueError::Declare(err.into()))?;
+
+    let chan = confirm_channel(client)
+        .await
+        .map_err(MigrateQueueError::Channel)?;
+    let migrated = async {
+        let moved = move_messages(&chan, name, "", &migration_name, None)
+            .await
+            .map_err(|err| MigrateQueueError::Move(err.into()))?;
+        info!("moved {moved} messages from {name} to {migration_name}");
+
+        // Refuse to delete the queue if a consumer attached in the meantime.
+        chan.queue_delete(
+            name,
+            QueueDeleteOptions {
+                if_unused: true,
+                ..QueueDeleteOptions::default()
+            },
+        )
+        .await
+        .map_err(|err| MigrateQueueError::Delete(err.into()))?;
+        let queue = configure(client.queue_declare_builder(name))
+            .declare()
+            .await
+            .map_err(|err| MigrateQueueError::Declare(err.into()))?;
+
+        let moved = move_messages(&chan, &migration_name, "", name, None)
+            .await
+            .map_err(|err| MigrateQueueError::Move(err.into()))?;
+        info!("moved {moved} messages from {migration_name} back to {name}");
+        chan.queue_delete(
+            &migration_name,
+            QueueDeleteOptions {
+                if_empty: true,
+                ..QueueDeleteOptions::default()
+            },
+        )
+        .await
+        .map_err(|err| MigrateQueueError::Delete(err.into()))?;
+        Ok(queue)
+    }
+    .await;
+    if let Err(err) = chan.close(REPLY_SUCCESS, "OK").await {
+        warn!("closing the migration channel failed: {err}");
+    }
+    migrated
+}
+
+/// Move up to `max_count` messages from the queue to the exchange, see
+/// [`Client::move_messages`].
+pub(super) async fn move_to<S>(
+    client: &Client<S>,
+    from: &str,
+    exchange_name: &str,
+    routing_key: &str,
+    max_count: Option<MessageCount>,
+) -> Result<MessageCount, MoveMessagesError>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let chan = confirm_channel(client)
+        .await
+        .map_err(MoveMessagesError::Channel)?;
+    let moved = move_messages(&chan, from, exchange_name, routing_key, max_count).await;
+    if let Err(err) = chan.close(REPLY_SUCCESS, "OK").await {
+        warn!("closing the channel messages were moved on failed: {err}");
+    }
+    moved
+}
+
+/// Create a dedicated channel in confirm mode.
+async fn confirm_channel<S>(client: &Client<S>) -> Result<lapin::Channel, anyhow::Error>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let chan = client.create_channel().await?;
+    if let Err(err) = chan.confirm_select(ConfirmSelectOptions::default()).await {
+        if let Err(err) = chan.close(REPLY_SUCCESS, "OK").await {
+            warn!("closing the channel failed: {err}");
+        }
+        return Err(err.into());
+    }
+    Ok(chan)
+}
+
+/// Move up to `max_count` messages from the queue to the exchange, all of them if not set.
+/// Every message is acknowledged only once the broker confirmed routing its copy, so a failure
+/// leaves the message in the source queue.
+async fn move_messages(
+    chan: &lapin::Channel,
+    from: &str,
+    exchange_name: &str,
+    routing_key: &str,
+    max_count: Option<MessageCount>,
+) -> Result<MessageCount, MoveMessagesError> {
+    let mut moved = 0;
+    let move_err = |moved, err: anyhow::Error| MoveMessagesError::Move { moved, source: err };
+    while max_count.map_or(true, |max_count| moved < max_count) {
+        let Some(message) = chan
+            .basic_get(from, BasicGetOptions::default())
+            .await
+            .map_err(|err| move_err(moved, err.into()))?
+        else {
+            break;
+        };
+        let delivery = message.delivery;
+        let confirmation = chan
+            .basic_publish(
+                exchange_name,
+                routing_key,
+                BasicPublishOptions {
+                    mandatory: true,
+                    ..BasicPublishOptions::default()
+                },
+                &delivery.data,
+                delivery.properties.clone(),
+            )
+            .await
+            .map_err(|err| move_err(moved, err.into()))?
+            .await
+            .map_err(|err| move_err(moved, err.into()))?;
+        if let Confirmation::Ack(Some(_)) | Confirmation::Nack(_) = confirmation {
+            return Err(move_err(
+                moved,
+                anyhow::format_err!(
+                    "the broker did not route a message to {exchange_name}/{routing_key}"
+                ),
+            ));
+        }
+        chan.basic_ack(delivery.delivery_tag, BasicAckOptions::default())
+            .await
+            .map_err(|err| move_err(moved, err.into()))?;
+        moved += 1;
+    }
+    Ok(moved)
+}
+
+/// Errors that can occur when moving messages.
+#[derive(Debug, thiserror::Error)]
+pub enum MoveMessagesError {
+    /// The channel to move the messages on could not be created.
+    #[error("creating the channel failed: {0}")]
+    Channel(anyhow::Error),
+    /// Moving a message failed, it stays in the source queue.
+    #[error("moving a message failed after moving {moved} messages: {source}")]
+    Move {
+        /// Number of messages moved before the failure.
+        moved: MessageCount,
+        /// The cause of the failure.
+        source: anyhow::Error,
+    },
+}
+
+/// Errors that can occur when migrating a queue.
+#[derive(Debug, thiserror::Error)]
+pub enum MigrateQueueError {
+    /// The queue needs to be migrated, but deleting it was not confirmed.
+    #[error("the queue exists with different arguments, migrating it requires confirmation")]
+    ConfirmationRequired,
+    /// The queue has consumers, which would be canceled by deleting it. Stop them before
+    /// migrating the queue.
+    #[error("the queue has {0} consumers, stop them before migrating it")]
+    InUse(u32),
+    /// Declaring the queue or the temporary queue failed.
+    #[error("declaring the queue failed: {0}")]
+    Declare(anyhow::Error),
+    /// The channel to move the messages on could not be created.
+    #[error("creating the migration channel failed: {0}")]
+    Channel(anyhow::Error),
+    /// Moving messages between the queue and the temporary queue failed.
+    #[error("moving messages failed: {0}")]
+    Move(anyhow::Error),
+    /// Deleting the queue or the temporary queue failed.
+    #[error("deleting the queue failed: {0}")]
+    Delete(anyhow::Error),
+}
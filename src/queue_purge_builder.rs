@@ -2,6 +2,7 @@
 
 use lapin::options::QueuePurgeOptions;
 use lapin::types::MessageCount;
+use tracing::warn;
 
 use crate::{Client, QueueName};
 
@@ -35,7 +36,9 @@ where
         }
     }
 
-    /// Defaults to `false`.
+    /// Defaults to `false`. When `false`, [`Self::purge`] waits for the broker to confirm the
+    /// purge, when `true` the purge is fire-and-forget, errors are not surfaced and the returned
+    /// message count is always `0`.
     #[must_use]
     pub fn nowait(mut self, nowait: bool) -> Self {
         self.opts.nowait = nowait;
@@ -61,6 +64,20 @@ where
             .get_channel()
             .await
             .map_err(|err| PurgeError(err.into()))?;
+        if self.opts.nowait {
+            // lapin waits for a purge-ok even with `nowait`, which the broker never sends. Purge
+            // in the background instead, so the purge does not hang.
+            let name = self.name.to_string();
+            self.client.runtime.spawn(Box::pin(async move {
+                if let Err(err) = chan
+                    .queue_purge(&name, QueuePurgeOptions { nowait: false })
+                    .await
+                {
+                    warn!("purging queue {name} failed: {err}");
+                }
+            }));
+            return Ok(0);
+        }
         chan.queue_purge(self.name, self.opts)
             .await
             .map_err(|err| PurgeError(err.into()))
@@ -65,7 +65,7 @@ where
 #[error(transparent)]
 pub struct UnbindError(#[from] anyhow::Error);
 
-impl<'a, S> BuilderArgs for QueueUnbindBuilder<'a, S>
+impl<S> BuilderArgs for QueueUnbindBuilder<'_, S>
 where
     S: Clone + Send + Sync + 'static,
 {
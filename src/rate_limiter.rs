@@ -0,0 +1,27 @@
+//! [`RateLimiter`] definition, cap the publish throughput.
+
+use std::num::NonZeroU32;
+use std::sync::Arc;
+
+use governor::{DefaultDirectRateLimiter, Quota};
+
+/// Token bucket limiting how many messages are published per second, see
+/// [`crate::basic_publish_builder::BasicPublishBuilder::rate_limit`]. Cloning is cheap and
+/// clones share the same bucket, so one limiter can cap several publishers at once.
+#[derive(Debug, Clone)]
+pub struct RateLimiter(Arc<DefaultDirectRateLimiter>);
+
+impl RateLimiter {
+    /// Allow up to `per_second` publishes per second, bursts up to the same amount are allowed.
+    #[must_use]
+    pub fn per_second(per_second: NonZeroU32) -> Self {
+        Self(Arc::new(DefaultDirectRateLimiter::direct(
+            Quota::per_second(per_second),
+        )))
+    }
+
+    /// Wait until the next publish is allowed.
+    pub(super) async fn until_ready(&self) {
+        self.0.until_ready().await;
+    }
+}
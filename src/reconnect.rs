@@ -0,0 +1,328 @@
+//! Reconnect to the broker once the connection failed, see [`crate::Client::with_reconnect`].
+
+use std::io;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+
+use lapin::tcp::TcpStream;
+use lapin::uri::{AMQPScheme, AMQPUri};
+use lapin::{Connection, ConnectionProperties};
+use rustls_connector::RustlsConnector;
+use tracing::{info, warn};
+
+use crate::runtime::Runtime;
+
+/// When and how often the [`crate::Client`] reconnects once its connection failed, see
+/// [`crate::Client::with_reconnect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// Give up after this many failed attempts.
+    max_retries: u32,
+    /// Backoff before the first attempt, doubled after every failed attempt.
+    initial_backoff: Duration,
+    /// Upper bound of the backoff.
+    max_backoff: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 10,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Create a new `ReconnectPolicy`, retrying 10 times with a backoff from 500 milliseconds up
+    /// to 30 seconds.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defaults to `10`. Give up reconnecting after this many failed attempts.
+    #[must_use]
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Defaults to 500 milliseconds. The backoff before the first attempt, doubled after every
+    /// failed attempt.
+    #[must_use]
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Defaults to 30 seconds. The upper bound of the backoff between attempts.
+    #[must_use]
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// The backoff before each attempt, one per attempt.
+    fn backoffs(&self) -> impl Iterator<Item = Duration> {
+        let max_backoff = self.max_backoff;
+        std::iter::successors(
+            Some(self.initial_backoff.min(max_backoff)),
+            move |backoff| Some(backoff.saturating_mul(2).min(max_backoff)),
+        )
+        .take(self.max_retries.try_into().unwrap_or(usize::MAX))
+    }
+}
+
+/// Holds the current connection of a client and replaces it once it failed, if a
+/// [`ReconnectPolicy`] is set.
+pub(super) struct Connector {
+    /// The uri to connect to.
+    uri: String,
+    /// The properties to connect with.
+    props: ConnectionProperties,
+    /// Performs the TLS handshake, `None` to use the TLS defaults of lapin.
+    tls: Option<RustlsConnector>,
+    /// The current connection.
+    conn: RwLock<Arc<Connection>>,
+    /// How to reconnect, `None` if the client does not reconnect.
+    policy: RwLock<Option<ReconnectPolicy>>,
+    /// Held while reconnecting, so concurrent callers wait for the same reconnect.
+    reconnecting: tokio::sync::Mutex<()>,
+    /// Invoked for errors of the current connection, registered on every new connection.
+    on_error: RwLock<Option<Arc<ErrorFn>>>,
+}
+
+/// Callback invoked for connection errors, see [`Connector::on_error`].
+pub(super) type ErrorFn = dyn Fn(lapin::Error) + Send + Sync;
+
+impl std::fmt::Debug for Connector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Connector")
+            .field("conn", &self.conn)
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Connector {
+    /// Connect to the broker, performing the TLS handshake with `tls` if set.
+    ///
+    /// # Errors
+    /// If the connection can not be established.
+    pub async fn connect(
+        uri: &str,
+        props: ConnectionProperties,
+        tls: Option<RustlsConnector>,
+    ) -> Result<Self, lapin::Error> {
+        let conn = open(uri, props.clone(), tls.clone()).await?;
+        Ok(Self {
+            uri: uri.to_string(),
+            props,
+            tls,
+            conn: RwLock::new(Arc::new(conn)),
+            policy: RwLock::default(),
+            reconnecting: tokio::sync::Mutex::default(),
+            on_error: RwLock::default(),
+        })
+    }
+
+    /// The current connection, it might have failed.
+    pub fn current(&self) -> Arc<Connection> {
+        self.conn
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Register the callback invoked for errors of the current connection, and of every
+    /// connection replacing it when reconnecting. Replaces the previous callback.
+    pub fn on_error(&self, on_error: Arc<ErrorFn>) {
+        *self
+            .on_error
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(on_error.clone());
+        self.current().on_error(move |err| on_error(err));
+    }
+
+    /// Set the [`ReconnectPolicy`].
+    pub fn set_policy(&self, policy: ReconnectPolicy) {
+        *self.policy.write().unwrap_or_else(PoisonError::into_inner) = Some(policy);
+    }
+
+    /// Whether a [`ReconnectPolicy`] is set.
+    pub fn reconnects(&self) -> bool {
+        self.policy
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_some()
+    }
+
+    /// The current connection, reconnecting first if it failed and a [`ReconnectPolicy`] is set.
+    /// Backoffs are awaited on `runtime`.
+    ///
+    /// # Errors
+    /// If reconnecting failed.
+    pub async fn connected(
+        &self,
+        runtime: &dyn Runtime,
+    ) -> Result<Arc<Connection>, ReconnectError> {
+        let conn = self.current();
+        if conn.status().connected() || !self.reconnects() {
+            return Ok(conn);
+        }
+        self.reconnect(runtime).await
+    }
+
+    /// Replace the failed connection with a new one, following the [`ReconnectPolicy`]. Backoffs
+    /// are awaited on `runtime`.
+    ///
+    /// # Errors
+    /// If no [`ReconnectPolicy`] is set, or every attempt failed.
+    pub async fn reconnect(
+        &self,
+        runtime: &dyn Runtime,
+    ) -> Result<Arc<Connection>, ReconnectError> {
+        let _reconnecting = self.reconnecting.lock().await;
+        // Another caller might have reconnected while this one waited.
+        let conn = self.current();
+        if conn.status().connected() {
+            return Ok(conn);
+        }
+        let Some(policy) = *self.policy.read().unwrap_or_else(PoisonError::into_inner) else {
+            return Err(ReconnectError(anyhow::format_err!(
+                "reconnecting is disabled"
+            )));
+        };
+
+        let mut last_err = None;
+        for (attempt, backoff) in (1_u32..).zip(policy.backoffs()) {
+            runtime.sleep(backoff).await;
+            match open(&self.uri, self.props.clone(), self.tls.clone()).await {
+                Ok(conn) => {
+                    info!(attempt, "reconnected to RabbitMQ");
+                    if let Some(on_error) = self
+                        .on_error
+                        .read()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .clone()
+                    {
+                        conn.on_error(move |err| on_error(err));
+                    }
+                    let conn = Arc::new(conn);
+                    *self.conn.write().unwrap_or_else(PoisonError::into_inner) = conn.clone();
+                    return Ok(conn);
+                }
+                Err(err) => {
+                    warn!(attempt, "reconnecting to RabbitMQ failed: {err}");
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(give_up(policy, last_err.as_ref()))
+    }
+}
+
+/// The error once every attempt of the policy failed.
+fn give_up(policy: ReconnectPolicy, last_err: Option<&lapin::Error>) -> ReconnectError {
+    ReconnectError(anyhow::format_err!(
+        "giving up after {} attempts, last error: {}",
+        policy.max_retries,
+        last_err.map_or_else(|| "none".to_string(), ToString::to_string)
+    ))
+}
+
+/// Open a connection to the broker, performing the TLS handshake with `tls` if set.
+///
+/// # Errors
+/// If the connection can not be established, or `tls` is set but the uri is not `amqps://`.
+// The handshake error of the connector closure is defined by lapin.
+#[allow(clippy::result_large_err)]
+async fn open(
+    uri: &str,
+    props: ConnectionProperties,
+    tls: Option<RustlsConnector>,
+) -> Result<Connection, lapin::Error> {
+    let Some(tls) = tls else {
+        return Connection::connect(uri, props).await;
+    };
+    let uri = uri
+        .parse::<AMQPUri>()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    // Refuse to silently fall back to plain text.
+    if uri.scheme != AMQPScheme::AMQPS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "connecting with TLS requires an amqps:// uri",
+        )
+        .into());
+    }
+    Connection::connector(
+        uri,
+        Box::new(move |uri| {
+            let addr = (uri.authority.host.as_str(), uri.authority.port);
+            let stream = match uri.query.connection_timeout {
+                Some(timeout) => TcpStream::connect_timeout(addr, Duration::from_millis(timeout)),
+                None => TcpStream::connect(addr),
+            }?;
+            let stream = stream.into_rustls(&tls, &uri.authority.host)?;
+            stream.set_nonblocking(true)?;
+            Ok(stream)
+        }),
+        props,
+    )
+    .await
+}
+
+/// Errors that can occur when reconnecting.
+#[derive(Debug, thiserror::Error)]
+#[error("reconnecting failed: {0}")]
+pub struct ReconnectError(#[from] anyhow::Error);
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{give_up, ReconnectPolicy};
+
+    #[test]
+    fn backoff_doubles_up_to_max_backoff() {
+        let backoffs: Vec<_> = ReconnectPolicy::new().backoffs().collect();
+        let expected: Vec<_> = [
+            500, 1_000, 2_000, 4_000, 8_000, 16_000, 30_000, 30_000, 30_000, 30_000,
+        ]
+        .into_iter()
+        .map(Duration::from_millis)
+        .collect();
+        assert_eq!(backoffs, expected);
+    }
+
+    #[test]
+    fn backoff_is_capped_from_the_first_attempt() {
+        let policy = ReconnectPolicy::new()
+            .initial_backoff(Duration::from_secs(10))
+            .max_backoff(Duration::from_secs(1));
+        assert!(policy
+            .backoffs()
+            .all(|backoff| backoff == Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn attempts_are_limited_by_max_retries() {
+        assert_eq!(ReconnectPolicy::new().max_retries(3).backoffs().count(), 3);
+        assert_eq!(ReconnectPolicy::new().max_retries(0).backoffs().count(), 0);
+    }
+
+    #[test]
+    fn give_up_reports_attempts_and_last_error() {
+        let policy = ReconnectPolicy::new().max_retries(2);
+        let err = lapin::Error::InvalidConnectionState(lapin::ConnectionState::Closed);
+        let message = give_up(policy, Some(&err)).to_string();
+        assert!(message.contains("giving up after 2 attempts"), "{message}");
+        assert!(message.contains(&err.to_string()), "{message}");
+        assert!(give_up(policy, None)
+            .to_string()
+            .ends_with("last error: none"));
+    }
+}
@@ -0,0 +1,133 @@
+//! [`ReconnectingPublisher`] implementation, retrying publishes across channel failures.
+
+use std::time::Duration;
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::basic_publish_builder::Published;
+use crate::{message, runtime, Client};
+
+/// Upper bound of the backoff between publish attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Publishes messages and retries them when publishing fails, e.g. because the pooled channel
+/// was closed by the broker. Every attempt gets a fresh channel from the pool, so a publish
+/// goes through as soon as connectivity returns, at most until the timeout is reached.
+///
+/// Publishes keep being retried while the connection is down if the [`Client`] reconnects, see
+/// [`Client::with_reconnect`], as taking a channel reconnects first. Without reconnecting, the
+/// connection never comes back once it is closed, so publishes fail right away with
+/// [`ReconnectingPublishError::ConnectionClosed`].
+///
+/// Retried publishes keep their message id, so consumers can deduplicate them. There are no
+/// ordering guarantees, a message retried after a failure may arrive after messages published
+/// later. Combine it with awaiting the [`Published`] confirm and publishing one message at a
+/// time if ordering matters.
+#[derive(Debug, Clone)]
+pub struct ReconnectingPublisher<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    /// The [`Client`] to publish with.
+    client: Client<S>,
+    /// Give up publishing after this duration.
+    timeout: Duration,
+    /// Initial backoff between publish attempts.
+    backoff: Duration,
+}
+
+impl<S> ReconnectingPublisher<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    /// Create a new `ReconnectingPublisher`.
+    #[must_use]
+    pub fn new(client: Client<S>) -> Self {
+        Self {
+            client,
+            timeout: Duration::from_secs(30),
+            backoff: Duration::from_millis(100),
+        }
+    }
+
+    /// Defaults to 30 seconds. Give up publishing a message after this duration.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Defaults to 100 milliseconds. The backoff between publish attempts, doubled after every
+    /// attempt up to 5 seconds.
+    #[must_use]
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Publish the payload, retrying until it was handed to the broker.
+    ///
+    /// # Errors
+    /// See [`ReconnectingPublishError`].
+    pub async fn publish<P>(
+        &self,
+        exchange_name: &str,
+        routing_key: &str,
+        payload: &P,
+    ) -> Result<Published, ReconnectingPublishError>
+    where
+        P: Serialize,
+    {
+        // Serialize once, also failing right away instead of retrying a payload that never
+        // serializes.
+        let payload = message::Payload(payload)
+            .serialize()
+            .map_err(|err| ReconnectingPublishError::Serialize(err.into()))?;
+        let message_id = (self.client.message_id_generator)();
+
+        let retry = async {
+            let mut backoff = self.backoff;
+            loop {
+                let err = match self
+                    .client
+                    .basic_publish_builder(exchange_name, routing_key)
+                    .message_id(message_id)
+                    .content_type(message::JSON_CONTENT_TYPE)
+                    .publish_bytes(&payload)
+                    .await
+                {
+                    Ok(published) => return Ok(published),
+                    Err(err)
+                        if !self.client.status().connected()
+                            && !self.client.connector.reconnects() =>
+                    {
+                        return Err(ReconnectingPublishError::ConnectionClosed(err.into()));
+                    }
+                    Err(err) => err,
+                };
+                warn!("publishing failed, retrying in {backoff:?}: {err}");
+                self.client.runtime.sleep(backoff).await;
+                backoff = backoff.saturating_mul(2).min(MAX_BACKOFF);
+            }
+        };
+        runtime::timeout(&*self.client.runtime, self.timeout, retry)
+            .await
+            .unwrap_or(Err(ReconnectingPublishError::Timeout(self.timeout)))
+    }
+}
+
+/// Errors that can occur when publishing with a [`ReconnectingPublisher`].
+#[derive(Debug, thiserror::Error)]
+pub enum ReconnectingPublishError {
+    /// Serializing the payload failed.
+    #[error("serializing the payload failed: {0}")]
+    Serialize(anyhow::Error),
+    /// The connection of the client is closed and the client does not reconnect, publishing can
+    /// not succeed anymore.
+    #[error("the connection is closed: {0}")]
+    ConnectionClosed(anyhow::Error),
+    /// Publishing did not succeed within the timeout.
+    #[error("publishing did not succeed within {0:?}")]
+    Timeout(Duration),
+}
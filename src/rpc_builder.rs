@@ -4,20 +4,55 @@
 //! RPC calls might result in other RPC calls, which chains response times and adds
 //! a lot of complexity to a system. Use with care.
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use futures::prelude::*;
-use lapin::options::BasicConsumeOptions;
+use lapin::message::Delivery;
+use lapin::options::{BasicCancelOptions, BasicConsumeOptions, BasicGetOptions};
+use lapin::protocol::{AMQPErrorKind, AMQPSoftError};
 use lapin::types::FieldTable;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use tokio::sync::SemaphorePermit;
+use tracing::warn;
 
-use crate::{message, Client};
+use crate::{message, runtime, Client, Queue};
 
 /// This duration will be added to the queue expiry, which is set
 /// to the RPC timeout. If the RPC timeout is `n`,
 /// the response queue will expire after `n + RESPONSE_QUEUE_EXPIRE_MARGIN`.
-const RESPONSE_QUEUE_EXPIRE_MARGIN: Duration = Duration::from_millis(500);
+pub const RESPONSE_QUEUE_EXPIRE_MARGIN: Duration = Duration::from_millis(500);
+
+/// How an RPC waits for its response, see [`RpcBuilder::poll_response`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseMode {
+    /// Consume the response queue, the response is delivered as soon as it arrives. Setting up
+    /// and canceling the consumer costs a dedicated channel and extra round-trips per call.
+    Consume,
+    /// Poll the response queue with `basic_get`, starting with `interval` and doubling it up to
+    /// `max_interval` between polls. Needs no consumer, but adds up to `max_interval` latency.
+    Poll {
+        /// Interval after the first poll.
+        interval: Duration,
+        /// Upper bound of the interval between polls.
+        max_interval: Duration,
+    },
+}
+
+/// The response of an RPC with its metadata, see [`RpcBuilder::call_with_meta`].
+#[derive(Debug, Clone)]
+pub struct RpcResponse<R> {
+    /// The deserialized response.
+    pub payload: R,
+    /// The correlation id of the response, the message id of the request.
+    pub correlation_id: String,
+    /// The `timestamp` property of the response, if set.
+    pub timestamp: Option<u64>,
+    /// The headers of the response.
+    pub headers: FieldTable,
+    /// Time from publishing the request until the response arrived.
+    pub latency: Duration,
+}
 
 /// Build an RPC request; Send an `amqp message` and wait for a response.
 /// This is discouraged as its contradictory to the 'fire and forget' philosophy of
@@ -28,12 +63,12 @@ const RESPONSE_QUEUE_EXPIRE_MARGIN: Duration = Duration::from_millis(500);
 /// The RPC functionallity works as follows:
 /// * Define a temporary RPC response queue.
 /// * Send a RPC request message, containing the arguments and the name of the
-/// response queue, to the request queue.
+///   response queue, to the request queue.
 /// * The RPC consumer (the other service) will handle the request message, and send its response to the
-/// provided response queue, while also setting the `correlation id` of the response
-/// message to the id of the request message.
+///   provided response queue, while also setting the `correlation id` of the response
+///   message to the id of the request message.
 /// * The call function will wait until the timeout is reached or a message with the corresponding
-/// `correlation id` appears in the queue and return its contents.
+///   `correlation id` appears in the queue and return its contents.
 pub struct RpcBuilder<'a, S>
 where
     S: Clone + Send + Sync + 'static,
@@ -44,6 +79,8 @@ where
     request_queue_name: &'a str,
     /// Response timeout.
     timeout_duration: Duration,
+    /// How to wait for the response.
+    response_mode: ResponseMode,
 }
 
 impl<'a, S> RpcBuilder<'a, S>
@@ -57,16 +94,31 @@ where
             client,
             request_queue_name,
             timeout_duration: Duration::from_secs(10),
+            response_mode: ResponseMode::Consume,
         }
     }
 
-    /// Defaults to 10 seconds.
+    /// Defaults to 10 seconds. The request expires after this duration as well, so the broker
+    /// drops it if it was not consumed in time.
     #[must_use]
     pub fn timeout_duration(mut self, timeout_duration: Duration) -> Self {
         self.timeout_duration = timeout_duration;
         self
     }
 
+    /// Defaults to [`ResponseMode::Consume`]. Poll the response queue with `basic_get` instead of
+    /// consuming it, see [`ResponseMode::Poll`]. Polling avoids setting up a consumer for a single
+    /// response, which pays off for fast responders polled at short intervals, while the upper
+    /// bound keeps slow calls from flooding the broker with polls.
+    #[must_use]
+    pub fn poll_response(mut self, interval: Duration, max_interval: Duration) -> Self {
+        self.response_mode = ResponseMode::Poll {
+            interval,
+            max_interval,
+        };
+        self
+    }
+
     /// Issue the RPC request.
     ///
     /// TODO: Maybe set `R` to [`Result<R, E>`] to
@@ -79,55 +131,62 @@ where
         A: Serialize,
         R: DeserializeOwned,
     {
+        self.call_with_meta(args)
+            .await
+            .map(|response| response.payload)
+    }
+
+    /// Issue the RPC request like [`Self::call`], but return the response with its metadata,
+    /// e.g. to inspect its headers or measure the round-trip time.
+    ///
+    /// # Errors
+    /// see [`CallError`]
+    pub async fn call_with_meta<A, R>(self, args: A) -> Result<RpcResponse<R>, CallError>
+    where
+        A: Serialize,
+        R: DeserializeOwned,
+    {
+        let _permit = self.acquire_permit().await?;
         let queue_uid = uuid::Uuid::new_v4();
+        let queue_expiry = self
+            .timeout_duration
+            .saturating_add(RESPONSE_QUEUE_EXPIRE_MARGIN);
         let response_queue = self
             .client
             .queue_declare_builder(&format!("rpc_response_queue_{queue_uid}"))
             .auto_delete(true)
             .durable(false)
-            .expires(
-                self.timeout_duration
-                    .saturating_add(RESPONSE_QUEUE_EXPIRE_MARGIN),
-            )
+            .expires(queue_expiry)
             .map_err(|_| CallError::TimeoutTooBig)?
             .declare()
             .await
             .map_err(|err| CallError::QueueDeclare(err.into()))?;
 
-        let message_id = message::Id::new_v4();
+        let message_id = (self.client.message_id_generator)();
 
+        let expiration = self.timeout_duration.as_millis().to_string();
+        let start = Instant::now();
         self.client
             .basic_publish_builder("", self.request_queue_name)
             .message_id(message_id)
             .reply_to(response_queue.name().clone())
+            // The broker drops requests not consumed before the caller gives up on them.
+            .props(|props| props.with_expiration(expiration.into()))
             .publish(args)
             .await
             .map_err(|err| CallError::PublishRequest(err.into()))?;
 
-        // TODO: Replace with `basic_get()` or `basic_get_builder()`.
-        let chan = self
-            .client
-            .create_channel()
-            .await
-            .map_err(|err| CallError::Channel(err.into()))?;
-        let mut consumer = chan
-            .basic_consume(
-                &response_queue.name().to_string(),
-                "",
-                BasicConsumeOptions::default(),
-                FieldTable::default(),
-            )
-            .await
-            .map_err(|err| CallError::ConsumeResponse(err.into()))?;
-
-        let delivery_result_opt = tokio::select! {
-            delivery_result_opt = consumer.next() => Ok(delivery_result_opt),
-            () = tokio::time::sleep(self.timeout_duration) => Err(CallError::TimeoutReached)
-        }?;
-
-        let delivery = delivery_result_opt
-            .ok_or(CallError::ConsumerCanceled)?
-            .map_err(|err| CallError::Delivery(err.into()))?;
+        let delivery = match self.response_mode {
+            ResponseMode::Consume => self.consume_response(&response_queue, queue_expiry).await?,
+            ResponseMode::Poll {
+                interval,
+                max_interval,
+            } => {
+                self.poll_response_queue(&response_queue, queue_expiry, interval, max_interval)
+                    .await?
+            }
+        };
+        let latency = start.elapsed();
 
         let correlation_id = delivery.properties.correlation_id().clone().ok_or(
             CallError::CorrelationIdMismatch(
@@ -151,8 +210,152 @@ where
             .await
             .map_err(|err| CallError::PurgeQueue(err.into()))?;
 
-        Ok(payload)
+        Ok(RpcResponse {
+            payload,
+            correlation_id: correlation_id.to_string(),
+            timestamp: *delivery.properties.timestamp(),
+            headers: delivery.properties.headers().clone().unwrap_or_default(),
+            latency,
+        })
+    }
+
+    /// Acquire a permit for an in-flight call if the client limits them, waits at most for the
+    /// timeout.
+    async fn acquire_permit(&self) -> Result<Option<SemaphorePermit<'a>>, CallError> {
+        let Some(permits) = &self.client.rpc_permits else {
+            return Ok(None);
+        };
+        runtime::timeout(
+            &*self.client.runtime,
+            self.timeout_duration,
+            permits.acquire(),
+        )
+        .await
+        .and_then(Result::ok)
+        .map(Some)
+        .ok_or(CallError::TooManyInFlight(self.timeout_duration))
     }
+
+    /// Wait for the response by consuming the response queue.
+    async fn consume_response(
+        &self,
+        response_queue: &Queue,
+        queue_expiry: Duration,
+    ) -> Result<Delivery, CallError> {
+        let chan = self
+            .client
+            .create_channel()
+            .await
+            .map_err(|err| CallError::Channel(err.into()))?;
+        let mut consumer = match chan
+            .basic_consume(
+                &response_queue.name().to_string(),
+                "",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+        {
+            Ok(consumer) => consumer,
+            Err(err) if is_not_found(&err) => {
+                return Err(CallError::ResponseQueueExpired(queue_expiry));
+            }
+            Err(err) => return Err(CallError::ConsumeResponse(err.into())),
+        };
+        let consumer_tag = consumer.tag();
+
+        let delivery_result_opt = tokio::select! {
+            delivery_result_opt = consumer.next() => delivery_result_opt,
+            () = self.client.runtime.sleep(self.timeout_duration) => {
+                self.cancel_response(&chan, consumer_tag.as_str(), response_queue).await;
+                return Err(CallError::TimeoutReached(self.timeout_duration));
+            }
+        };
+
+        match delivery_result_opt {
+            Some(Ok(delivery)) => Ok(delivery),
+            // The broker closes the channel with a 404 if the queue vanished while consuming.
+            Some(Err(err)) if is_not_found(&err) => {
+                Err(CallError::ResponseQueueExpired(queue_expiry))
+            }
+            Some(Err(err)) => Err(CallError::Delivery(err.into())),
+            None => Err(CallError::ConsumerCanceled),
+        }
+    }
+
+    /// Wait for the response by polling the response queue with `basic_get`, backing off from
+    /// `interval` up to `max_interval`.
+    async fn poll_response_queue(
+        &self,
+        response_queue: &Queue,
+        queue_expiry: Duration,
+        interval: Duration,
+        max_interval: Duration,
+    ) -> Result<Delivery, CallError> {
+        let chan = self
+            .client
+            .get_channel()
+            .await
+            .map_err(|err| CallError::Channel(err.into()))?;
+        let poll = async {
+            let mut interval = interval.min(max_interval);
+            loop {
+                match chan
+                    .basic_get(
+                        response_queue.name().as_str(),
+                        BasicGetOptions { no_ack: true },
+                    )
+                    .await
+                {
+                    Ok(Some(message)) => return Ok(message.delivery),
+                    Ok(None) => {}
+                    Err(err) if is_not_found(&err) => {
+                        return Err(CallError::ResponseQueueExpired(queue_expiry));
+                    }
+                    Err(err) => return Err(CallError::ConsumeResponse(err.into())),
+                }
+                self.client.runtime.sleep(interval).await;
+                interval = interval.saturating_mul(2).min(max_interval);
+            }
+        };
+
+        let Some(result) =
+            runtime::timeout(&*self.client.runtime, self.timeout_duration, poll).await
+        else {
+            self.delete_response_queue(response_queue).await;
+            return Err(CallError::TimeoutReached(self.timeout_duration));
+        };
+        result
+    }
+
+    /// Cancel the response consumer and delete the response queue right away, instead of
+    /// waiting for it to expire. Failures are only logged, the queue expires eventually.
+    async fn cancel_response(&self, chan: &lapin::Channel, consumer_tag: &str, queue: &Queue) {
+        if let Err(err) = chan
+            .basic_cancel(consumer_tag, BasicCancelOptions::default())
+            .await
+        {
+            warn!("canceling the RPC response consumer failed: {err}");
+        }
+        self.delete_response_queue(queue).await;
+    }
+
+    /// Delete the response queue right away, instead of waiting for it to expire. Failures are
+    /// only logged, the queue expires eventually.
+    async fn delete_response_queue(&self, queue: &Queue) {
+        if let Err(err) = self.client.queue_delete_builder(queue).delete().await {
+            warn!("deleting the RPC response queue failed: {err}");
+        }
+    }
+}
+
+/// Whether the broker reported that the response queue does not exist (anymore).
+fn is_not_found(err: &lapin::Error) -> bool {
+    matches!(
+        err,
+        lapin::Error::ProtocolError(err)
+            if *err.kind() == AMQPErrorKind::Soft(AMQPSoftError::NOTFOUND)
+    )
 }
 
 /// Errors when issuing an RPC request.
@@ -173,9 +376,17 @@ pub enum CallError {
     /// Serializing the RPC arguments failed.
     #[error("an error ocurred while serializing the RPC arguments: {0}")]
     SerializeArguments(anyhow::Error),
-    /// The response message wasn't sent in time.
-    #[error("timeout reached")]
-    TimeoutReached,
+    /// The response message wasn't sent within the timeout. The response queue was deleted.
+    #[error("no response arrived within the timeout of {0:?}")]
+    TimeoutReached(Duration),
+    /// The response queue expired before the response could be consumed, the expiry is the
+    /// timeout plus [`RESPONSE_QUEUE_EXPIRE_MARGIN`].
+    #[error("the response queue expired after {0:?}")]
+    ResponseQueueExpired(Duration),
+    /// The client limits the number of in-flight calls and no running call finished within the
+    /// timeout, see [`Client::with_max_in_flight_rpcs`].
+    #[error("too many in-flight RPC calls, no call finished within {0:?}")]
+    TooManyInFlight(Duration),
     /// The set timeout is too big.
     #[error("(timeout + {RESPONSE_QUEUE_EXPIRE_MARGIN:?}) milliseconds need to be smaller than u32::MAX")]
     TimeoutTooBig,
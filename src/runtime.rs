@@ -0,0 +1,62 @@
+//! [`Runtime`] definition and the default [`Tokio`] implementation.
+use std::fmt::Debug;
+use std::future::Future;
+use std::time::Duration;
+
+use futures::future::{self, BoxFuture, Either};
+
+use crate::util;
+
+/// Spawns tasks and provides timers for the consumer machinery, publishing and RPCs, so they
+/// are not hardwired to an executor. Register it with [`crate::Client::with_runtime`].
+///
+/// Note that the consumer set of the [`crate::Client`] and the in-flight deliveries of consumers
+/// handling them in parallel are `tokio` join sets, and the stop signal is a `tokio` signal
+/// handler, so a `tokio` runtime is required regardless.
+pub trait Runtime: Debug + Send + Sync + 'static {
+    /// Spawn a detached task.
+    fn spawn(&self, future: BoxFuture<'static, ()>);
+
+    /// Spawn a detached task named `name`, ignoring the name by default.
+    fn spawn_named(&self, name: &str, future: BoxFuture<'static, ()>) {
+        let _ = name;
+        self.spawn(future);
+    }
+
+    /// A future completing after `duration`.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// The default [`Runtime`], backed by `tokio`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tokio;
+
+impl Runtime for Tokio {
+    fn spawn(&self, future: BoxFuture<'static, ()>) {
+        std::mem::drop(tokio::spawn(future));
+    }
+
+    fn spawn_named(&self, name: &str, future: BoxFuture<'static, ()>) {
+        std::mem::drop(util::spawn_named(name, future));
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Await `future`, returns `None` if it did not complete within `duration`.
+pub(super) async fn timeout<F>(
+    runtime: &dyn Runtime,
+    duration: Duration,
+    future: F,
+) -> Option<F::Output>
+where
+    F: Future,
+{
+    let future = std::pin::pin!(future);
+    match future::select(future, runtime.sleep(duration)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(((), _)) => None,
+    }
+}
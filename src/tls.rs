@@ -0,0 +1,264 @@
+//! Connect to the broker over TLS, see [`crate::Client::new_with_tls`].
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rustls_connector::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use rustls_connector::rustls::crypto::{self, CryptoProvider};
+use rustls_connector::rustls::pki_types::pem::PemObject;
+use rustls_connector::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls_connector::rustls::{
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use rustls_connector::rustls_native_certs::load_native_certs;
+use rustls_connector::RustlsConnector;
+
+/// TLS settings of the connection to the broker, see [`crate::Client::new_with_tls`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// PEM file of the CA certificates the server certificate is verified against, `None` to
+    /// verify it against the root certificates of the platform.
+    ca_cert: Option<PathBuf>,
+    /// PEM files of the client certificate chain and its private key, for mutual TLS.
+    client_cert: Option<(PathBuf, PathBuf)>,
+    /// Whether the server certificate is accepted without verifying it.
+    danger_accept_invalid_certs: bool,
+}
+
+impl TlsConfig {
+    /// Create a new `TlsConfig`, verifying the server certificate against the root certificates
+    /// of the platform, e.g. for brokers with certificates issued by a public CA.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Not set by default. Verify the server certificate against the CA certificates in the PEM
+    /// file at `ca_cert` instead of the root certificates of the platform, e.g. for brokers with
+    /// certificates issued by a private CA.
+    #[must_use]
+    pub fn ca_cert(mut self, ca_cert: impl Into<PathBuf>) -> Self {
+        self.ca_cert = Some(ca_cert.into());
+        self
+    }
+
+    /// Not set by default. Authenticate with the certificate chain and private key in the PEM
+    /// files at `cert` and `key`, for brokers requiring mutual TLS.
+    #[must_use]
+    pub fn client_cert(mut self, cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        self.client_cert = Some((cert.into(), key.into()));
+        self
+    }
+
+    /// Defaults to `false`. Accept any server certificate, including expired, self-signed or
+    /// ones issued for another host. The connection is still encrypted, but open to
+    /// man-in-the-middle attacks, so only ever use this for local development.
+    #[must_use]
+    pub fn danger_accept_invalid_certs(mut self, danger_accept_invalid_certs: bool) -> Self {
+        self.danger_accept_invalid_certs = danger_accept_invalid_certs;
+        self
+    }
+
+    /// Build the connector performing the TLS handshake.
+    ///
+    /// # Errors
+    /// If a certificate or key can not be read, no root certificates are found, or rustls
+    /// rejects them.
+    pub(super) fn connector(&self) -> Result<RustlsConnector, TlsError> {
+        let provider = Arc::new(crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+        let builder = if self.danger_accept_invalid_certs {
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(NoVerification(provider)))
+        } else {
+            let mut roots = RootCertStore::empty();
+            if let Some(ca_cert) = &self.ca_cert {
+                for cert in read_certs(ca_cert)? {
+                    roots.add(cert)?;
+                }
+            } else {
+                // Certificates the platform has, but rustls can not parse, are skipped.
+                let certs = load_native_certs().map_err(TlsError::NativeCerts)?;
+                roots.add_parsable_certificates(certs);
+                if roots.is_empty() {
+                    return Err(TlsError::NativeCerts(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "no root certificates found",
+                    )));
+                }
+            }
+            builder.with_root_certificates(roots)
+        };
+        let config = match &self.client_cert {
+            Some((cert, key)) => builder.with_client_auth_cert(
+                read_certs(cert)?,
+                PrivateKeyDer::from_pem_file(key).map_err(|err| TlsError::Read {
+                    path: key.clone(),
+                    source: err.into(),
+                })?,
+            )?,
+            None => builder.with_no_client_auth(),
+        };
+        Ok(config.into())
+    }
+}
+
+/// Read all certificates of the PEM file.
+///
+/// # Errors
+/// If the file can not be read or contains no certificate.
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let read_err = |source: anyhow::Error| TlsError::Read {
+        path: path.to_path_buf(),
+        source,
+    };
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .map_err(|err| read_err(err.into()))?;
+    if certs.is_empty() {
+        return Err(read_err(anyhow::format_err!("no certificate found")));
+    }
+    Ok(certs)
+}
+
+/// Accepts any server certificate, see [`TlsConfig::danger_accept_invalid_certs`]. Handshake
+/// signatures are still checked, so the server has to own the key of the certificate it sent.
+#[derive(Debug)]
+struct NoVerification(
+    /// Verifies the handshake signatures.
+    Arc<CryptoProvider>,
+);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls_connector::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls_connector::rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls_connector::rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Errors that can occur when setting up TLS.
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    /// A certificate or key file can not be read.
+    #[error("reading {path:?} failed: {source}")]
+    Read {
+        /// The file.
+        path: PathBuf,
+        /// Why reading failed.
+        source: anyhow::Error,
+    },
+    /// The root certificates of the platform can not be loaded.
+    #[error("loading the platform root certificates failed: {0}")]
+    NativeCerts(io::Error),
+    /// Rustls rejected the configuration.
+    #[error("invalid TLS configuration: {0}")]
+    Config(#[from] rustls_connector::rustls::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{TlsConfig, TlsError};
+
+    /// Path of a PEM file in the TLS test fixtures.
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/tls")
+            .join(name)
+    }
+
+    #[test]
+    fn connector_with_ca_cert() {
+        let config = TlsConfig::new().ca_cert(fixture("ca.pem"));
+        assert!(config.connector().is_ok());
+    }
+
+    #[test]
+    fn connector_with_client_cert() {
+        let config = TlsConfig::new()
+            .ca_cert(fixture("ca.pem"))
+            .client_cert(fixture("client.pem"), fixture("client-key.pem"));
+        assert!(config.connector().is_ok());
+    }
+
+    #[test]
+    fn connector_with_invalid_certs_accepted() {
+        let config = TlsConfig::new()
+            .ca_cert(fixture("missing.pem"))
+            .danger_accept_invalid_certs(true);
+        assert!(config.connector().is_ok());
+    }
+
+    #[test]
+    fn missing_ca_cert() {
+        let config = TlsConfig::new().ca_cert(fixture("missing.pem"));
+        assert!(matches!(
+            config.connector(),
+            Err(TlsError::Read { path, .. }) if path == fixture("missing.pem")
+        ));
+    }
+
+    #[test]
+    fn ca_cert_without_certificates() {
+        let config = TlsConfig::new().ca_cert(fixture("client-key.pem"));
+        assert!(matches!(
+            config.connector(),
+            Err(TlsError::Read { path, .. }) if path == fixture("client-key.pem")
+        ));
+    }
+
+    #[test]
+    fn client_key_without_key() {
+        let config = TlsConfig::new()
+            .ca_cert(fixture("ca.pem"))
+            .client_cert(fixture("client.pem"), fixture("client.pem"));
+        assert!(matches!(
+            config.connector(),
+            Err(TlsError::Read { path, .. }) if path == fixture("client.pem")
+        ));
+    }
+}
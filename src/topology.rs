@@ -0,0 +1,222 @@
+//! [`Topology`] definition, declare or tear down a set of exchanges, queues and bindings at once.
+
+use lapin::options::{ExchangeDeleteOptions, QueueDeleteOptions};
+use lapin::protocol::{AMQPErrorKind, AMQPSoftError};
+use lapin::types::FieldTable;
+use lapin::ExchangeKind;
+
+use crate::Client;
+
+/// A set of exchanges, queues and bindings that belong together.
+#[derive(Debug, Clone, Default)]
+pub struct Topology {
+    /// Exchanges to declare.
+    pub exchanges: Vec<ExchangeDefinition>,
+    /// Queues to declare.
+    pub queues: Vec<QueueDefinition>,
+    /// Bindings between the queues and exchanges.
+    pub bindings: Vec<BindingDefinition>,
+}
+
+impl Topology {
+    /// Create a new, empty `Topology`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an exchange.
+    #[must_use]
+    pub fn exchange(mut self, name: impl Into<String>, kind: ExchangeKind) -> Self {
+        self.exchanges.push(ExchangeDefinition {
+            name: name.into(),
+            kind,
+        });
+        self
+    }
+
+    /// Add a queue.
+    #[must_use]
+    pub fn queue(mut self, name: impl Into<String>) -> Self {
+        self.queues.push(QueueDefinition { name: name.into() });
+        self
+    }
+
+    /// Add a binding of a queue to an exchange.
+    #[must_use]
+    pub fn binding(
+        mut self,
+        queue_name: impl Into<String>,
+        exchange_name: impl Into<String>,
+        routing_key: impl Into<String>,
+    ) -> Self {
+        self.bindings.push(BindingDefinition {
+            queue_name: queue_name.into(),
+            exchange_name: exchange_name.into(),
+            routing_key: routing_key.into(),
+        });
+        self
+    }
+}
+
+/// An exchange that is part of a [`Topology`].
+#[derive(Debug, Clone)]
+pub struct ExchangeDefinition {
+    /// Name of the exchange.
+    pub name: String,
+    /// Kind of the exchange.
+    pub kind: ExchangeKind,
+}
+
+/// A queue that is part of a [`Topology`].
+#[derive(Debug, Clone)]
+pub struct QueueDefinition {
+    /// Name of the queue.
+    pub name: String,
+}
+
+/// A binding that is part of a [`Topology`].
+#[derive(Debug, Clone)]
+pub struct BindingDefinition {
+    /// Name of the queue.
+    pub queue_name: String,
+    /// Name of the exchange.
+    pub exchange_name: String,
+    /// Routing key.
+    pub routing_key: String,
+}
+
+impl Topology {
+    /// Declares every exchange and queue with the builders defaults and binds the queues
+    /// afterwards.
+    ///
+    /// # Errors
+    /// See [`DeclareTopologyError`].
+    pub async fn declare<S>(&self, client: &Client<S>) -> Result<(), DeclareTopologyError>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        for exchange in &self.exchanges {
+            client
+                .exchange_declare_builder(&exchange.name, exchange.kind.clone())
+                .declare()
+                .await
+                .map_err(|err| DeclareTopologyError::Exchange(exchange.name.clone(), err.into()))?;
+        }
+        for queue in &self.queues {
+            client
+                .queue_declare_builder(&queue.name)
+                .declare()
+                .await
+                .map_err(|err| DeclareTopologyError::Queue(queue.name.clone(), err.into()))?;
+        }
+        for binding in &self.bindings {
+            client
+                .queue_bind_builder(
+                    &binding.queue_name.as_str(),
+                    &binding.exchange_name,
+                    &binding.routing_key,
+                )
+                .bind()
+                .await
+                .map_err(|err| {
+                    DeclareTopologyError::Binding(binding.queue_name.clone(), err.into())
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Tears everything down in reverse dependency order: bindings are removed first, then queues
+    /// and exchanges are deleted. Entities that do not exist (anymore) are ignored.
+    ///
+    /// # Errors
+    /// See [`TeardownError`].
+    pub async fn teardown<S>(&self, client: &Client<S>) -> Result<(), TeardownError>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        for binding in self.bindings.iter().rev() {
+            let chan = client
+                .get_channel()
+                .await
+                .map_err(|err| TeardownError::Channel(err.into()))?;
+            ignore_not_found(
+                chan.queue_unbind(
+                    &binding.queue_name,
+                    &binding.exchange_name,
+                    &binding.routing_key,
+                    FieldTable::default(),
+                )
+                .await,
+            )
+            .map_err(|err| TeardownError::Binding(binding.queue_name.clone(), err.into()))?;
+        }
+        for queue in self.queues.iter().rev() {
+            let chan = client
+                .get_channel()
+                .await
+                .map_err(|err| TeardownError::Channel(err.into()))?;
+            ignore_not_found(
+                chan.queue_delete(&queue.name, QueueDeleteOptions::default())
+                    .await
+                    .map(|_| ()),
+            )
+            .map_err(|err| TeardownError::Queue(queue.name.clone(), err.into()))?;
+        }
+        for exchange in self.exchanges.iter().rev() {
+            let chan = client
+                .get_channel()
+                .await
+                .map_err(|err| TeardownError::Channel(err.into()))?;
+            ignore_not_found(
+                chan.exchange_delete(&exchange.name, ExchangeDeleteOptions::default())
+                    .await,
+            )
+            .map_err(|err| TeardownError::Exchange(exchange.name.clone(), err.into()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Turn `NOT_FOUND` errors into successes.
+fn ignore_not_found(result: Result<(), lapin::Error>) -> Result<(), lapin::Error> {
+    match result {
+        Err(lapin::Error::ProtocolError(err))
+            if *err.kind() == AMQPErrorKind::Soft(AMQPSoftError::NOTFOUND) =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Errors that can occur when declaring a [`Topology`].
+#[derive(Debug, thiserror::Error)]
+pub enum DeclareTopologyError {
+    /// Declaring an exchange failed.
+    #[error("declaring exchange {0} failed: {1}")]
+    Exchange(String, anyhow::Error),
+    /// Declaring a queue failed.
+    #[error("declaring queue {0} failed: {1}")]
+    Queue(String, anyhow::Error),
+    /// Binding a queue failed.
+    #[error("binding queue {0} failed: {1}")]
+    Binding(String, anyhow::Error),
+}
+
+/// Errors that can occur when tearing down a [`Topology`].
+#[derive(Debug, thiserror::Error)]
+pub enum TeardownError {
+    /// Getting a channel failed.
+    #[error("getting a channel failed: {0}")]
+    Channel(anyhow::Error),
+    /// Deleting an exchange failed.
+    #[error("deleting exchange {0} failed: {1}")]
+    Exchange(String, anyhow::Error),
+    /// Deleting a queue failed.
+    #[error("deleting queue {0} failed: {1}")]
+    Queue(String, anyhow::Error),
+    /// Unbinding a queue failed.
+    #[error("unbinding queue {0} failed: {1}")]
+    Binding(String, anyhow::Error),
+}
@@ -2,6 +2,8 @@
 
 pub mod on_error;
 pub mod signal_stop;
+pub mod spawn_named;
 
 pub use self::on_error::OnError;
 pub use self::signal_stop::signal_stop;
+pub use self::spawn_named::{spawn_named, spawn_named_in};
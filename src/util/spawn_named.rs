@@ -0,0 +1,55 @@
+//! [`spawn_named`] and [`spawn_named_in`] utility functions.
+
+use std::future::Future;
+
+use tokio::task::{AbortHandle, JoinHandle, JoinSet};
+
+/// Spawns a task, named `name` for `tokio-console` when built with `--cfg tokio_unstable` and
+/// the `tokio_console` feature. Otherwise the name is ignored.
+///
+/// # Panics
+///
+/// If the task can not be spawned, like [`tokio::spawn`].
+pub fn spawn_named<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(all(tokio_unstable, feature = "tokio_console"))]
+    {
+        tokio::task::Builder::new()
+            .name(name)
+            .spawn(future)
+            .expect("spawning a named task failed")
+    }
+    #[cfg(not(all(tokio_unstable, feature = "tokio_console")))]
+    {
+        let _ = name;
+        tokio::spawn(future)
+    }
+}
+
+/// Spawns a task on the [`JoinSet`], named like in [`spawn_named`].
+///
+/// # Panics
+///
+/// If the task can not be spawned, like [`JoinSet::spawn`].
+pub fn spawn_named_in<T, F>(join_set: &mut JoinSet<T>, name: &str, future: F) -> AbortHandle
+where
+    T: Send + 'static,
+    F: Future<Output = T> + Send + 'static,
+{
+    #[cfg(all(tokio_unstable, feature = "tokio_console"))]
+    {
+        join_set
+            .build_task()
+            .name(name)
+            .spawn(future)
+            .expect("spawning a named task failed")
+    }
+    #[cfg(not(all(tokio_unstable, feature = "tokio_console")))]
+    {
+        let _ = name;
+        join_set.spawn(future)
+    }
+}
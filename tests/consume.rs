@@ -0,0 +1,295 @@
+//! Consuming deliveries, requires the `testcontainers` feature and docker.
+#![cfg(feature = "testcontainers")]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hare::consumer_extract::{Delivery, Json, State};
+use hare::testing::{connect, RabbitMqContainer, TIMEOUT};
+use hare::{dlx, Client};
+use tokio::sync::mpsc;
+
+/// How long to wait for messages that are not expected to arrive.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// Declare a queue collecting the error data published to the DLX.
+async fn declare_error_queue<S>(client: &Client<S>)
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let errors = client
+        .queue_declare_builder("consume-test-errors")
+        .dead_lettering(false)
+        .declare()
+        .await
+        .expect("declaring the error queue failed");
+    client
+        .queue_bind_builder(&errors, dlx::EXCHANGE_NAME, dlx::ROUTING_KEY_ERROR)
+        .bind()
+        .await
+        .expect("binding the error queue failed");
+}
+
+/// The amount of error data messages published to the DLX.
+async fn error_count<S>(client: &Client<S>) -> u32
+where
+    S: Clone + Send + Sync + 'static,
+{
+    client
+        .queue_declare_builder("consume-test-errors")
+        .passive(true)
+        .declare()
+        .await
+        .expect("inspecting the error queue failed")
+        .message_count()
+}
+
+/// Consume the queue, failing every delivery that was not redelivered yet and reporting
+/// whether each delivery was redelivered.
+async fn consume_failing(
+    client: &Client<mpsc::UnboundedSender<bool>>,
+    queue_name: &str,
+    requeue_on_error: bool,
+) {
+    let queue = client
+        .queue_declare_builder(queue_name)
+        .declare()
+        .await
+        .expect("declaring the queue failed");
+    client
+        .basic_consume_builder(&queue, queue_name)
+        .requeue_on_error(requeue_on_error)
+        .consume(
+            |delivery: Delivery, State(sender): State<mpsc::UnboundedSender<bool>>| async move {
+                let _ = sender.send(delivery.redelivered);
+                if delivery.redelivered {
+                    Ok(())
+                } else {
+                    Err(anyhow::format_err!("failing the first delivery"))
+                }
+            },
+        )
+        .await
+        .expect("consuming failed");
+    client
+        .basic_publish_builder("", queue_name)
+        .publish(&"message")
+        .await
+        .expect("publishing failed");
+}
+
+#[tokio::test]
+async fn requeue_on_error_requeues_without_publishing_error_data() {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let (_container, client) = connect("consume-test", sender).await;
+    declare_error_queue(&client).await;
+
+    consume_failing(&client, "consume-test-requeue", true).await;
+
+    let first = tokio::time::timeout(TIMEOUT, receiver.recv()).await;
+    assert_eq!(first.expect("no delivery"), Some(false));
+    let second = tokio::time::timeout(TIMEOUT, receiver.recv()).await;
+    assert_eq!(
+        second.expect("failed delivery was not requeued"),
+        Some(true)
+    );
+    tokio::time::sleep(SETTLE_TIME).await;
+    assert_eq!(error_count(&client).await, 0, "error data was published");
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_dead_lettered_with_error_data() {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let (_container, client) = connect("consume-test", sender).await;
+    declare_error_queue(&client).await;
+
+    consume_failing(&client, "consume-test-dead-letter", false).await;
+
+    let first = tokio::time::timeout(TIMEOUT, receiver.recv()).await;
+    assert_eq!(first.expect("no delivery"), Some(false));
+    tokio::time::sleep(SETTLE_TIME).await;
+    assert!(receiver.try_recv().is_err(), "failed delivery was requeued");
+    assert_eq!(
+        error_count(&client).await,
+        1,
+        "error data was not published"
+    );
+}
+
+/// Tracks how many deliveries are handled at once.
+#[derive(Debug, Default)]
+struct Concurrency {
+    /// Deliveries currently handled.
+    current: AtomicUsize,
+    /// Most deliveries handled at once.
+    max: AtomicUsize,
+    /// Deliveries handled so far.
+    handled: AtomicUsize,
+}
+
+#[tokio::test]
+async fn max_concurrency_is_never_exceeded() {
+    const MAX_CONCURRENCY: usize = 3;
+    const MESSAGES: usize = 20;
+
+    let concurrency = Arc::new(Concurrency::default());
+    let (_container, client) = connect("consume-test", concurrency.clone()).await;
+    let queue = client
+        .queue_declare_builder("consume-test-max-concurrency")
+        .declare()
+        .await
+        .expect("declaring the queue failed");
+    for i in 0..MESSAGES {
+        client
+            .basic_publish_builder("", "consume-test-max-concurrency")
+            .publish(&i)
+            .await
+            .expect("publishing failed");
+    }
+
+    client
+        .basic_consume_builder(&queue, "consume-test-max-concurrency")
+        .in_parallel(true)
+        .max_concurrency(MAX_CONCURRENCY)
+        .consume(|State(concurrency): State<Arc<Concurrency>>| async move {
+            let current = concurrency.current.fetch_add(1, Ordering::SeqCst) + 1;
+            concurrency.max.fetch_max(current, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            concurrency.current.fetch_sub(1, Ordering::SeqCst);
+            concurrency.handled.fetch_add(1, Ordering::SeqCst);
+        })
+        .await
+        .expect("consuming failed");
+
+    tokio::time::timeout(TIMEOUT, async {
+        while concurrency.handled.load(Ordering::SeqCst) < MESSAGES {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("not all deliveries were handled");
+    let max = concurrency.max.load(Ordering::SeqCst);
+    assert!(max <= MAX_CONCURRENCY, "handled {max} deliveries at once");
+    assert!(max > 1, "deliveries were not handled in parallel");
+}
+
+/// Report the message to the test.
+async fn report_message(
+    Json(message): Json<String>,
+    State(sender): State<mpsc::UnboundedSender<String>>,
+) {
+    let _ = sender.send(message);
+}
+
+#[tokio::test]
+async fn no_local_is_ignored_by_rabbitmq() {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let (_container, client) = connect("consume-test", sender).await;
+    let queue = client
+        .queue_declare_builder("consume-test-no-local")
+        .declare()
+        .await
+        .expect("declaring the queue failed");
+    client
+        .basic_consume_builder(&queue, "consume-test-no-local")
+        .no_local(true)
+        .consume(report_message)
+        .await
+        .expect("consuming failed");
+
+    client
+        .basic_publish_builder("", "consume-test-no-local")
+        .publish(&"own message")
+        .await
+        .expect("publishing failed");
+
+    let message = tokio::time::timeout(TIMEOUT, receiver.recv()).await;
+    assert_eq!(
+        message.expect("own message was not delivered").as_deref(),
+        Some("own message")
+    );
+}
+
+/// Wait until the broker has the expected amount of channels open.
+async fn wait_for_channels(container: &RabbitMqContainer, expected: usize) {
+    let result = tokio::time::timeout(TIMEOUT, async {
+        while container.channel_count() != expected {
+            tokio::time::sleep(SETTLE_TIME).await;
+        }
+    })
+    .await;
+    assert!(
+        result.is_ok(),
+        "expected {expected} open channels, found {}",
+        container.channel_count()
+    );
+}
+
+#[tokio::test]
+async fn consumer_channel_is_closed_when_the_consumer_ends() {
+    let (container, client) = connect("consume-test", ()).await;
+    let queue = client
+        .queue_declare_builder("consume-test-close-channel")
+        .declare()
+        .await
+        .expect("declaring the queue failed");
+    let before = container.channel_count();
+
+    client
+        .basic_consume_builder(&queue, "consume-test-close-channel")
+        .recovery(0, Duration::ZERO)
+        .consume(|| async {})
+        .await
+        .expect("consuming failed");
+    wait_for_channels(&container, before + 1).await;
+
+    // Deleting the queue cancels the consumer, which ends without recovery attempts.
+    client
+        .queue_delete_builder(&queue)
+        .delete()
+        .await
+        .expect("deleting the queue failed");
+    wait_for_channels(&container, before).await;
+}
+
+#[tokio::test]
+async fn deliveries_older_than_the_max_age_are_skipped() {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let (_container, client) = connect("consume-test", sender).await;
+    let queue = client
+        .queue_declare_builder("consume-test-max-age")
+        .declare()
+        .await
+        .expect("declaring the queue failed");
+    let hour_ago = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock is before the epoch")
+        .saturating_sub(Duration::from_secs(60 * 60));
+    client
+        .basic_publish_builder("", "consume-test-max-age")
+        .timestamp(hour_ago.as_secs())
+        .publish(&"stale")
+        .await
+        .expect("publishing failed");
+    client
+        .basic_publish_builder("", "consume-test-max-age")
+        .publish(&"fresh")
+        .await
+        .expect("publishing failed");
+
+    client
+        .basic_consume_builder(&queue, "consume-test-max-age")
+        .max_message_age(Duration::from_secs(60))
+        .consume(report_message)
+        .await
+        .expect("consuming failed");
+
+    let message = tokio::time::timeout(TIMEOUT, receiver.recv()).await;
+    assert_eq!(
+        message.expect("fresh message was not delivered").as_deref(),
+        Some("fresh")
+    );
+    tokio::time::sleep(SETTLE_TIME).await;
+    assert!(receiver.try_recv().is_err(), "stale message was handled");
+}
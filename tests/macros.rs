@@ -0,0 +1,87 @@
+//! The `consumer` attribute and the `FromHeaders` derive, requires the `macros` feature.
+#![cfg(feature = "macros")]
+
+#[test]
+fn consumer() {
+    let cases = trybuild::TestCases::new();
+    cases.pass("tests/ui/consumer/pass_*.rs");
+    cases.compile_fail("tests/ui/consumer/fail_*.rs");
+}
+
+mod from_headers {
+    use hare::consumer_extract::{FromHeaders, Headers, HeadersError};
+    use hare::lapin::types::{AMQPValue, FieldTable, LongString};
+
+    #[derive(Debug, PartialEq, hare::FromHeaders)]
+    struct Routing {
+        #[header(rename = "x-tenant-id")]
+        tenant_id: String,
+        attempt: Option<u32>,
+    }
+
+    /// Headers containing the given values.
+    fn headers(values: &[(&str, AMQPValue)]) -> Headers {
+        let mut table = FieldTable::default();
+        for (key, value) in values {
+            table.insert((*key).into(), value.clone());
+        }
+        Headers(table)
+    }
+
+    #[test]
+    fn reads_renamed_and_optional_fields() {
+        let headers = headers(&[
+            (
+                "x-tenant-id",
+                AMQPValue::LongString(LongString::from("acme")),
+            ),
+            ("attempt", AMQPValue::ShortShortUInt(2)),
+        ]);
+        assert_eq!(
+            Routing::from_headers(&headers).ok(),
+            Some(Routing {
+                tenant_id: "acme".to_string(),
+                attempt: Some(2),
+            })
+        );
+    }
+
+    #[test]
+    fn optional_fields_may_be_missing() {
+        let headers = headers(&[(
+            "x-tenant-id",
+            AMQPValue::LongString(LongString::from("acme")),
+        )]);
+        assert_eq!(
+            Routing::from_headers(&headers).ok(),
+            Some(Routing {
+                tenant_id: "acme".to_string(),
+                attempt: None,
+            })
+        );
+    }
+
+    #[test]
+    fn required_fields_are_read_from_the_renamed_header() {
+        let headers = headers(&[("tenant_id", AMQPValue::LongString(LongString::from("acme")))]);
+        assert!(matches!(
+            Routing::from_headers(&headers),
+            Err(HeadersError::Missing(key)) if key == "x-tenant-id"
+        ));
+    }
+
+    #[test]
+    fn out_of_range_fields_are_invalid() {
+        let headers = headers(&[
+            (
+                "x-tenant-id",
+                AMQPValue::LongString(LongString::from("acme")),
+            ),
+            ("attempt", AMQPValue::LongLongInt(-1)),
+        ]);
+        assert!(matches!(
+            Routing::from_headers(&headers),
+            Err(HeadersError::Invalid(key)) if key == "attempt"
+        ));
+    }
+}
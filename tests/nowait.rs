@@ -0,0 +1,74 @@
+//! The `nowait` option of the builders, requires the `testcontainers` feature and docker.
+#![cfg(feature = "testcontainers")]
+
+use hare::lapin::ExchangeKind;
+use hare::testing::{connect, TIMEOUT};
+
+#[tokio::test]
+async fn failing_exchange_declarations_are_only_surfaced_without_nowait() {
+    let (_container, client) = connect("nowait-test", ()).await;
+    client
+        .exchange_declare_builder("nowait-test-exchange", ExchangeKind::Direct)
+        .declare()
+        .await
+        .expect("declaring the exchange failed");
+
+    let result = client
+        .exchange_declare_builder("nowait-test-exchange", ExchangeKind::Topic)
+        .declare()
+        .await;
+    assert!(result.is_err(), "conflicting declaration succeeded");
+
+    client
+        .exchange_declare_builder("nowait-test-exchange", ExchangeKind::Topic)
+        .nowait(true)
+        .declare()
+        .await
+        .expect("nowait declaration waited for the broker");
+}
+
+#[tokio::test]
+async fn failing_bindings_are_only_surfaced_without_nowait() {
+    let (_container, client) = connect("nowait-test", ()).await;
+    let queue = client
+        .queue_declare_builder("nowait-test-bind")
+        .declare()
+        .await
+        .expect("declaring the queue failed");
+
+    let result = client
+        .queue_bind_builder(&queue, "nowait-test-missing", "key")
+        .bind()
+        .await;
+    assert!(result.is_err(), "binding to a missing exchange succeeded");
+
+    client
+        .queue_bind_builder(&queue, "nowait-test-missing", "key")
+        .nowait(true)
+        .bind()
+        .await
+        .expect("nowait binding waited for the broker");
+}
+
+#[tokio::test]
+async fn failing_purges_are_only_surfaced_without_nowait() {
+    let (_container, client) = connect("nowait-test", ()).await;
+
+    let result = client
+        .queue_purge_builder(&"nowait-test-missing")
+        .purge()
+        .await;
+    assert!(result.is_err(), "purging a missing queue succeeded");
+
+    let purged = tokio::time::timeout(
+        TIMEOUT,
+        client
+            .queue_purge_builder(&"nowait-test-missing")
+            .nowait(true)
+            .purge(),
+    )
+    .await
+    .expect("nowait purge hung")
+    .expect("nowait purge waited for the broker");
+    assert_eq!(purged, 0);
+}
@@ -0,0 +1,127 @@
+//! Publishing messages, requires the `testcontainers` feature and docker.
+#![cfg(feature = "testcontainers")]
+
+use hare::basic_publish_builder::PublishMandatoryError;
+use hare::lapin::message::BasicGetMessage;
+use hare::lapin::options::BasicGetOptions;
+use hare::lapin::protocol::AMQPSoftError;
+use hare::lapin::publisher_confirm::Confirmation;
+use hare::lapin::ExchangeKind;
+use hare::testing::connect;
+use hare::Client;
+
+/// Get the next message of the queue.
+async fn get(client: &Client<()>, queue_name: &str) -> BasicGetMessage {
+    client
+        .get_channel()
+        .await
+        .expect("getting a channel failed")
+        .basic_get(queue_name, BasicGetOptions { no_ack: true })
+        .await
+        .expect("getting the message failed")
+        .expect("queue is empty")
+}
+
+#[tokio::test]
+async fn messages_are_persistent_unless_disabled() {
+    let (_container, client) = connect("publish-test", ()).await;
+    client
+        .queue_declare_builder("publish-test-persistent")
+        .declare()
+        .await
+        .expect("declaring the queue failed");
+
+    client
+        .basic_publish_builder("", "publish-test-persistent")
+        .publish(&"persistent")
+        .await
+        .expect("publishing failed");
+    let message = get(&client, "publish-test-persistent").await;
+    assert_eq!(*message.delivery.properties.delivery_mode(), Some(2));
+
+    client
+        .basic_publish_builder("", "publish-test-persistent")
+        .persistent(false)
+        .publish(&"transient")
+        .await
+        .expect("publishing failed");
+    let message = get(&client, "publish-test-persistent").await;
+    assert_eq!(*message.delivery.properties.delivery_mode(), Some(1));
+}
+
+#[tokio::test]
+async fn mandatory_publishes_report_unroutable_messages() {
+    let (_container, client) = connect("publish-test", ()).await;
+    client
+        .exchange_declare_builder("publish-test-topic", ExchangeKind::Topic)
+        .declare()
+        .await
+        .expect("declaring the exchange failed");
+    let queue = client
+        .queue_declare_builder("publish-test-mandatory")
+        .declare()
+        .await
+        .expect("declaring the queue failed");
+    client
+        .queue_bind_builder(&queue, "publish-test-topic", "orders.*")
+        .bind()
+        .await
+        .expect("binding the queue failed");
+
+    client
+        .basic_publish_builder("publish-test-topic", "orders.created")
+        .publish_mandatory(&"routable")
+        .await
+        .expect("routable message was not accepted");
+
+    let result = client
+        .basic_publish_builder("publish-test-topic", "invoices.created")
+        .publish_mandatory(&"unroutable")
+        .await;
+    let Err(PublishMandatoryError::Unroutable(unroutable)) = result else {
+        panic!("message was not returned as unroutable: {result:?}");
+    };
+    assert_eq!(unroutable.reply_code, AMQPSoftError::NOROUTE.get_id());
+    assert_eq!(unroutable.payload, br#""unroutable""#);
+}
+
+#[tokio::test]
+async fn confirm_mode_does_not_leak_into_pooled_channels() {
+    const ROUNDS: u32 = 5;
+
+    let (_container, client) = connect("publish-test", ()).await;
+    let client = client.with_channel_pool_size(1);
+    client
+        .queue_declare_builder("publish-test-confirm-mode")
+        .declare()
+        .await
+        .expect("declaring the queue failed");
+
+    for round in 0..ROUNDS {
+        client
+            .basic_publish_builder("", "publish-test-confirm-mode")
+            .publish_confirmed(&round)
+            .await
+            .expect("confirmed publishing failed");
+        let confirmation = client
+            .basic_publish_builder("", "publish-test-confirm-mode")
+            .publish(&round)
+            .await
+            .expect("publishing failed")
+            .await
+            .expect("awaiting the confirmation failed");
+        assert_eq!(
+            confirmation,
+            Confirmation::NotRequested,
+            "unconfirmed publish ran on a channel in confirm mode"
+        );
+    }
+
+    let queue = client
+        .queue_declare_builder("publish-test-confirm-mode")
+        .passive(true)
+        .declare()
+        .await
+        .expect("inspecting the queue failed");
+    assert_eq!(queue.message_count(), 2 * ROUNDS);
+}
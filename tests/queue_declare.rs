@@ -0,0 +1,82 @@
+//! Declaring queues, requires the `testcontainers` feature and docker.
+#![cfg(feature = "testcontainers")]
+
+use hare::queue_migration::MigrateQueueError;
+use hare::testing::connect;
+
+#[tokio::test]
+async fn declare_or_skip_keeps_a_queue_with_different_arguments() {
+    let (_container, client) = connect("queue-declare-test", ()).await;
+    client
+        .queue_declare_builder("queue-declare-test-different")
+        .dead_lettering(false)
+        .declare()
+        .await
+        .expect("declaring the queue failed");
+
+    let err = client
+        .queue_declare_builder("queue-declare-test-different")
+        .declare()
+        .await
+        .expect_err("redeclaring with different arguments succeeded");
+    assert!(err.is_precondition_failed(), "unexpected error: {err}");
+
+    let queue = client
+        .queue_declare_builder("queue-declare-test-different")
+        .declare_or_skip()
+        .await
+        .expect("declare_or_skip failed for an existing queue");
+    assert_eq!(queue.name().as_str(), "queue-declare-test-different");
+}
+
+#[tokio::test]
+async fn declare_or_skip_fails_for_a_missing_queue() {
+    let (_container, client) = connect("queue-declare-test", ()).await;
+    let err = client
+        .queue_declare_builder("queue-declare-test-missing")
+        .passive(true)
+        .declare_or_skip()
+        .await
+        .expect_err("declare_or_skip succeeded for a missing queue");
+    assert!(!err.is_precondition_failed(), "unexpected error: {err}");
+}
+
+#[tokio::test]
+async fn declare_or_skip_declares_a_new_queue() {
+    let (_container, client) = connect("queue-declare-test", ()).await;
+    client
+        .queue_declare_builder("queue-declare-test-new")
+        .declare_or_skip()
+        .await
+        .expect("declare_or_skip failed for a new queue");
+    client
+        .queue_declare_builder("queue-declare-test-new")
+        .passive(true)
+        .declare()
+        .await
+        .expect("queue was not declared");
+}
+
+#[tokio::test]
+async fn migrate_queue_refuses_queues_with_consumers() {
+    let (_container, client) = connect("queue-declare-test", ()).await;
+    let queue = client
+        .queue_declare_builder("queue-declare-test-migrate")
+        .dead_lettering(false)
+        .declare()
+        .await
+        .expect("declaring the queue failed");
+    client
+        .basic_consume_builder(&queue, "queue-declare-test-migrate")
+        .consume(|| async {})
+        .await
+        .expect("consuming failed");
+
+    let result = client
+        .migrate_queue("queue-declare-test-migrate", |builder| builder, true)
+        .await;
+    assert!(
+        matches!(result, Err(MigrateQueueError::InUse(1))),
+        "queue with a consumer was migrated: {result:?}"
+    );
+}
@@ -0,0 +1,121 @@
+//! Reconnecting after the broker restarted, requires the `testcontainers` feature and docker.
+#![cfg(feature = "testcontainers")]
+
+use std::time::Duration;
+
+use hare::consumer_extract::{Json, State};
+use hare::lapin::ConnectionProperties;
+use hare::reconnect::ReconnectPolicy;
+use hare::testing::{connect, rabbitmq_container, RabbitMqContainer, TIMEOUT};
+use hare::{Client, ReconnectingPublisher};
+use tokio::sync::mpsc;
+
+/// Restart the broker and wait until the client noticed the connection is gone.
+async fn restart_broker<S>(container: &RabbitMqContainer, client: &Client<S>)
+where
+    S: Clone + Send + Sync + 'static,
+{
+    container.stop_broker();
+    tokio::time::timeout(TIMEOUT, async {
+        while client.status().connected() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("connection did not close");
+    container.start_broker();
+    container
+        .wait_until_ready(TIMEOUT)
+        .await
+        .expect("broker did not restart");
+}
+
+#[tokio::test]
+async fn consumer_resumes_after_broker_restart() {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+    let (container, client) = connect("reconnect-test", sender).await;
+    let client =
+        client.with_reconnect(ReconnectPolicy::new().initial_backoff(Duration::from_millis(100)));
+
+    // Durable, so the queue survives the restart.
+    let queue = client
+        .queue_declare_builder("reconnect-test")
+        .durable(true)
+        .declare()
+        .await
+        .expect("declaring the queue failed");
+    client
+        .basic_consume_builder(&queue, "reconnect-test")
+        .consume(
+            |Json(message): Json<String>, State(sender): State<mpsc::UnboundedSender<String>>| async move {
+                sender.send(message)
+            },
+        )
+        .await
+        .expect("consuming failed");
+    let run = tokio::spawn({
+        let client = client.clone();
+        async move { client.run().await }
+    });
+
+    let publisher = ReconnectingPublisher::new(client.clone()).timeout(TIMEOUT);
+    publisher
+        .publish("", "reconnect-test", &"before")
+        .await
+        .expect("publishing failed");
+    let received = tokio::time::timeout(TIMEOUT, receiver.recv()).await;
+    assert_eq!(received.expect("no delivery").as_deref(), Some("before"));
+
+    restart_broker(&container, &client).await;
+
+    publisher
+        .publish("", "reconnect-test", &"after")
+        .await
+        .expect("publishing after the restart failed");
+    let received = tokio::time::timeout(TIMEOUT, receiver.recv()).await;
+    assert_eq!(
+        received.expect("no delivery after the restart").as_deref(),
+        Some("after")
+    );
+    assert!(!run.is_finished(), "run returned after the restart");
+}
+
+#[tokio::test]
+async fn gives_up_after_max_retries() {
+    let container = rabbitmq_container();
+    container
+        .wait_until_ready(TIMEOUT)
+        .await
+        .expect("broker did not start");
+    let client = Client::new_without_dlx(
+        container.uri(),
+        ConnectionProperties::default(),
+        "reconnect-test",
+        (),
+    )
+    .await
+    .expect("connecting failed")
+    .with_reconnect(
+        ReconnectPolicy::new()
+            .max_retries(2)
+            .initial_backoff(Duration::from_millis(10)),
+    );
+
+    container.stop_broker();
+    tokio::time::timeout(TIMEOUT, async {
+        while client.status().connected() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("connection did not close");
+
+    let err = client
+        .create_channel()
+        .await
+        .expect_err("reconnected to a stopped broker");
+    assert!(
+        err.to_string().contains("giving up after 2 attempts"),
+        "unexpected error: {err}"
+    );
+}
@@ -0,0 +1,6 @@
+use hare::consumer_extract::Json;
+
+#[hare::consumer]
+async fn handle(_: Json<String>, _: String) {}
+
+fn main() {}
@@ -0,0 +1,4 @@
+#[hare::consumer]
+async fn handle<T>(_: T) {}
+
+fn main() {}
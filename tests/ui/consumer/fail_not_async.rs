@@ -0,0 +1,4 @@
+#[hare::consumer]
+fn handle() {}
+
+fn main() {}
@@ -0,0 +1,6 @@
+#[hare::consumer]
+async fn handle() -> u32 {
+    0
+}
+
+fn main() {}
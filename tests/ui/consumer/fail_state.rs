@@ -0,0 +1,12 @@
+use hare::consumer_extract::State;
+
+#[derive(Clone)]
+struct AppState;
+
+#[derive(Clone)]
+struct OtherState;
+
+#[hare::consumer(state = AppState)]
+async fn handle(_: State<OtherState>) {}
+
+fn main() {}
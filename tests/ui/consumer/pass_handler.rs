@@ -0,0 +1,33 @@
+use hare::consumer_extract::{Json, MessageId, State};
+use hare::ConsumerHandler;
+
+#[derive(Clone)]
+struct AppState;
+
+/// Handles orders.
+#[hare::consumer(state = AppState)]
+async fn handle_order(
+    Json(order): Json<String>,
+    State(AppState): State<AppState>,
+    MessageId(id): MessageId<String>,
+) -> Result<(), std::io::Error> {
+    let _ = (order, id);
+    Ok(())
+}
+
+#[hare::consumer]
+async fn handle_nothing() {}
+
+fn assert_handler<H, S, T>(_: H)
+where
+    H: ConsumerHandler<S, T>,
+    S: Clone + Send + Sync + 'static,
+{
+}
+
+fn main() {
+    assert_handler::<_, AppState, _>(handle_order);
+    assert_handler::<_, (), _>(handle_nothing);
+    // The function stays callable.
+    let _ = handle_nothing::handle();
+}
@@ -0,0 +1,33 @@
+use hare::consumer_extract::{
+    AppId, ConsumerTag, ContentType, Delivery, Headers, Json, MessageId, Priority, State,
+};
+use hare::{Client, ConsumerHandler};
+
+#[derive(Clone)]
+struct AppState;
+
+// More extractors than plain functions support.
+#[hare::consumer(state = AppState)]
+async fn handle_everything(
+    _: Json<String>,
+    _: State<AppState>,
+    _: MessageId<String>,
+    _: AppId<String>,
+    _: ConsumerTag,
+    _: ContentType,
+    _: Priority,
+    _: Headers,
+    _: Delivery,
+    _: Client<AppState>,
+) {
+}
+
+fn assert_handler<H, T>(_: H)
+where
+    H: ConsumerHandler<AppState, T>,
+{
+}
+
+fn main() {
+    assert_handler(handle_everything);
+}